@@ -0,0 +1,134 @@
+use crate::{BwError, BwTree, KeyType};
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A Bw-Tree that holds at most a fixed number of live keys, and evicts the
+/// oldest ones to make room for new ones.
+///
+/// Every key is stored along with the sequence number of the insert that
+/// last wrote it, and a second Bw-Tree maps the sequence numbers back to the
+/// keys, which orders the keys from the oldest to the newest write. An insert
+/// that takes the tree beyond its capacity evicts keys from the front of that
+/// order. Overwriting a key moves it to the back.
+pub struct BoundedBwTree<K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    pub(crate) tree: BwTree<K, (u64, V)>,
+    /// The keys by the sequence number of their last write.
+    pub(crate) order: BwTree<u64, K>,
+    /// The sequence number of the next insert.
+    next_seq: AtomicU64,
+    /// The maximum number of live keys.
+    capacity: usize,
+}
+
+impl<K, V> BoundedBwTree<K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    /// Creates a tree that holds at most `capacity` live keys.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn with_capacity(capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be positive");
+        BoundedBwTree {
+            tree: BwTree::new(),
+            order: BwTree::new(),
+            next_seq: AtomicU64::new(0),
+            capacity,
+        }
+    }
+
+    /// Returns the maximum number of live keys.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Inserts `value` for `key`, returning the previous value of `key`, or
+    /// `None` if the key was not present.
+    ///
+    /// If the tree then holds more keys than its capacity, the keys written
+    /// longest ago are evicted, but never `key` itself. Concurrent inserts
+    /// may exceed the capacity until the last of them returns.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `BwTree::insert`, in which case the eviction may be
+    /// incomplete.
+    pub fn insert(&self, key: K, value: V) -> Result<Option<V>, BwError> {
+        let seq = self.next_seq.fetch_add(1, Ordering::SeqCst);
+        // The order entry goes in last, so that every order entry an evictor
+        // can find refers to a write that already happened.
+        let old = self.tree.insert(key.clone(), (seq, value))?;
+        if let Some((old_seq, _)) = &old {
+            self.order.delete(*old_seq)?;
+        }
+        self.order.insert(seq, key)?;
+        self.evict(seq)?;
+        Ok(old.map(|(_, value)| value))
+    }
+
+    /// Returns a copy of the value of `key`.
+    pub fn get(&self, key: K) -> Option<V> {
+        self.tree.get(key).map(|entry| entry.1.clone())
+    }
+
+    /// Returns `true` if `key` has a live value.
+    pub fn contains_key(&self, key: K) -> bool {
+        self.tree.contains_key(key)
+    }
+
+    /// Deletes `key`, returning `true` if it had a live value.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `BwTree::delete`.
+    pub fn delete(&self, key: K) -> Result<bool, BwError> {
+        match self.tree.remove_if(key, |_| true)? {
+            Some((seq, _)) => {
+                self.order.delete(seq)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Returns the number of live keys.
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    /// Returns `true` if the tree holds no live keys.
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// Evicts the oldest keys until the tree is within its capacity, or only
+    /// keys written after the insert with sequence number `inserted` are
+    /// left to evict.
+    ///
+    /// A key is only evicted if it still holds the write the order entry was
+    /// taken for. Order entries of keys that were overwritten or deleted in
+    /// the meantime, possibly before the entry went in, are dropped without
+    /// evicting anything.
+    fn evict(&self, inserted: u64) -> Result<(), BwError> {
+        while self.tree.len() > self.capacity {
+            let Some((seq, key)) = self.order.first_key_value() else {
+                break;
+            };
+            if seq >= inserted {
+                break;
+            }
+            // Whichever thread deletes the order entry evicts the key.
+            if self.order.delete(seq)? {
+                self.tree.remove_if(key, |(current, _)| *current == seq)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,122 @@
+use crate::{
+    BwTree, KeyOrder, KeyType, DEFAULT_CONSOLIDATION_THRESHOLD, DEFAULT_MAX_CHAIN_LEN,
+    DEFAULT_SPLIT_THRESHOLD,
+};
+use std::cmp::Ordering;
+use std::fmt::Debug;
+use std::marker::PhantomData;
+
+/// A builder for a Bw-Tree with custom node sizes, as returned by
+/// `BwTree::builder`.
+///
+/// Thresholds that are not set keep their defaults, except that the merge
+/// threshold defaults to a quarter of the leaf split threshold, and the inner
+/// split threshold to the leaf split threshold.
+#[derive(Clone, Debug)]
+pub struct BwTreeBuilder<K, V> {
+    consolidation_threshold: usize,
+    max_chain_len: usize,
+    leaf_split_threshold: usize,
+    leaf_merge_threshold: Option<usize>,
+    inner_split_threshold: Option<usize>,
+    order: KeyOrder<K>,
+    _marker: PhantomData<fn() -> V>,
+}
+
+impl<K, V> BwTreeBuilder<K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    pub(crate) fn new() -> Self {
+        BwTreeBuilder {
+            consolidation_threshold: DEFAULT_CONSOLIDATION_THRESHOLD,
+            max_chain_len: DEFAULT_MAX_CHAIN_LEN,
+            leaf_split_threshold: DEFAULT_SPLIT_THRESHOLD,
+            leaf_merge_threshold: None,
+            inner_split_threshold: None,
+            order: KeyOrder::natural(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Sets the delta chain length above which a node is consolidated.
+    pub fn consolidation_threshold(mut self, threshold: usize) -> Self {
+        self.consolidation_threshold = threshold;
+        self
+    }
+
+    /// Sets the delta chain length at which writers consolidate a node
+    /// inline, like `BwTree::with_max_chain_len`.
+    pub fn max_chain_len(mut self, max: usize) -> Self {
+        self.max_chain_len = max;
+        self
+    }
+
+    /// Sets the number of entries above which a leaf is split.
+    pub fn leaf_split_threshold(mut self, threshold: usize) -> Self {
+        self.leaf_split_threshold = threshold;
+        self
+    }
+
+    /// Sets the number of entries below which a leaf is merged into its left
+    /// sibling.
+    pub fn leaf_merge_threshold(mut self, threshold: usize) -> Self {
+        self.leaf_merge_threshold = Some(threshold);
+        self
+    }
+
+    /// Sets the number of children above which an inner node is split.
+    pub fn inner_split_threshold(mut self, threshold: usize) -> Self {
+        self.inner_split_threshold = Some(threshold);
+        self
+    }
+
+    /// Orders the keys by `compare` rather than by their natural order, like
+    /// `BwTree::with_comparator`.
+    pub fn comparator(
+        mut self,
+        minimum: K,
+        compare: impl Fn(&K, &K) -> Ordering + Send + Sync + 'static,
+    ) -> Self {
+        self.order = KeyOrder::custom(minimum, compare);
+        self
+    }
+
+    /// Creates an empty Bw-Tree with the configured thresholds.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a split threshold or the maximum chain length is zero, or if
+    /// the merge threshold is more than half of the leaf split threshold, as
+    /// either half of a split leaf could then be merged right away.
+    pub fn build(self) -> BwTree<K, V> {
+        let leaf_merge_threshold = self
+            .leaf_merge_threshold
+            .unwrap_or(self.leaf_split_threshold / 4);
+        let inner_split_threshold = self
+            .inner_split_threshold
+            .unwrap_or(self.leaf_split_threshold);
+        assert!(
+            self.leaf_split_threshold > 0,
+            "split threshold must be positive"
+        );
+        assert!(
+            leaf_merge_threshold <= self.leaf_split_threshold / 2,
+            "merge threshold must be at most half of the split threshold"
+        );
+        assert!(
+            inner_split_threshold > 0,
+            "inner split threshold must be positive"
+        );
+        assert!(self.max_chain_len > 0, "max chain length must be positive");
+        BwTree::with_config(
+            self.consolidation_threshold,
+            self.max_chain_len,
+            self.leaf_split_threshold,
+            leaf_merge_threshold,
+            inner_split_threshold,
+            self.order,
+        )
+    }
+}
@@ -0,0 +1,133 @@
+use crate::KeyType;
+use std::cmp::Ordering;
+use std::fmt::{self, Debug};
+use std::sync::Arc;
+
+/// The order of the keys of a Bw-Tree: their natural `Ord`, or the order of
+/// the comparator the tree was created with, as by `BwTree::with_comparator`.
+///
+/// Every comparison of keys in the tree goes through the order, so a
+/// comparator can be any closure, including one that captures state, like a
+/// collation.
+pub(crate) struct KeyOrder<K> {
+    comparator: Option<Arc<Comparator<K>>>,
+}
+
+type CompareFn<K> = Box<dyn Fn(&K, &K) -> Ordering + Send + Sync>;
+
+/// A caller-supplied ordering of keys.
+struct Comparator<K> {
+    compare: CompareFn<K>,
+    /// The smallest key under `compare`, which bounds the left-most leaf like
+    /// `KeyType::minimum`.
+    minimum: K,
+}
+
+impl<K: KeyType> KeyOrder<K> {
+    /// Returns the natural order of the keys.
+    pub(crate) fn natural() -> Self {
+        KeyOrder { comparator: None }
+    }
+
+    /// Returns the order of `compare`, under which no key is smaller than
+    /// `minimum`.
+    pub(crate) fn custom(
+        minimum: K,
+        compare: impl Fn(&K, &K) -> Ordering + Send + Sync + 'static,
+    ) -> Self {
+        KeyOrder {
+            comparator: Some(Arc::new(Comparator {
+                compare: Box::new(compare),
+                minimum,
+            })),
+        }
+    }
+
+    /// Compares two keys.
+    #[inline]
+    pub(crate) fn cmp(&self, a: &K, b: &K) -> Ordering {
+        match &self.comparator {
+            None => a.cmp(b),
+            Some(comparator) => (comparator.compare)(a, b),
+        }
+    }
+
+    /// Returns `true` if `a` is ordered before `b`.
+    #[inline]
+    pub(crate) fn lt(&self, a: &K, b: &K) -> bool {
+        self.cmp(a, b) == Ordering::Less
+    }
+
+    /// Returns `true` if `a` is ordered before `b` or equal to it.
+    #[inline]
+    pub(crate) fn le(&self, a: &K, b: &K) -> bool {
+        self.cmp(a, b) != Ordering::Greater
+    }
+
+    /// Returns `true` if `a` and `b` are the same key.
+    #[inline]
+    pub(crate) fn eq(&self, a: &K, b: &K) -> bool {
+        self.cmp(a, b) == Ordering::Equal
+    }
+
+    /// Returns `true` if `other` is the same order as `self`, which it is
+    /// for two natural orders or two copies of the same comparator.
+    pub(crate) fn same(&self, other: &Self) -> bool {
+        match (&self.comparator, &other.comparator) {
+            (None, None) => true,
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+
+    /// Returns the smallest key, which bounds the left-most leaf.
+    pub(crate) fn minimum(&self) -> K {
+        match &self.comparator {
+            None => K::minimum(),
+            Some(comparator) => comparator.minimum.clone(),
+        }
+    }
+
+    /// Returns the number of keys in `keys`, which are sorted, that are
+    /// ordered before `key`.
+    pub(crate) fn lower_bound(&self, keys: &[K], key: &K) -> usize {
+        keys.partition_point(|k| self.lt(k, key))
+    }
+
+    /// Searches `keys`, which are sorted, for `key`, like
+    /// `slice::binary_search`.
+    pub(crate) fn binary_search(&self, keys: &[K], key: &K) -> Result<usize, usize> {
+        keys.binary_search_by(|k| self.cmp(k, key))
+    }
+
+    /// Returns `true` if `keys` are sorted in ascending order.
+    pub(crate) fn is_sorted<'a>(&self, keys: impl IntoIterator<Item = &'a K>) -> bool
+    where
+        K: 'a,
+    {
+        keys.into_iter().is_sorted_by(|a, b| self.le(a, b))
+    }
+}
+
+impl<K> Clone for KeyOrder<K> {
+    fn clone(&self) -> Self {
+        KeyOrder {
+            comparator: self.comparator.clone(),
+        }
+    }
+}
+
+impl<K: KeyType> Default for KeyOrder<K> {
+    fn default() -> Self {
+        KeyOrder::natural()
+    }
+}
+
+impl<K> Debug for KeyOrder<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.comparator {
+            None => f.write_str("natural"),
+            Some(_) => f.write_str("comparator"),
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use crate::{BwTree, KeyType};
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// A background thread that periodically consolidates the delta chains of a
+/// Bw-Tree, as returned by `BwTree::spawn_consolidator`.
+///
+/// The thread stops once `shutdown` is called or the handle is dropped, or
+/// once the last other reference to the tree is dropped.
+pub struct Consolidator {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Consolidator {
+    /// Signals the thread to stop and waits for it to finish the pass it is
+    /// in the middle of, if any.
+    pub fn shutdown(mut self) {
+        self.stop_and_join();
+    }
+
+    fn stop_and_join(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.stop.store(true, Ordering::SeqCst);
+            thread.thread().unpark();
+            // A panic of the thread has already been reported by the panic
+            // hook, and there is nothing left to clean up after it.
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Consolidator {
+    fn drop(&mut self) {
+        self.stop_and_join();
+    }
+}
+
+impl<K, V> BwTree<K, V>
+where
+    K: KeyType + Debug + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    /// Spawns a thread that compacts the tree every `interval`, like
+    /// `compact`, until the returned handle is shut down.
+    ///
+    /// Writers still consolidate the chains that grow beyond the
+    /// consolidation threshold themselves, so building the tree with a high
+    /// threshold leaves most of the consolidation to the background thread.
+    /// The thread consolidates through the same freeze and compare-and-swap
+    /// as writers do, so writes that race with it are kept. A pass that fails
+    /// because the mapping table is full is retried after the next interval.
+    pub fn spawn_consolidator(tree: &Arc<Self>, interval: Duration) -> Consolidator {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let tree = Arc::downgrade(tree);
+            let stop = stop.clone();
+            std::thread::spawn(move || {
+                while !stop.load(Ordering::SeqCst) {
+                    let Some(tree) = tree.upgrade() else {
+                        return;
+                    };
+                    let _ = tree.compact();
+                    // Drop the reference before sleeping, so that the tree can
+                    // be dropped in the meantime.
+                    drop(tree);
+                    std::thread::park_timeout(interval);
+                }
+            })
+        };
+        Consolidator {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
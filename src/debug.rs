@@ -0,0 +1,162 @@
+use crate::{BwTree, DeltaRecord, KeyType, Node, NodeID, MAX_NODE_ID};
+use std::fmt::Debug;
+
+/// The kind of node a node ID is mapped to, as returned by
+/// `BwTree::debug_node_kind`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeKind {
+    /// An inner node.
+    Inner,
+    /// An inner node with a chain of `len` index-term deltas on top of it.
+    IndexDelta { len: usize },
+    /// A leaf node with a chain of `len` delta records on top of it.
+    Delta { len: usize },
+    /// A leaf node.
+    Leaf,
+}
+
+/// A copy of a delta record, as returned by `BwTree::debug_chain`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeltaRecordView<K, V> {
+    /// Inserts `value` for `key`, which has no live entry below the record.
+    Insert { key: K, value: V },
+    /// Replaces the value of `key`, which has a live entry below the record.
+    Update { key: K, value: V },
+    /// Deletes `key`.
+    Delete { key: K },
+    /// Keys at or above `separator` moved to the right sibling `right`.
+    Split { separator: K, right: NodeID },
+    /// The right sibling was merged into the node, along with its `len`
+    /// entries at or above `separator`.
+    Merge { separator: K, len: usize },
+    /// The node was merged into its left sibling `left`.
+    Remove { left: NodeID },
+    /// The chain is being consolidated.
+    Freeze,
+}
+
+impl<K, V> BwTree<K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    /// Returns the kind of node `id` is mapped to, or `None` if it is not
+    /// mapped.
+    ///
+    /// Node IDs are handed out in ascending order, starting with the root at
+    /// 1, and freed IDs are reused, so a tool that renders the layout of the
+    /// tree can probe the IDs in order. The node may change as soon as this
+    /// returns if the tree is modified concurrently.
+    pub fn debug_node_kind(&self, id: NodeID) -> Option<NodeKind> {
+        if id > MAX_NODE_ID {
+            return None;
+        }
+        let _guard = self.epoch.pin();
+        let node = unsafe { self.mapping_table.load(id).as_ref()? };
+        Some(match node {
+            Node::Inner(_) => NodeKind::Inner,
+            Node::IndexDelta(delta) => NodeKind::IndexDelta {
+                len: delta.terms.len(),
+            },
+            Node::Delta(delta) => NodeKind::Delta {
+                len: delta.records.len(),
+            },
+            Node::Leaf(_) => NodeKind::Leaf,
+        })
+    }
+
+    /// Returns copies of the delta records on top of the leaf-level node
+    /// responsible for `key`, newest first, or nothing if the node is a bare
+    /// leaf.
+    ///
+    /// The records are those of the node a lookup of `key` lands on, so they
+    /// may concern other keys of the node as well.
+    pub fn debug_chain(&self, key: K) -> Vec<DeltaRecordView<K, V>> {
+        let _guard = self.epoch.pin();
+        let (node_id, _) = self.find_leaf_with_upper_bound(&key);
+        let Node::Delta(delta) = self.mapping_table.get(node_id) else {
+            return Vec::new();
+        };
+        delta
+            .records
+            .iter()
+            .map(|record| match record {
+                DeltaRecord::Insert(key, value) => DeltaRecordView::Insert {
+                    key: key.clone(),
+                    value: value.clone(),
+                },
+                DeltaRecord::Update(key, value) => DeltaRecordView::Update {
+                    key: key.clone(),
+                    value: value.clone(),
+                },
+                DeltaRecord::Delete(key) => DeltaRecordView::Delete { key: key.clone() },
+                DeltaRecord::Split(separator, right) => DeltaRecordView::Split {
+                    separator: separator.clone(),
+                    right: *right,
+                },
+                DeltaRecord::Merge(separator, merged) => DeltaRecordView::Merge {
+                    separator: separator.clone(),
+                    len: merged.count,
+                },
+                DeltaRecord::Remove(left) => DeltaRecordView::Remove { left: *left },
+                DeltaRecord::Freeze => DeltaRecordView::Freeze,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DeltaRecordView, NodeKind};
+    use crate::{BwTree, FIRST_LEAF_NODE_ID};
+
+    #[test]
+    fn test_debug_node_kind() {
+        let tree = BwTree::with_consolidation_threshold(4);
+        assert_eq!(tree.debug_node_kind(tree.root_id()), Some(NodeKind::Inner));
+        assert_eq!(
+            tree.debug_node_kind(FIRST_LEAF_NODE_ID),
+            Some(NodeKind::Leaf)
+        );
+        for i in 0..3u64 {
+            tree.insert(i, i).unwrap();
+        }
+        assert_eq!(
+            tree.debug_node_kind(FIRST_LEAF_NODE_ID),
+            Some(NodeKind::Delta { len: 3 })
+        );
+        // The fifth record exceeds the threshold and consolidates the chain.
+        for i in 3..5u64 {
+            tree.insert(i, i).unwrap();
+        }
+        assert_eq!(
+            tree.debug_node_kind(FIRST_LEAF_NODE_ID),
+            Some(NodeKind::Leaf)
+        );
+        assert_eq!(tree.debug_node_kind(FIRST_LEAF_NODE_ID + 1), None);
+        assert_eq!(tree.debug_node_kind(usize::MAX), None);
+    }
+
+    #[test]
+    fn test_debug_chain() {
+        let tree = BwTree::with_consolidation_threshold(16);
+        assert_eq!(tree.debug_chain(1), vec![]);
+        tree.insert(1, 10).unwrap();
+        tree.insert(2, 20).unwrap();
+        assert_eq!(tree.delete(1), Ok(true));
+        tree.insert(1, 11).unwrap();
+        tree.insert(2, 21).unwrap();
+        assert_eq!(
+            tree.debug_chain(1),
+            vec![
+                DeltaRecordView::Update { key: 2, value: 21 },
+                DeltaRecordView::Insert { key: 1, value: 11 },
+                DeltaRecordView::Delete { key: 1 },
+                DeltaRecordView::Insert { key: 2, value: 20 },
+                DeltaRecordView::Insert { key: 1, value: 10 },
+            ]
+        );
+        tree.compact().unwrap();
+        assert_eq!(tree.debug_chain(1), vec![]);
+    }
+}
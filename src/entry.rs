@@ -0,0 +1,275 @@
+use crate::epoch::Guard;
+use crate::{BwError, BwTree, DeltaNode, DeltaRecord, KeyType, Node, NodeID};
+use std::fmt::Debug;
+use std::sync::atomic::Ordering;
+
+/// A view into a single entry of a Bw-Tree, which is either occupied or
+/// vacant.
+///
+/// The entry remembers the node the key was found in, so the write that
+/// completes a read-modify-write is prepended to the delta chain of that node
+/// without descending the tree again. The write only goes through if nothing
+/// was written to the key since it was read, and the key is located again
+/// otherwise. The epoch stays pinned for as long as the entry is alive.
+pub enum Entry<'a, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    Occupied(OccupiedEntry<'a, K, V>),
+    Vacant(VacantEntry<'a, K, V>),
+}
+
+impl<'a, K, V> Entry<'a, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    pub(crate) fn new(tree: &'a BwTree<K, V>, key: K) -> Result<Self, BwError> {
+        let guard = tree.epoch.pin();
+        let (node_id, delta) = tree.delta_for(&key)?;
+        let position = Position {
+            tree,
+            key,
+            node_id,
+            delta,
+            _guard: guard,
+        };
+        Ok(position.into_entry())
+    }
+
+    /// Returns the key of the entry.
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    /// Inserts `default` if the entry is vacant, and returns a copy of the
+    /// value of the key.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `BwTree::insert`.
+    pub fn or_insert(self, default: V) -> Result<V, BwError> {
+        self.or_insert_with(|| default)
+    }
+
+    /// Inserts the result of `default` if the entry is vacant, and returns a
+    /// copy of the value of the key.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `BwTree::insert`.
+    pub fn or_insert_with(self, default: impl FnOnce() -> V) -> Result<V, BwError> {
+        match self {
+            Entry::Occupied(entry) => Ok(entry.get().clone()),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    /// Modifies the value with `f` if the entry is occupied.
+    ///
+    /// `f` is applied to a copy of the value, which then replaces it, so `f`
+    /// is called again on the newer value if another writer changed the key
+    /// in between. If the key is deleted in the meantime, a vacant entry is
+    /// returned.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `BwTree::insert`.
+    pub fn and_modify(self, mut f: impl FnMut(&mut V)) -> Result<Self, BwError> {
+        match self {
+            Entry::Occupied(entry) => entry.replace_with(|value| {
+                let mut value = value.clone();
+                f(&mut value);
+                value
+            }),
+            vacant => Ok(vacant),
+        }
+    }
+}
+
+/// An entry for a key that has a live value.
+pub struct OccupiedEntry<'a, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    position: Position<'a, K, V>,
+    /// The value of the key, as read from the delta chain.
+    value: &'a V,
+}
+
+impl<'a, K, V> OccupiedEntry<'a, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    /// Returns the key of the entry.
+    pub fn key(&self) -> &K {
+        &self.position.key
+    }
+
+    /// Returns the value of the key at the time the entry was located.
+    pub fn get(&self) -> &V {
+        self.value
+    }
+
+    /// Replaces the value of the key with `value`, returning the replaced
+    /// value, or `None` if the key was deleted in the meantime and `value`
+    /// was inserted anew.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `BwTree::insert`.
+    pub fn insert(self, value: V) -> Result<Option<V>, BwError> {
+        let mut position = self.position;
+        loop {
+            let mut old = None;
+            let update = DeltaRecord::Update(position.key.clone(), value.clone());
+            if position.write(update, |current| {
+                old = current.cloned();
+                old.is_some()
+            }) {
+                position.consolidate();
+                return Ok(old);
+            }
+            let insert = DeltaRecord::Insert(position.key.clone(), value.clone());
+            if position.write(insert, |current| current.is_none()) {
+                position.tree.len.fetch_add(1, Ordering::SeqCst);
+                position.consolidate();
+                return Ok(None);
+            }
+            position = position.relocate()?;
+        }
+    }
+
+    /// Replaces the value with `f` applied to it, as long as the value is
+    /// the one `f` was applied to, and returns the entry after the write.
+    fn replace_with(self, mut f: impl FnMut(&V) -> V) -> Result<Entry<'a, K, V>, BwError> {
+        let mut entry = self;
+        loop {
+            let observed = entry.value;
+            let position = entry.position;
+            let update = DeltaRecord::Update(position.key.clone(), f(observed));
+            if position.write(update, |current| {
+                current.is_some_and(|current| std::ptr::eq(current, observed))
+            }) {
+                position.consolidate();
+                return Ok(position.into_entry());
+            }
+            match position.relocate()?.into_entry() {
+                Entry::Occupied(occupied) => entry = occupied,
+                vacant => return Ok(vacant),
+            }
+        }
+    }
+}
+
+/// An entry for a key that has no live value.
+pub struct VacantEntry<'a, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    position: Position<'a, K, V>,
+}
+
+impl<K, V> VacantEntry<'_, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    /// Returns the key of the entry.
+    pub fn key(&self) -> &K {
+        &self.position.key
+    }
+
+    /// Inserts `value` unless another writer inserted the key in the
+    /// meantime, and returns a copy of the value the key ends up with.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `BwTree::insert`.
+    pub fn insert(self, value: V) -> Result<V, BwError> {
+        let mut position = self.position;
+        loop {
+            let insert = DeltaRecord::Insert(position.key.clone(), value.clone());
+            if position.write(insert, |current| current.is_none()) {
+                position.tree.len.fetch_add(1, Ordering::SeqCst);
+                position.consolidate();
+                return Ok(value);
+            }
+            match position.relocate()?.into_entry() {
+                Entry::Occupied(occupied) => return Ok(occupied.get().clone()),
+                Entry::Vacant(vacant) => position = vacant.position,
+            }
+        }
+    }
+}
+
+/// The node a key was located in.
+struct Position<'a, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    tree: &'a BwTree<K, V>,
+    key: K,
+    node_id: NodeID,
+    delta: &'a DeltaNode<K, V>,
+    /// Keeps `delta` and the values read from it alive.
+    _guard: Guard<'a>,
+}
+
+impl<'a, K, V> Position<'a, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    fn into_entry(self) -> Entry<'a, K, V> {
+        let (tree, delta) = (self.tree, self.delta);
+        match delta
+            .get(&tree.order, &self.key, &tree.mapping_table)
+            .expect("dangling node ID")
+        {
+            Some(value) => Entry::Occupied(OccupiedEntry {
+                position: self,
+                value,
+            }),
+            None => Entry::Vacant(VacantEntry { position: self }),
+        }
+    }
+
+    /// Locates the key again, after the node changed under the entry.
+    fn relocate(self) -> Result<Self, BwError> {
+        let (node_id, delta) = self.tree.delta_for(&self.key)?;
+        Ok(Position {
+            node_id,
+            delta,
+            ..self
+        })
+    }
+
+    /// Prepends `record` if the delta chain is still the one mapped to the
+    /// node, and `accept` returns `true` for the value of the key on top of
+    /// which the record would be prepended.
+    fn write(&self, record: DeltaRecord<K, V>, mut accept: impl FnMut(Option<&V>) -> bool) -> bool {
+        let tree = self.tree;
+        self.delta
+            .push_if(&tree.order, record, &tree.mapping_table, |current| {
+                let mapped = matches!(
+                    tree.mapping_table.get(self.node_id),
+                    Node::Delta(delta) if std::ptr::eq(delta, self.delta)
+                );
+                mapped && accept(current)
+            })
+            .is_ok()
+    }
+
+    fn consolidate(&self) {
+        self.tree.maybe_consolidate(self.node_id, self.delta)
+    }
+}
@@ -0,0 +1,140 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Epoch-based memory reclamation.
+///
+/// Readers pin the current epoch before dereferencing shared pointers and
+/// unpin it when they are done. Memory that is unlinked from a shared data
+/// structure is retired in the epoch it was unlinked in, and freed only once
+/// the global epoch has advanced twice past it. The global epoch advances only
+/// when no guard is pinned in the epoch before the current one, so by then no
+/// reader can still hold a reference to the retired memory.
+#[derive(Debug, Default)]
+pub struct Epoch {
+    /// The global epoch.
+    global: AtomicUsize,
+    /// The number of guards pinned in each epoch, indexed by epoch modulo 3.
+    pinned: [AtomicUsize; 3],
+    /// Retired memory, tagged with the epoch it was retired in.
+    garbage: Mutex<Vec<(usize, Deferred)>>,
+}
+
+impl Epoch {
+    pub fn new() -> Self {
+        Epoch::default()
+    }
+
+    /// Pins the current epoch, preventing memory retired from now on from
+    /// being freed until the returned guard is dropped.
+    pub fn pin(&self) -> Guard<'_> {
+        loop {
+            let epoch = self.global.load(Ordering::SeqCst);
+            self.pinned[epoch % 3].fetch_add(1, Ordering::SeqCst);
+            // The global epoch may have advanced between loading it and
+            // announcing the pin, in which case the pin is not valid.
+            if self.global.load(Ordering::SeqCst) == epoch {
+                return Guard {
+                    epoch: self,
+                    pinned_at: epoch,
+                };
+            }
+            self.pinned[epoch % 3].fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Defers freeing `ptr` with `free` until no guard can reference it.
+    ///
+    /// # Safety
+    ///
+    /// The memory must already be unreachable for readers that pin after this
+    /// call, and `free(ptr)` must be safe to call exactly once.
+    pub unsafe fn defer(&self, ptr: *mut u8, free: unsafe fn(*mut u8)) {
+        let epoch = self.current();
+        self.garbage
+            .lock()
+            .unwrap()
+            .push((epoch, Deferred { ptr, free }));
+        self.try_advance();
+        self.collect();
+    }
+
+    /// Returns the current global epoch.
+    pub fn current(&self) -> usize {
+        self.global.load(Ordering::SeqCst)
+    }
+
+    /// Returns `true` if nothing retired in epoch `retired_at` can still be
+    /// referenced by a guard.
+    pub fn is_reclaimable(&self, retired_at: usize) -> bool {
+        retired_at + 2 <= self.current()
+    }
+
+    /// Returns the number of retired allocations that have not been freed yet.
+    #[cfg(test)]
+    pub fn pending(&self) -> usize {
+        self.garbage.lock().unwrap().len()
+    }
+
+    /// Advances the global epoch if no guard is pinned in the previous epoch.
+    fn try_advance(&self) {
+        let epoch = self.global.load(Ordering::SeqCst);
+        if self.pinned[(epoch + 2) % 3].load(Ordering::SeqCst) == 0 {
+            let _ =
+                self.global
+                    .compare_exchange(epoch, epoch + 1, Ordering::SeqCst, Ordering::SeqCst);
+        }
+    }
+
+    /// Frees retired memory that no guard can reference anymore.
+    fn collect(&self) {
+        let reclaimable = {
+            let mut garbage = self.garbage.lock().unwrap();
+            let (reclaimable, pending) = garbage
+                .drain(..)
+                .partition::<Vec<_>, _>(|(retired_at, _)| self.is_reclaimable(*retired_at));
+            *garbage = pending;
+            reclaimable
+        };
+        for (_, deferred) in reclaimable {
+            deferred.free();
+        }
+    }
+}
+
+impl Drop for Epoch {
+    fn drop(&mut self) {
+        for (_, deferred) in self.garbage.get_mut().unwrap().drain(..) {
+            deferred.free();
+        }
+    }
+}
+
+/// A pinned epoch. Memory retired while the guard is alive is not freed until
+/// the guard is dropped.
+#[derive(Debug)]
+pub struct Guard<'a> {
+    epoch: &'a Epoch,
+    pinned_at: usize,
+}
+
+impl Drop for Guard<'_> {
+    fn drop(&mut self) {
+        self.epoch.pinned[self.pinned_at % 3].fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A retired allocation and the function that frees it.
+#[derive(Debug)]
+struct Deferred {
+    ptr: *mut u8,
+    free: unsafe fn(*mut u8),
+}
+
+// The retired memory is no longer shared, so it can be freed by any thread.
+unsafe impl Send for Deferred {}
+
+impl Deferred {
+    fn free(self) {
+        unsafe { (self.free)(self.ptr) }
+    }
+}
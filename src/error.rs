@@ -0,0 +1,110 @@
+use crate::NodeID;
+use std::fmt;
+
+/// The error returned by the write methods of a Bw-Tree, and by the ones that
+/// read it without panicking.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BwError {
+    /// Every node ID the mapping table can address is in use, so the tree
+    /// cannot grow any further. Writes don't fail with it but defer the
+    /// splits they can't make, while `compact` reports it.
+    MappingTableFull,
+    /// The node responsible for the key kept changing under concurrent
+    /// structure modifications, and the write gave up. Retrying the write
+    /// later is safe.
+    Retry,
+    /// A node links to a node ID that is not mapped to a node, which means
+    /// the tree is corrupted.
+    DanglingNode(NodeID),
+}
+
+impl fmt::Display for BwError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BwError::MappingTableFull => write!(f, "mapping table is full"),
+            BwError::Retry => write!(f, "too many retries reaching the node"),
+            BwError::DanglingNode(id) => write!(f, "node {id} is not mapped"),
+        }
+    }
+}
+
+impl std::error::Error for BwError {}
+
+/// The error returned by the methods of a `DurableBwTree`.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum WalError {
+    /// Reading, writing, or flushing the log failed.
+    Io(std::io::Error),
+    /// The tree failed the write, which was taken back out of the log.
+    Tree(BwError),
+    /// The record at the given offset of the log passes its checksum but
+    /// doesn't decode, which means the log was written for other key or value
+    /// types.
+    Corrupt(u64),
+}
+
+impl fmt::Display for WalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalError::Io(err) => write!(f, "write-ahead log I/O failed: {err}"),
+            WalError::Tree(err) => err.fmt(f),
+            WalError::Corrupt(offset) => {
+                write!(
+                    f,
+                    "record at offset {offset} of the write-ahead log does not decode"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for WalError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WalError::Io(err) => Some(err),
+            WalError::Tree(err) => Some(err),
+            WalError::Corrupt(_) => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for WalError {
+    fn from(err: std::io::Error) -> Self {
+        WalError::Io(err)
+    }
+}
+
+impl From<BwError> for WalError {
+    fn from(err: BwError) -> Self {
+        WalError::Tree(err)
+    }
+}
+
+/// The error returned by `BwTree::from_sorted` when the entries are not in
+/// strictly ascending key order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnsortedError {
+    pub(crate) index: usize,
+}
+
+impl UnsortedError {
+    /// Returns the position of the first entry whose key is not greater than
+    /// the key before it.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+impl fmt::Display for UnsortedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "entry {} is not greater than the entry before it",
+            self.index
+        )
+    }
+}
+
+impl std::error::Error for UnsortedError {}
@@ -0,0 +1,29 @@
+use crate::NodeID;
+
+/// A structure modification of a Bw-Tree, as reported to the callback
+/// registered with `BwTree::on_smo`.
+///
+/// Every event is reported once the modification has been completed, by the
+/// thread that performed it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SmoEvent {
+    /// The leaf-level node `node` moved the upper half of its entries to the
+    /// new right sibling `new_sibling`, which its parent now routes to.
+    LeafSplit { node: NodeID, new_sibling: NodeID },
+    /// The leaf-level node `node` was merged into its left sibling `into`,
+    /// and removed from its parent.
+    LeafMerge { node: NodeID, into: NodeID },
+    /// The inner node `node` moved the upper half of its children to the new
+    /// right sibling `new_sibling`.
+    InnerSplit { node: NodeID, new_sibling: NodeID },
+    /// The root `old_root` was split like in `InnerSplit`, and the new root
+    /// `new_root` was installed above it and `new_sibling`.
+    RootSplit {
+        old_root: NodeID,
+        new_sibling: NodeID,
+        new_root: NodeID,
+    },
+    /// The delta chain of the leaf-level node `node`, which held `chain_len`
+    /// delta records, was consolidated into a fresh leaf.
+    Consolidation { node: NodeID, chain_len: usize },
+}
@@ -0,0 +1,120 @@
+use crate::epoch::Epoch;
+use crate::NodeID;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+/// A lock-free stack of node IDs that are free for reuse.
+///
+/// Popped entries are retired through the epoch rather than freed right away,
+/// so an entry cannot be reallocated while a concurrent `pop` still looks at
+/// it, which rules out the ABA problem on `head`.
+///
+/// IDs that concurrent readers may still hold are pushed with `retire`, and
+/// are not handed out again until the epoch they were retired in is over.
+#[derive(Debug)]
+pub struct FreeList {
+    head: AtomicPtr<Entry>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    id: NodeID,
+    /// The epoch the ID was retired in, if it has to wait for readers.
+    retired_at: Option<usize>,
+    next: *mut Entry,
+}
+
+impl FreeList {
+    pub fn new() -> Self {
+        FreeList {
+            head: AtomicPtr::new(std::ptr::null_mut()),
+        }
+    }
+
+    pub fn push(&self, id: NodeID) {
+        self.push_entry(id, None);
+    }
+
+    /// Pushes `id`, which becomes available for reuse only once no guard
+    /// pinned before this call is alive.
+    pub fn retire(&self, id: NodeID, epoch: &Epoch) {
+        self.push_entry(id, Some(epoch.current()));
+    }
+
+    /// Pops the most recently pushed ID, unless it is still retired.
+    pub fn pop(&self, epoch: &Epoch) -> Option<NodeID> {
+        let _guard = epoch.pin();
+        loop {
+            let head = self.head.load(Ordering::Acquire);
+            if head.is_null() {
+                return None;
+            }
+            let (next, retired_at) = unsafe { ((*head).next, (*head).retired_at) };
+            if retired_at.is_some_and(|retired_at| !epoch.is_reclaimable(retired_at)) {
+                return None;
+            }
+            if self
+                .head
+                .compare_exchange_weak(head, next, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+            {
+                let id = unsafe { (*head).id };
+                unsafe { epoch.defer(head as *mut u8, free_entry) };
+                return Some(id);
+            }
+        }
+    }
+
+    /// Returns the number of IDs in the list.
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        let mut len = 0;
+        let mut entry = self.head.load(Ordering::Acquire);
+        while !entry.is_null() {
+            len += 1;
+            entry = unsafe { (*entry).next };
+        }
+        len
+    }
+
+    fn push_entry(&self, id: NodeID, retired_at: Option<usize>) {
+        let entry = Box::into_raw(Box::new(Entry {
+            id,
+            retired_at,
+            next: std::ptr::null_mut(),
+        }));
+        loop {
+            let head = self.head.load(Ordering::Acquire);
+            unsafe {
+                (*entry).next = head;
+            }
+            if self
+                .head
+                .compare_exchange_weak(head, entry, Ordering::Release, Ordering::Relaxed)
+                .is_ok()
+            {
+                break;
+            }
+        }
+    }
+}
+
+impl Default for FreeList {
+    fn default() -> Self {
+        FreeList::new()
+    }
+}
+
+impl Drop for FreeList {
+    fn drop(&mut self) {
+        let mut entry = *self.head.get_mut();
+        while !entry.is_null() {
+            let next = unsafe { (*entry).next };
+            unsafe { free_entry(entry as *mut u8) };
+            entry = next;
+        }
+    }
+}
+
+unsafe fn free_entry(ptr: *mut u8) {
+    drop(Box::from_raw(ptr as *mut Entry));
+}
@@ -0,0 +1,113 @@
+use crate::{epoch, BwTree, KeyType, Node, NodeID};
+use std::fmt::{self, Debug};
+use std::ops::Deref;
+
+/// A reference to a value in a Bw-Tree that keeps the epoch pinned.
+///
+/// The node the value lives in is not freed while the guard is alive, even
+/// if the key is overwritten or the node is consolidated concurrently, so the
+/// reference cannot dangle.
+pub struct Guard<'a, V> {
+    value: &'a V,
+    _pin: epoch::Guard<'a>,
+}
+
+impl<'a, V> Guard<'a, V> {
+    pub(crate) fn new(pin: epoch::Guard<'a>, value: &'a V) -> Self {
+        Guard { value, _pin: pin }
+    }
+}
+
+impl<V> Deref for Guard<'_, V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        self.value
+    }
+}
+
+impl<V: Debug> Debug for Guard<'_, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+/// The leaf-level node responsible for a key, as returned by
+/// `BwTree::descend_to_leaf`, which keeps the epoch pinned.
+///
+/// The node is not freed while the guard is alive, so the references handed
+/// out by `get` cannot dangle, but the node keeps taking writes: lookups
+/// through the guard may observe writes that landed after the descent, and
+/// miss those that landed after the node was consolidated or split. Use
+/// `is_current`, like `BwTree::validate`, to check that the values read
+/// through the guard so far were all current together.
+///
+/// The node covers the key it was descended to and every key above it up to
+/// `upper_bound`, and lookups of other keys may miss them. Its node ID stays
+/// reserved only while the guard is alive: once the node is merged away, the
+/// ID may be reused for an unrelated node after the guard is dropped.
+pub struct LeafGuard<'a, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    tree: &'a BwTree<K, V>,
+    node_id: NodeID,
+    node: &'a Node<K, V>,
+    upper_bound: Option<K>,
+    version: u64,
+    _pin: epoch::Guard<'a>,
+}
+
+impl<'a, K, V> LeafGuard<'a, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    pub(crate) fn new(tree: &'a BwTree<K, V>, key: &K) -> Self {
+        let pin = tree.epoch.pin();
+        let (node_id, upper_bound) = tree.find_leaf_with_upper_bound(key);
+        // The node is not freed while the epoch is pinned.
+        let node = unsafe { &*tree.mapping_table.load(node_id) };
+        LeafGuard {
+            tree,
+            node_id,
+            node,
+            upper_bound,
+            version: node.version(),
+            _pin: pin,
+        }
+    }
+
+    /// Returns the ID of the node.
+    pub fn node_id(&self) -> NodeID {
+        self.node_id
+    }
+
+    /// Returns the lowest key of the next leaf-level node, if there is one.
+    pub fn upper_bound(&self) -> Option<&K> {
+        self.upper_bound.as_ref()
+    }
+
+    /// Returns the value of `key` in the node.
+    ///
+    /// # Panics
+    ///
+    /// Panics like `BwTree::get`.
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.node
+            .get(&self.tree.order, key, &self.tree.mapping_table)
+            .expect("dangling node ID")
+    }
+
+    /// Returns `true` if no write has landed on the node since the descent,
+    /// which means that the values read through the guard so far are all
+    /// still current together.
+    ///
+    /// Consolidating the node keeps it current, but splitting it or merging
+    /// it away does not.
+    pub fn is_current(&self) -> bool {
+        let node = self.tree.mapping_table.get(self.node_id);
+        node.left_link().is_none() && node.version() == self.version
+    }
+}
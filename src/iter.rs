@@ -0,0 +1,595 @@
+use crate::{BwError, BwTree, KeyType, NodeID};
+use std::fmt::Debug;
+use std::ops::Bound;
+
+/// An iterator over a range of entries in a Bw-Tree, in ascending key order.
+///
+/// The iterator visits one leaf-level node at a time. The logical contents of
+/// the node, with its delta chain applied on top of the base leaf, are
+/// buffered, and the next node is found by following the right sibling link
+/// of the current one. If the current node changed in a way that the link
+/// can't be trusted anymore, for example because it was merged away, the next
+/// node is found by descending the tree from its lowest key instead.
+///
+/// The epoch is pinned only while a node is being buffered, and released
+/// before the buffered entries are handed out, so an iterator that is held
+/// open for a long time doesn't keep retired memory from being freed. The
+/// price is that the scan is not a snapshot: every node is read as of the time
+/// the iterator reaches it, so writes that land between two nodes are seen in
+/// the later node only.
+pub struct RangeIter<'a, K, V, T = (K, V)>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    tree: &'a BwTree<K, V>,
+    /// Buffered items of the current node, in descending key order.
+    buffer: Vec<T>,
+    /// The lowest key of the next node to visit.
+    next: Option<K>,
+    /// The ID of the node visited last, whose right sibling is visited next.
+    prev: Option<NodeID>,
+    /// The lower bound of the range.
+    start: Bound<K>,
+    /// The upper bound of the range.
+    end: Bound<K>,
+    /// Copies the item for an entry out of its node, so that only the keys
+    /// are buffered by `Keys`, and only the values by `Values`.
+    project: fn(&K, &V) -> T,
+}
+
+impl<'a, K, V> RangeIter<'a, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    pub(crate) fn new(tree: &'a BwTree<K, V>, start: Bound<K>, end: Bound<K>) -> Self {
+        RangeIter::projected(tree, start, end, |k, v| (k.clone(), v.clone()))
+    }
+}
+
+impl<'a, K, V, T> RangeIter<'a, K, V, T>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    /// Creates an iterator over the items `project` makes of the entries in
+    /// the range.
+    pub(crate) fn projected(
+        tree: &'a BwTree<K, V>,
+        start: Bound<K>,
+        end: Bound<K>,
+        project: fn(&K, &V) -> T,
+    ) -> Self {
+        let next = match &start {
+            Bound::Included(start) | Bound::Excluded(start) => start.clone(),
+            Bound::Unbounded => tree.order.minimum(),
+        };
+        RangeIter {
+            tree,
+            buffer: Vec::new(),
+            next: Some(next),
+            prev: None,
+            start,
+            end,
+            project,
+        }
+    }
+
+    /// Buffers the items of the entries of the leaf-level node that covers
+    /// `start`, copying nothing out of the node but the items themselves.
+    fn fill(&mut self, start: K) {
+        let tree = self.tree;
+        let _guard = tree.epoch.pin();
+        let (node_id, upper_bound) = self
+            .prev
+            .and_then(|prev| tree.find_leaf_after(prev, &start))
+            .unwrap_or_else(|| tree.find_leaf_with_upper_bound(&start));
+        let node = tree.mapping_table.get(node_id);
+        // The next node is most likely the right sibling, if the scan goes on.
+        if let Some((high_key, right)) = node.split() {
+            if self.below_end(high_key) {
+                prefetch(tree, right);
+            }
+        }
+        let mut buffer =
+            node.fold_range(&tree.order, &start, None, Vec::new(), |mut buffer, k, v| {
+                if self.above_start(k) && self.below_end(k) {
+                    buffer.push((self.project)(k, v));
+                }
+                buffer
+            });
+        buffer.reverse();
+        self.buffer = buffer;
+        self.next = upper_bound;
+        self.prev = Some(node_id);
+    }
+
+    /// Returns `true` if `key` is within the lower bound of the range.
+    fn above_start(&self, key: &K) -> bool {
+        let order = &self.tree.order;
+        match &self.start {
+            Bound::Included(start) => order.le(start, key),
+            Bound::Excluded(start) => order.lt(start, key),
+            Bound::Unbounded => true,
+        }
+    }
+
+    /// Returns `true` if `key` is within the upper bound of the range.
+    fn below_end(&self, key: &K) -> bool {
+        let order = &self.tree.order;
+        match &self.end {
+            Bound::Included(end) => order.le(key, end),
+            Bound::Excluded(end) => order.lt(key, end),
+            Bound::Unbounded => true,
+        }
+    }
+}
+
+impl<K, V, T> Iterator for RangeIter<'_, K, V, T>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.buffer.pop() {
+                return Some(entry);
+            }
+            let start = self.next.take()?;
+            if !self.below_end(&start) {
+                return None;
+            }
+            self.fill(start);
+        }
+    }
+}
+
+/// Hints the CPU to start loading the node `node_id` is mapped to into the
+/// cache, ahead of a scan reaching it.
+///
+/// Only the node itself is prefetched, as following its pointers would stall
+/// on the very cache misses the hint is meant to hide.
+#[cfg(all(feature = "prefetch", target_arch = "x86_64"))]
+fn prefetch<K, V>(tree: &BwTree<K, V>, node_id: NodeID)
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+    let ptr: *const crate::Node<K, V> = tree.mapping_table.load(node_id);
+    // A prefetch never faults, even on a dangling or null pointer.
+    unsafe { _mm_prefetch::<_MM_HINT_T0>(ptr.cast()) };
+}
+
+#[cfg(not(all(feature = "prefetch", target_arch = "x86_64")))]
+fn prefetch<K, V>(_tree: &BwTree<K, V>, _node_id: NodeID)
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+}
+
+/// An iterator over a range of entries in a Bw-Tree in pages, which are
+/// vectors of at most a fixed number of entries in ascending key order.
+///
+/// Every page but the last one is full, and no page is empty.
+pub struct RangePages<'a, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    inner: RangeIter<'a, K, V>,
+    page_size: usize,
+}
+
+impl<'a, K, V> RangePages<'a, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    pub(crate) fn new(inner: RangeIter<'a, K, V>, page_size: usize) -> Self {
+        assert!(page_size > 0, "page size must be positive");
+        RangePages { inner, page_size }
+    }
+}
+
+impl<K, V> Iterator for RangePages<'_, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    type Item = Vec<(K, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let page: Vec<_> = self.inner.by_ref().take(self.page_size).collect();
+        (!page.is_empty()).then_some(page)
+    }
+}
+
+/// An iterator over a range of entries in a Bw-Tree, in descending key order.
+///
+/// Like `RangeIter`, the iterator buffers the logical contents of one
+/// leaf-level node at a time. The previous node is found by descending the
+/// tree to the node holding the keys right below the lowest key of the
+/// current one. The epoch is likewise only pinned while a node is buffered.
+pub struct RevRangeIter<'a, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    tree: &'a BwTree<K, V>,
+    /// Buffered entries of the current node, in ascending key order.
+    buffer: Vec<(K, V)>,
+    /// The exclusive upper bound of the keys in the next node to visit, or
+    /// `None` if the right-most node is next.
+    next: Option<K>,
+    /// The inclusive lower bound of the range.
+    start: K,
+    /// Set once the node covering `start` has been visited.
+    done: bool,
+}
+
+impl<'a, K, V> RevRangeIter<'a, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    pub(crate) fn new(tree: &'a BwTree<K, V>, start: K, end: Option<K>) -> Self {
+        RevRangeIter {
+            tree,
+            buffer: Vec::new(),
+            next: end,
+            start,
+            done: false,
+        }
+    }
+
+    /// Buffers the entries of the leaf-level node that covers the keys right
+    /// below `bound`.
+    fn fill(&mut self, bound: Option<K>) {
+        let tree = self.tree;
+        let _guard = tree.epoch.pin();
+        let (node_id, low) = tree.find_leaf_before(bound.as_ref());
+        let node = tree.mapping_table.get(node_id);
+        let order = &tree.order;
+        self.buffer = node
+            .entries(order)
+            .into_iter()
+            .filter(|(k, _)| {
+                order.le(&self.start, k) && bound.as_ref().is_none_or(|bound| order.lt(k, bound))
+            })
+            .collect();
+        if order.le(&low, &self.start) || order.eq(&low, &order.minimum()) {
+            self.done = true;
+        } else {
+            self.next = Some(low);
+        }
+    }
+}
+
+impl<K, V> Iterator for RevRangeIter<'_, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.buffer.pop() {
+                return Some(entry);
+            }
+            if self.done {
+                return None;
+            }
+            let bound = self.next.take();
+            if bound
+                .as_ref()
+                .is_some_and(|bound| self.tree.order.le(bound, &self.start))
+            {
+                self.done = true;
+                return None;
+            }
+            self.fill(bound);
+        }
+    }
+}
+
+/// An iterator over all entries in a Bw-Tree, in descending key order.
+pub struct RevIter<'a, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    inner: RevRangeIter<'a, K, V>,
+}
+
+impl<'a, K, V> RevIter<'a, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    pub(crate) fn new(tree: &'a BwTree<K, V>) -> Self {
+        RevIter {
+            inner: RevRangeIter::new(tree, tree.order.minimum(), None),
+        }
+    }
+}
+
+impl<K, V> Iterator for RevIter<'_, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+/// An iterator over all entries in a Bw-Tree, in ascending key order.
+pub struct Iter<'a, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    inner: RangeIter<'a, K, V>,
+}
+
+impl<'a, K, V> Iter<'a, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    pub(crate) fn new(tree: &'a BwTree<K, V>) -> Self {
+        Iter {
+            inner: RangeIter::new(tree, Bound::Unbounded, Bound::Unbounded),
+        }
+    }
+}
+
+impl<K, V> Iterator for Iter<'_, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+/// An iterator over the keys of a Bw-Tree, in ascending order.
+///
+/// Like `Iter`, the iterator buffers one leaf-level node at a time, but only
+/// copies the keys of its entries out of it.
+pub struct Keys<'a, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    inner: RangeIter<'a, K, V, K>,
+}
+
+impl<'a, K, V> Keys<'a, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    pub(crate) fn new(tree: &'a BwTree<K, V>) -> Self {
+        Keys {
+            inner: RangeIter::projected(tree, Bound::Unbounded, Bound::Unbounded, |k, _| k.clone()),
+        }
+    }
+}
+
+impl<K, V> Iterator for Keys<'_, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    type Item = K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+/// An iterator over the values of a Bw-Tree, in ascending order of their
+/// keys.
+///
+/// Like `Keys`, the iterator only copies the values of the entries out of
+/// the leaf-level node it buffers.
+pub struct Values<'a, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    inner: RangeIter<'a, K, V, V>,
+}
+
+impl<'a, K, V> Values<'a, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    pub(crate) fn new(tree: &'a BwTree<K, V>) -> Self {
+        Values {
+            inner: RangeIter::projected(tree, Bound::Unbounded, Bound::Unbounded, |_, v| v.clone()),
+        }
+    }
+}
+
+impl<K, V> Iterator for Values<'_, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    type Item = V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+/// An iterator that deletes the entries of a Bw-Tree as it yields them, in
+/// ascending key order.
+///
+/// Every entry is deleted through its own `remove_if` right before it is
+/// yielded, so the value yielded for a key is the one that was deleted, and
+/// keys deleted concurrently are skipped. Two drains of the same tree thus
+/// never yield the same entry. Entries that the iterator hasn't reached yet
+/// stay in the tree, including those written concurrently below the current
+/// key, so dropping the iterator early leaves the rest of the tree intact.
+pub struct Drain<'a, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    tree: &'a BwTree<K, V>,
+    inner: RangeIter<'a, K, V>,
+}
+
+impl<'a, K, V> Drain<'a, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    pub(crate) fn new(tree: &'a BwTree<K, V>) -> Self {
+        Drain {
+            tree,
+            inner: RangeIter::new(tree, Bound::Unbounded, Bound::Unbounded),
+        }
+    }
+}
+
+impl<K, V> Iterator for Drain<'_, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    /// The deleted entry, or the error that the delete of the next entry
+    /// failed with, in which case that entry stays in the tree.
+    type Item = Result<(K, V), BwError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (key, _) = self.inner.next()?;
+            match self.tree.remove_if(key.clone(), |_| true) {
+                Ok(Some(value)) => return Some(Ok((key, value))),
+                Ok(None) => continue,
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+/// A cursor over the entries of a Bw-Tree that is moved in either direction
+/// by hand, as returned by `BwTree::cursor`.
+///
+/// The cursor holds its position as a key rather than as a reference into a
+/// node, and every step looks up the entry next to that key afresh. The tree
+/// may thus change freely between steps: a step goes to the entry next to the
+/// position as the tree is at the time of the step, even if the entry at the
+/// position itself has been deleted in the meantime.
+pub struct Cursor<'a, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    tree: &'a BwTree<K, V>,
+    position: Position<K>,
+}
+
+/// The position of a `Cursor`.
+enum Position<K> {
+    /// Before the first entry.
+    Start,
+    /// Between the entries with keys below the key and those at or above it.
+    Before(K),
+    /// At the entry with the key, the one the cursor returned last.
+    At(K),
+    /// After the last entry.
+    End,
+}
+
+impl<'a, K, V> Cursor<'a, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    pub(crate) fn new(tree: &'a BwTree<K, V>) -> Self {
+        Cursor {
+            tree,
+            position: Position::Start,
+        }
+    }
+
+    /// Moves the cursor right in front of `key`, so that `next` returns the
+    /// entry with the lowest key at or above `key`, and `prev` the one with
+    /// the highest key below it.
+    pub fn seek(&mut self, key: K) {
+        self.position = Position::Before(key);
+    }
+
+    /// Moves the cursor to the entry before its position and returns it, or
+    /// returns `None` and moves the cursor before the first entry if there is
+    /// no such entry.
+    pub fn prev(&mut self) -> Option<(K, V)> {
+        let end = match &self.position {
+            Position::Start => return None,
+            Position::Before(key) | Position::At(key) => Some(key.clone()),
+            Position::End => None,
+        };
+        let entry = RevRangeIter::new(self.tree, self.tree.order.minimum(), end).next();
+        self.position = match &entry {
+            Some((key, _)) => Position::At(key.clone()),
+            None => Position::Start,
+        };
+        entry
+    }
+}
+
+impl<K, V> Iterator for Cursor<'_, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    type Item = (K, V);
+
+    /// Moves the cursor to the entry after its position and returns it, or
+    /// returns `None` and moves the cursor after the last entry if there is
+    /// no such entry.
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = match &self.position {
+            Position::Start => Bound::Unbounded,
+            Position::Before(key) => Bound::Included(key.clone()),
+            Position::At(key) => Bound::Excluded(key.clone()),
+            Position::End => return None,
+        };
+        let entry = RangeIter::new(self.tree, start, Bound::Unbounded).next();
+        self.position = match &entry {
+            Some((key, _)) => Position::At(key.clone()),
+            None => Position::End,
+        };
+        entry
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a BwTree<K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    type Item = (K, V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
@@ -1,17 +1,145 @@
+mod bounded;
+mod builder;
+mod comparator;
+mod consolidator;
+#[cfg(feature = "debug-internals")]
+mod debug;
+mod entry;
+mod epoch;
+mod error;
+mod event;
+mod free_list;
+mod guard;
+mod iter;
 mod linked_list;
+#[cfg(all(test, loom))]
+mod loom_test;
+mod multimap;
+#[cfg(feature = "numa")]
+mod numa;
+#[cfg(feature = "serde")]
+mod serde;
+mod snapshot;
+mod stats;
+mod sync;
+mod wal;
 
+pub use crate::bounded::BoundedBwTree;
+pub use crate::builder::BwTreeBuilder;
+use crate::comparator::KeyOrder;
+pub use crate::consolidator::Consolidator;
+#[cfg(feature = "debug-internals")]
+pub use crate::debug::{DeltaRecordView, NodeKind};
+pub use crate::entry::{Entry, OccupiedEntry, VacantEntry};
+use crate::epoch::Epoch;
+pub use crate::error::{BwError, UnsortedError, WalError};
+pub use crate::event::SmoEvent;
+use crate::free_list::FreeList;
+pub use crate::guard::{Guard, LeafGuard};
+pub use crate::iter::{
+    Cursor, Drain, Iter, Keys, RangeIter, RangePages, RevIter, RevRangeIter, Values,
+};
 use crate::linked_list::LinkedList;
+pub use crate::multimap::BwTreeMultimap;
+pub use crate::snapshot::{Snapshot, SnapshotIter};
+pub use crate::stats::Stats;
+use crate::sync::AtomicPtr;
+pub use crate::wal::{DurableBwTree, WalCodec};
+use std::borrow::Cow;
 use std::fmt::Debug;
-use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::ops::Bound;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
 
+const ROOT_NODE_ID: usize = 1;
 const FIRST_LEAF_NODE_ID: usize = 2;
 
-pub trait KeyType: Ord {
-    const MINIMUM: Self;
+/// The number of times a write retries reaching its node before giving up.
+const MAX_RETRIES: usize = 10_000;
+
+/// The default maximum length of a delta chain before it is consolidated.
+const DEFAULT_CONSOLIDATION_THRESHOLD: usize = 8;
+
+/// The default number of delta records at which a writer consolidates a
+/// delta chain before and after writing to it.
+const DEFAULT_MAX_CHAIN_LEN: usize = 64;
+
+/// The default maximum number of entries in a leaf before it is split.
+const DEFAULT_SPLIT_THRESHOLD: usize = 64;
+
+/// The default number of entries below which a leaf is merged into its left
+/// sibling.
+const DEFAULT_MERGE_THRESHOLD: usize = DEFAULT_SPLIT_THRESHOLD / 4;
+
+/// The default number of entries printed by the `Debug` impl of a Bw-Tree.
+const DEFAULT_DEBUG_ENTRIES: usize = 32;
+
+/// The largest size of values whose delta records are packed into blocks
+/// rather than boxed one at a time.
+const MAX_PACKED_VALUE_SIZE: usize = 16;
+
+pub trait KeyType: Ord + Clone {
+    /// Returns the smallest possible key, which bounds the left-most leaf.
+    fn minimum() -> Self;
+}
+
+macro_rules! impl_key_type_for_integer {
+    ($($t:ty),*) => {
+        $(
+            impl KeyType for $t {
+                fn minimum() -> Self {
+                    <$t>::MIN
+                }
+            }
+        )*
+    };
+}
+
+impl_key_type_for_integer!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+impl KeyType for char {
+    fn minimum() -> Self {
+        '\0'
+    }
+}
+
+impl KeyType for String {
+    fn minimum() -> Self {
+        String::new()
+    }
+}
+
+impl KeyType for &str {
+    fn minimum() -> Self {
+        ""
+    }
+}
+
+impl<T: Ord + Clone> KeyType for Vec<T> {
+    fn minimum() -> Self {
+        Vec::new()
+    }
 }
 
-impl KeyType for u64 {
-    const MINIMUM: Self = u64::MIN;
+/// Composite keys, ordered by their first component and then by the second.
+impl<A: KeyType, B: KeyType> KeyType for (A, B) {
+    fn minimum() -> Self {
+        (A::minimum(), B::minimum())
+    }
+}
+
+/// Composite keys of three components, ordered like pairs.
+impl<A: KeyType, B: KeyType, C: KeyType> KeyType for (A, B, C) {
+    fn minimum() -> Self {
+        (A::minimum(), B::minimum(), C::minimum())
+    }
+}
+
+/// Fixed-size byte strings, like UUIDs and digests, ordered lexicographically.
+impl<const N: usize> KeyType for [u8; N] {
+    fn minimum() -> Self {
+        [0; N]
+    }
 }
 
 pub type NodeID = usize;
@@ -21,251 +149,7287 @@ pub type NodeID = usize;
 pub struct BwTree<K, V>
 where
     K: KeyType + Debug,
-    V: Clone + Debug,
+    V: Clone,
 {
-    root_id: usize,
+    /// The ID of the root node, which changes when the root is split.
+    root_id: AtomicUsize,
     /// Mapping table from logical node IDs to physical pointers.
     mapping_table: MappingTable<K, V>,
     /// The next unused node ID in the `mapping_table`.
     next_unused_node_id: AtomicUsize,
+    /// Node IDs of retired nodes that can be reused.
+    free_node_ids: FreeList,
+    /// The stripes of unused node IDs the NUMA nodes allocate from.
+    #[cfg(feature = "numa")]
+    node_id_stripes: numa::NodeIdStripes,
+    /// The delta chain length above which a node is consolidated.
+    consolidation_threshold: usize,
+    /// The delta chain length at which writers consolidate a node inline,
+    /// whatever the consolidation threshold.
+    max_chain_len: usize,
+    /// The number of entries above which a consolidated leaf is split.
+    split_threshold: usize,
+    /// The number of entries below which a consolidated leaf is merged.
+    merge_threshold: usize,
+    /// The number of children above which an inner node is split.
+    inner_split_threshold: usize,
+    /// Set while a thread performs a split or a merge, which serializes
+    /// structure modifications.
+    smo_in_progress: AtomicBool,
+    /// The order of the keys, which every node is searched and sorted by.
+    order: KeyOrder<K>,
+    /// Reclamation of nodes swapped out of the `mapping_table`.
+    epoch: Epoch,
+    /// The number of live entries in the tree.
+    len: AtomicUsize,
+    /// The callback that structure modifications are reported to.
+    smo_callback: Option<SmoCallback>,
+    /// The number of descents from the root to a leaf-level node.
+    #[cfg(test)]
+    descents: AtomicUsize,
 }
 
+type SmoCallback = Box<dyn Fn(SmoEvent) + Send + Sync>;
+
+/// A Bw-Tree that stores its values behind an `Arc`, for values that are not
+/// `Clone` or are expensive to clone.
+///
+/// Reads and consolidations only clone the `Arc`, never the value itself.
+pub type ArcBwTree<K, V> = BwTree<K, Arc<V>>;
+
+/// A Bw-Tree that stores byte buffers behind an `Arc`, so that reading a
+/// value only bumps its reference count.
+pub type BytesBwTree<K> = BwTree<K, Arc<[u8]>>;
+
 impl<K, V> BwTree<K, V>
 where
     K: KeyType + Debug,
-    V: Clone + Debug,
+    V: Clone,
 {
     pub fn new() -> Self {
+        Self::builder().build()
+    }
+
+    /// Returns a builder for a Bw-Tree with custom thresholds.
+    pub fn builder() -> BwTreeBuilder<K, V> {
+        BwTreeBuilder::new()
+    }
+
+    /// Registers `callback` to be called with every structure modification
+    /// and consolidation of a leaf-level node, replacing any callback
+    /// registered before.
+    ///
+    /// The callback runs on the thread that performed the modification,
+    /// possibly while other structure modifications wait for it, so it
+    /// should return quickly.
+    pub fn on_smo(&mut self, callback: impl Fn(SmoEvent) + Send + Sync + 'static) {
+        self.smo_callback = Some(Box::new(callback));
+    }
+
+    /// Reports `event` to the callback registered with `on_smo`, if any.
+    fn emit(&self, event: SmoEvent) {
+        if let Some(callback) = &self.smo_callback {
+            callback(event);
+        }
+    }
+
+    /// Creates a Bw-Tree that consolidates a node once its delta chain grows
+    /// longer than `threshold` records.
+    ///
+    /// Delta chains are still capped at a default length, as with
+    /// `with_max_chain_len`, if `threshold` is higher than that.
+    pub fn with_consolidation_threshold(threshold: usize) -> Self {
+        Self::with_thresholds(threshold, DEFAULT_SPLIT_THRESHOLD, DEFAULT_MERGE_THRESHOLD)
+    }
+
+    /// Creates a Bw-Tree whose delta chains never hold more than `max`
+    /// records once the writes to them have returned.
+    ///
+    /// A writer that finds a chain of `max` records, or leaves one behind,
+    /// consolidates it before going on, even if the consolidation threshold
+    /// is higher. Writers that append to the same chain at the same time may
+    /// still overshoot `max` by one record each until the next of them
+    /// consolidates it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` is zero.
+    pub fn with_max_chain_len(max: usize) -> Self {
+        Self::builder().max_chain_len(max).build()
+    }
+
+    /// Creates a Bw-Tree that splits a leaf once it holds more than
+    /// `threshold` entries, and merges it once it holds fewer than a quarter
+    /// of that.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `threshold` is zero.
+    pub fn with_split_threshold(threshold: usize) -> Self {
+        Self::with_thresholds(DEFAULT_CONSOLIDATION_THRESHOLD, threshold, threshold / 4)
+    }
+
+    /// Creates a Bw-Tree that splits a leaf once it holds more than
+    /// `split_threshold` entries, and merges it into its left sibling once it
+    /// holds fewer than `merge_threshold` entries.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `split_threshold` is zero, or if `merge_threshold` is more
+    /// than half of it, as either half of a split leaf could then be merged
+    /// right away.
+    pub fn with_split_and_merge_thresholds(split_threshold: usize, merge_threshold: usize) -> Self {
+        Self::with_thresholds(
+            DEFAULT_CONSOLIDATION_THRESHOLD,
+            split_threshold,
+            merge_threshold,
+        )
+    }
+
+    /// Creates a Bw-Tree whose keys are ordered by `compare` rather than by
+    /// their natural order.
+    ///
+    /// `compare` must be a total order, and no key may be ordered before
+    /// `minimum`, which bounds the left-most leaf like `KeyType::minimum`
+    /// does under the natural order. Lookups, iteration, and the bounds of
+    /// range scans all follow `compare`, which may capture state.
+    pub fn with_comparator(
+        minimum: K,
+        compare: impl Fn(&K, &K) -> std::cmp::Ordering + Send + Sync + 'static,
+    ) -> Self {
+        Self::builder().comparator(minimum, compare).build()
+    }
+
+    fn with_thresholds(
+        consolidation_threshold: usize,
+        split_threshold: usize,
+        merge_threshold: usize,
+    ) -> Self {
+        Self::builder()
+            .consolidation_threshold(consolidation_threshold)
+            .leaf_split_threshold(split_threshold)
+            .leaf_merge_threshold(merge_threshold)
+            .build()
+    }
+
+    /// Creates a Bw-Tree with thresholds that `BwTreeBuilder::build` has
+    /// validated.
+    fn with_config(
+        consolidation_threshold: usize,
+        max_chain_len: usize,
+        split_threshold: usize,
+        merge_threshold: usize,
+        inner_split_threshold: usize,
+        order: KeyOrder<K>,
+    ) -> Self {
         let ret: BwTree<K, V> = BwTree {
-            root_id: 1,
+            root_id: AtomicUsize::new(ROOT_NODE_ID),
             mapping_table: MappingTable::new(),
-            next_unused_node_id: AtomicUsize::new(1),
+            next_unused_node_id: AtomicUsize::new(FIRST_LEAF_NODE_ID + 1),
+            free_node_ids: FreeList::new(),
+            #[cfg(feature = "numa")]
+            node_id_stripes: numa::NodeIdStripes::new(),
+            consolidation_threshold,
+            max_chain_len,
+            split_threshold,
+            merge_threshold,
+            inner_split_threshold,
+            smo_in_progress: AtomicBool::new(false),
+            order,
+            epoch: Epoch::new(),
+            len: AtomicUsize::new(0),
+            smo_callback: None,
+            #[cfg(test)]
+            descents: AtomicUsize::new(0),
         };
 
         // The Bw-Tree initially consists of two nodes: an empty leaf node
-        // and an inner node that contains the empty leaf node
-        let root_id = ret.get_next_node_id();
-        assert_eq!(1, root_id);
-        let first_leaf_id = ret.get_next_node_id();
-        assert_eq!(FIRST_LEAF_NODE_ID, first_leaf_id);
-
+        // and an inner node that contains the empty leaf node. Their IDs are
+        // reserved up front rather than allocated, so that they don't depend
+        // on how node IDs are handed out.
         let left_most_leaf = Node::Leaf(LeafNode::new());
         let mut root = InnerNode::new();
-        root.insert(KeyType::MINIMUM, first_leaf_id);
+        root.insert(&ret.order, ret.order.minimum(), FIRST_LEAF_NODE_ID);
 
-        ret.mapping_table.insert(root_id, Node::Inner(root));
-        ret.mapping_table.insert(first_leaf_id, left_most_leaf);
+        ret.mapping_table.insert(ROOT_NODE_ID, Node::Inner(root));
+        ret.mapping_table.insert(FIRST_LEAF_NODE_ID, left_most_leaf);
 
         ret
     }
 
-    fn get_next_node_id(&self) -> NodeID {
-        // TODO: recycle deleted node IDs
-        self.next_unused_node_id.fetch_add(1, Ordering::SeqCst)
+    /// Builds a Bw-Tree from `entries` sorted by strictly ascending key.
+    ///
+    /// The entries are packed into full leaves directly, which is much faster
+    /// than inserting them one at a time. Returns an error if a key is not
+    /// greater than the key before it.
+    pub fn from_sorted(entries: impl IntoIterator<Item = (K, V)>) -> Result<Self, UnsortedError> {
+        Self::load_sorted(Self::new(), entries)
     }
 
-    pub fn insert(&self, key: K, value: V) -> bool {
-        let root = self.mapping_table.get(self.root_id);
-        match root {
-            Node::Inner(_) => {
-                let delta = DeltaNode::new();
-                delta.insert(key, value);
-                let delta = Node::Delta(delta);
-                self.mapping_table.insert(self.root_id, delta);
+    /// Packs `entries` into the leaves of `tree`, a freshly created tree,
+    /// like `from_sorted`.
+    fn load_sorted(
+        tree: Self,
+        entries: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<Self, UnsortedError> {
+        let mut leaves = Vec::new();
+        let mut leaf = LeafNode::new();
+        for (index, (key, value)) in entries.into_iter().enumerate() {
+            let prev = leaf.keys.last().or_else(|| {
+                leaves
+                    .last()
+                    .and_then(|leaf: &LeafNode<K, V>| leaf.keys.last())
+            });
+            if prev.is_some_and(|prev| tree.order.le(&key, prev)) {
+                return Err(UnsortedError { index });
             }
-            Node::Delta(delta) => {
-                delta.insert(key, value);
+            if leaf.count == tree.split_threshold {
+                leaves.push(std::mem::replace(&mut leaf, LeafNode::new()));
+            }
+            leaf.keys.push(key);
+            leaf.values.push(value);
+            leaf.count += 1;
+        }
+        leaves.push(leaf);
+
+        let mut ids = vec![FIRST_LEAF_NODE_ID];
+        ids.extend((1..leaves.len()).map(|_| {
+            tree.get_next_node_id()
+                .expect("a fresh tree has node IDs left for every leaf")
+        }));
+        let mut root = InnerNode::new();
+        root.insert(&tree.order, tree.order.minimum(), FIRST_LEAF_NODE_ID);
+        for i in 1..leaves.len() {
+            let separator = leaves[i].keys[0].clone();
+            root.insert(&tree.order, separator.clone(), ids[i]);
+            leaves[i - 1].high_key = Some(separator);
+            leaves[i - 1].right_sibling = Some(ids[i]);
+        }
+        let len = leaves.iter().map(|leaf| leaf.count).sum();
+
+        // Nobody else can observe the tree yet, so the replaced nodes can be
+        // freed right away.
+        let replace = |id, node| {
+            let old = tree.mapping_table.insert(id, node);
+            if let Some(old) = old.filter(|old| !old.is_null()) {
+                unsafe { free_node::<K, V>(old as *mut u8) };
             }
-            Node::Leaf(_) => todo!(),
+        };
+        for (id, leaf) in ids.into_iter().zip(leaves) {
+            replace(id, Node::Leaf(leaf));
         }
-        true
+        replace(tree.root_id(), Node::Inner(root));
+        tree.len.store(len, Ordering::SeqCst);
+        Ok(tree)
     }
 
-    pub fn get(&self, key: K) -> Option<&V> {
-        let root = self.mapping_table.get(self.root_id);
-        root.get(&key)
+    /// Allocates a node ID, reusing a freed one if possible.
+    ///
+    /// Returns `BwError::MappingTableFull` if every ID the mapping table can
+    /// address is in use.
+    fn get_next_node_id(&self) -> Result<NodeID, BwError> {
+        if let Some(id) = self.free_node_ids.pop(&self.epoch) {
+            // A merged node stays mapped until no reader can observe it, so
+            // free it on reuse of its ID.
+            let stale = self.mapping_table.remove(id);
+            if !stale.is_null() {
+                unsafe { free_node::<K, V>(stale as *mut u8) };
+            }
+            return Ok(id);
+        }
+        #[cfg(feature = "numa")]
+        return self
+            .node_id_stripes
+            .next(numa::current_node(), &self.next_unused_node_id);
+        #[cfg(not(feature = "numa"))]
+        self.next_unused_node_id
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |id| {
+                (id <= MAX_NODE_ID).then_some(id + 1)
+            })
+            .map_err(|_| BwError::MappingTableFull)
     }
-}
 
-const MAPPING_TABLE_SIZE: usize = 1 << 20;
+    /// Uses up every node ID the mapping table can address, so that the next
+    /// allocation of a node ID fails with `BwError::MappingTableFull`.
+    #[cfg(test)]
+    fn exhaust_node_ids(&self) {
+        self.next_unused_node_id
+            .store(MAX_NODE_ID + 1, Ordering::SeqCst);
+        #[cfg(feature = "numa")]
+        self.node_id_stripes.exhaust();
+    }
 
-/// Mapping from logical node IDs to physical pointers.
-#[derive(Default)]
-pub struct MappingTable<K: Ord, V: Clone> {
-    /// The mapping table.
-    entries: Vec<AtomicPtr<Node<K, V>>>,
-}
+    /// Makes `id` available for reuse by `get_next_node_id`.
+    ///
+    /// The node must be fully retired: it is no longer mapped in the mapping
+    /// table and no inner node points to it.
+    fn free_node_id(&self, id: NodeID) {
+        self.free_node_ids.push(id);
+    }
 
-impl<K: Ord, V: Clone> MappingTable<K, V> {
-    pub fn new() -> Self {
-        let mut entries = Vec::default();
-        entries.resize_with(MAPPING_TABLE_SIZE, AtomicPtr::default);
-        MappingTable { entries }
+    /// Makes `id` available for reuse once no reader can observe it anymore.
+    ///
+    /// No inner node may point to the node anymore. It stays mapped until
+    /// the ID is reused, so that readers that looked it up before it was
+    /// unlinked can still follow it.
+    fn retire_node_id(&self, id: NodeID) {
+        self.free_node_ids.retire(id, &self.epoch);
     }
 
-    fn get(&self, id: usize) -> &Node<K, V> {
-        assert!(id < MAPPING_TABLE_SIZE);
-        let entry = self.entries[id].load(Ordering::Acquire);
-        assert!(!entry.is_null());
-        unsafe { &*entry }
+    /// Creates an empty Bw-Tree with the thresholds and the key order of
+    /// `self`.
+    fn empty_copy(&self) -> Self {
+        Self::with_config(
+            self.consolidation_threshold,
+            self.max_chain_len,
+            self.split_threshold,
+            self.merge_threshold,
+            self.inner_split_threshold,
+            self.order.clone(),
+        )
+    }
+
+    /// Acquires the right to perform a structure modification, or returns
+    /// `None` if another thread is performing one.
+    fn try_begin_smo(&self) -> Option<SmoGuard<'_>> {
+        self.smo_in_progress
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| SmoGuard {
+                flag: &self.smo_in_progress,
+            })
     }
 
-    fn insert(&self, id: usize, node: Node<K, V>) -> bool {
-        assert!(id < MAPPING_TABLE_SIZE);
-        let entry = &self.entries[id];
-        let old = entry.load(Ordering::Acquire);
-        let new = Box::leak(Box::new(node));
-        match entry.compare_exchange(old, new, Ordering::SeqCst, Ordering::SeqCst) {
-            Ok(_old) => {
-                // TODO: deferred delete of '_old'
-                true
+    /// Inserts `value` for `key`, returning the previous value of `key`, or
+    /// `None` if the key was not present.
+    ///
+    /// If the node the entry is written to has to be split but no node ID is
+    /// left, the split is deferred: the node is left oversized, and a later
+    /// write splits it once IDs have been freed.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BwError::Retry` if the node responsible for `key` could not
+    /// be reached, in which case the tree is left unchanged.
+    pub fn insert(&self, key: K, value: V) -> Result<Option<V>, BwError> {
+        let _guard = self.epoch.pin();
+        let mut entry = (key, value);
+        let (node_id, delta, old) = loop {
+            let (node_id, delta) = self.delta_for(&entry.0)?;
+            match delta.upsert(&self.order, entry.0, entry.1, &self.mapping_table) {
+                Ok(old) => break (node_id, delta, old),
+                Err(frozen) => entry = frozen,
             }
-            Err(new) => {
-                std::mem::drop(Box::from(new));
-                false
+        };
+        if old.is_none() {
+            self.len.fetch_add(1, Ordering::SeqCst);
+        }
+        self.maybe_consolidate(node_id, delta);
+        Ok(old)
+    }
+
+    /// Inserts `value` for `key` like `insert`, but hands both back instead
+    /// of retrying if another thread gets in the way.
+    ///
+    /// The record is prepended with a single compare-and-swap, which fails if
+    /// another record was prepended to the delta chain since the write read
+    /// it. The write also gives up if the delta chain is being consolidated,
+    /// or is at the maximum chain length, or if the node is a bare leaf and
+    /// another thread chains a delta node on top of it first. The caller can
+    /// then retry, or drop the write.
+    pub fn try_insert(&self, key: K, value: V) -> Result<Option<V>, (K, V)> {
+        let _guard = self.epoch.pin();
+        let Some((node_id, delta)) = self.try_delta_for(&key) else {
+            return Err((key, value));
+        };
+        // `accept` is called once per compare-and-swap, so a second call means
+        // that the first one failed.
+        let mut attempts = 0;
+        let mut old = None;
+        let update = DeltaRecord::Update(key, value);
+        let record = match delta.push_if(&self.order, update, &self.mapping_table, |current| {
+            attempts += 1;
+            old = current.cloned();
+            attempts == 1 && old.is_some()
+        }) {
+            Ok(()) => None,
+            Err(Refused::Rejected(DeltaRecord::Update(key, value))) if attempts == 1 => {
+                Some(DeltaRecord::Insert(key, value))
+            }
+            Err(Refused::Rejected(record) | Refused::Frozen(record)) => {
+                return Err(record.into_entry())
+            }
+        };
+        // The update was rejected without a compare-and-swap, because the key
+        // had no live entry, so the single attempt goes to the insert.
+        if let Some(insert) = record {
+            let mut attempts = 0;
+            if let Err(Refused::Rejected(record) | Refused::Frozen(record)) =
+                delta.push_if(&self.order, insert, &self.mapping_table, |current| {
+                    attempts += 1;
+                    attempts == 1 && current.is_none()
+                })
+            {
+                return Err(record.into_entry());
             }
+            self.len.fetch_add(1, Ordering::SeqCst);
         }
+        self.maybe_consolidate(node_id, delta);
+        Ok(old)
     }
-}
 
-#[derive(Debug)]
-enum Node<K, V> {
-    Inner(InnerNode<K>),
-    Delta(DeltaNode<K, V>),
-    Leaf(LeafNode<K, V>),
-}
+    /// Prepends `record`, an insert, update, or delete record, to the delta
+    /// chain of the leaf-level node responsible for its key, if `accept`
+    /// returns `true` for the value of the key on top of which it would be
+    /// prepended. Returns the node the record was prepended to, or `None` if
+    /// `accept` rejected it.
+    ///
+    /// If the delta chain is frozen by a consolidation before the record gets
+    /// in, the tree is descended again to the node that replaced it.
+    fn push_if(
+        &self,
+        record: DeltaRecord<K, V>,
+        mut accept: impl FnMut(Option<&V>) -> bool,
+    ) -> Result<Option<LeafDelta<'_, K, V>>, BwError> {
+        let mut record = record;
+        loop {
+            let key = record
+                .key()
+                .expect("not an insert, update, or delete record");
+            let (node_id, delta) = self.delta_for(key)?;
+            match delta.push_if(&self.order, record, &self.mapping_table, &mut accept) {
+                Ok(()) => return Ok(Some((node_id, delta))),
+                Err(Refused::Rejected(_)) => return Ok(None),
+                Err(Refused::Frozen(frozen)) => record = frozen,
+            }
+        }
+    }
 
-impl<K, V> Node<K, V>
-where
-    K: KeyType,
-{
-    fn get(&self, key: &K) -> Option<&V> {
-        match self {
-            Node::Inner(_) => todo!(),
-            Node::Delta(node) => node.get(key),
-            Node::Leaf(node) => node.get(key),
+    /// Inserts all of `items`, sorting them by key first unless they are
+    /// already sorted. If a key occurs more than once, its last value wins.
+    ///
+    /// Consecutive keys that fall into the same leaf-level node are written
+    /// to its delta chain after a single descent, so a sorted batch costs
+    /// about one descent per touched node rather than one per key. The tree
+    /// is descended again whenever the delta chain has to be consolidated.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `insert`, in which case only a prefix of the sorted batch
+    /// has been inserted.
+    pub fn batch_insert(&self, items: &[(K, V)]) -> Result<(), BwError> {
+        if self.order.is_sorted(items.iter().map(|(key, _)| key)) {
+            return self.write_sorted(items.iter().cloned(), true);
         }
+        // The positions of the entries are sorted rather than copies of the
+        // entries, so that every entry is only cloned as it is written.
+        let mut positions: Vec<usize> = (0..items.len()).collect();
+        positions.sort_by(|&a, &b| self.order.cmp(&items[a].0, &items[b].0));
+        self.write_sorted(positions.into_iter().map(|i| items[i].clone()), true)
     }
-}
 
-#[derive(Debug)]
-struct InnerNode<K> {
-    /// The key ranges stored in the children.
-    keys: Vec<K>,
-    /// Pointers to the children.
-    children: Vec<NodeID>,
-}
+    /// Inserts all of `items` like `batch_insert`, but takes them by value,
+    /// so that they are sorted in place and moved into the tree rather than
+    /// cloned.
+    ///
+    /// The sort is stable, so if a key occurs more than once, its value that
+    /// comes last in `items` wins.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `batch_insert`.
+    pub fn insert_batch_unsorted(&self, mut items: Vec<(K, V)>) -> Result<(), BwError> {
+        if !self.order.is_sorted(items.iter().map(|(key, _)| key)) {
+            items.sort_by(|a, b| self.order.cmp(&a.0, &b.0));
+        }
+        self.write_sorted(items, true)
+    }
 
-impl<K> InnerNode<K> {
-    fn new() -> Self {
-        InnerNode {
-            keys: Vec::new(),
-            children: Vec::new(),
+    /// Inserts every entry of `items` like `insert_batch_unsorted`, after
+    /// collecting them.
+    ///
+    /// This is an inherent method rather than an implementation of `Extend`,
+    /// so that a failed insert can be returned instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `batch_insert`.
+    pub fn extend(&self, items: impl IntoIterator<Item = (K, V)>) -> Result<(), BwError> {
+        self.insert_batch_unsorted(items.into_iter().collect())
+    }
+
+    /// Inserts all live entries of `other`, keeping the existing value of
+    /// keys that are present in both trees.
+    ///
+    /// The entries are written like with `batch_insert`, directly from an
+    /// iterator over `other`, so writes to `other` that race with the merge
+    /// may or may not be included. If `other` orders its keys differently,
+    /// its entries are collected and sorted by the order of `self` first.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `insert`, in which case only the entries of `other` up to
+    /// some key have been merged.
+    pub fn merge_from(&self, other: &BwTree<K, V>) -> Result<(), BwError> {
+        self.write_merged(other, false)
+    }
+
+    /// Inserts all live entries of `other`, replacing the existing value of
+    /// keys that are present in both trees.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `merge_from`.
+    pub fn merge_from_overwriting(&self, other: &BwTree<K, V>) -> Result<(), BwError> {
+        self.write_merged(other, true)
+    }
+
+    /// Writes the live entries of `other` like `write_sorted`, sorting them
+    /// first unless `other` orders its keys like `self`.
+    fn write_merged(&self, other: &BwTree<K, V>, overwrite: bool) -> Result<(), BwError> {
+        if self.order.same(&other.order) {
+            return self.write_sorted(other, overwrite);
+        }
+        let mut items: Vec<_> = other.iter().collect();
+        items.sort_by(|a, b| self.order.cmp(&a.0, &b.0));
+        self.write_sorted(items, overwrite)
+    }
+
+    /// Writes `items`, which must be sorted by key, replacing the values of
+    /// keys that are present if `overwrite` is set and keeping them
+    /// otherwise.
+    fn write_sorted(
+        &self,
+        items: impl IntoIterator<Item = (K, V)>,
+        overwrite: bool,
+    ) -> Result<(), BwError> {
+        let mut items = items.into_iter().peekable();
+        // An entry handed back by a delta chain that got frozen by a
+        // consolidation, which is written first after descending again.
+        let mut frozen = None;
+        while let Some((key, _)) = frozen.as_ref().or_else(|| items.peek()) {
+            // Pin per node rather than for the whole batch, so that the delta
+            // chains consolidated along the way can be reclaimed.
+            let _guard = self.epoch.pin();
+            let (node_id, delta) = self.delta_for(key)?;
+            let high_key = self
+                .mapping_table
+                .get(node_id)
+                .split()
+                .map(|(high_key, _)| high_key.clone());
+            let in_node = |(key, _): &(K, V)| {
+                high_key
+                    .as_ref()
+                    .is_none_or(|high| self.order.lt(key, high))
+            };
+            while let Some((key, value)) = frozen.take().or_else(|| items.next_if(in_node)) {
+                let written = if overwrite {
+                    delta
+                        .upsert(&self.order, key, value, &self.mapping_table)
+                        .map(|old| old.is_none())
+                } else {
+                    delta
+                        .insert_if_absent(&self.order, key, value, &self.mapping_table)
+                        .map(|inserted| inserted.is_ok())
+                };
+                match written {
+                    Ok(true) => {
+                        self.len.fetch_add(1, Ordering::SeqCst);
+                    }
+                    Ok(false) => {}
+                    Err(entry) => {
+                        frozen = Some(entry);
+                        break;
+                    }
+                }
+                if self.needs_consolidation(delta) {
+                    break;
+                }
+            }
+            self.maybe_consolidate(node_id, delta);
         }
+        Ok(())
     }
 
-    fn insert(&mut self, key: K, node_id: NodeID) {
-        self.keys.push(key);
-        self.children.push(node_id);
+    /// Deletes `key` from the tree, returning `true` if the key was present.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `insert`.
+    pub fn delete(&self, key: K) -> Result<bool, BwError> {
+        let _guard = self.epoch.pin();
+        let record = DeltaRecord::Delete(key);
+        let Some((node_id, delta)) = self.push_if(record, |current| current.is_some())? else {
+            return Ok(false);
+        };
+        self.len.fetch_sub(1, Ordering::SeqCst);
+        self.maybe_consolidate(node_id, delta);
+        Ok(true)
     }
-}
 
-#[derive(Debug)]
-struct DeltaNode<K, V> {
-    records: LinkedList<DeltaRecord<K, V>>,
-}
+    /// Deletes `key` only if `pred` accepts its current value, returning the
+    /// deleted value.
+    ///
+    /// Returns `None` if the key is absent or `pred` rejects its value. The
+    /// check is atomic with respect to other writers to the same node like in
+    /// `insert_if_absent`, so `pred` is called again on the newer value if
+    /// another record was prepended in the meantime.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `insert`.
+    pub fn remove_if(
+        &self,
+        key: K,
+        mut pred: impl FnMut(&V) -> bool,
+    ) -> Result<Option<V>, BwError> {
+        let _guard = self.epoch.pin();
+        let mut removed = None;
+        let Some((node_id, delta)) = self.push_if(DeltaRecord::Delete(key), |current| {
+            removed = current.filter(|value| pred(value)).cloned();
+            removed.is_some()
+        })?
+        else {
+            return Ok(None);
+        };
+        self.len.fetch_sub(1, Ordering::SeqCst);
+        self.maybe_consolidate(node_id, delta);
+        Ok(removed)
+    }
 
-impl<K, V> DeltaNode<K, V>
-where
-    K: KeyType,
-{
-    fn new() -> Self {
-        DeltaNode {
-            records: LinkedList::new(),
+    /// Inserts `value` for `key` only if the key has no live entry, returning
+    /// `Ok(Err(existing))` with a copy of the existing value otherwise.
+    ///
+    /// The check is atomic with respect to other writers to the same node:
+    /// the insert record is only prepended on top of a delta chain the key
+    /// was found absent in, and the check is repeated if another record was
+    /// prepended in the meantime.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `insert`.
+    pub fn insert_if_absent(&self, key: K, value: V) -> Result<Result<(), V>, BwError> {
+        let _guard = self.epoch.pin();
+        let mut entry = (key, value);
+        let (node_id, delta) = loop {
+            let (node_id, delta) = self.delta_for(&entry.0)?;
+            match delta.insert_if_absent(&self.order, entry.0, entry.1, &self.mapping_table) {
+                Ok(Ok(())) => break (node_id, delta),
+                Ok(Err(existing)) => return Ok(Err(existing)),
+                Err(frozen) => entry = frozen,
+            }
+        };
+        self.len.fetch_add(1, Ordering::SeqCst);
+        self.maybe_consolidate(node_id, delta);
+        Ok(Ok(()))
+    }
+
+    /// Replaces the value of `key` with `new` only if its current value equals
+    /// `expected`, returning `Ok(Err(actual))` with a copy of the current
+    /// value, or `None` if the key is absent, otherwise.
+    ///
+    /// Like `insert_if_absent`, the comparison is atomic with respect to
+    /// other writers to the same node, as it is repeated whenever another
+    /// record is prepended before the new one.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `insert`.
+    pub fn compare_and_swap(
+        &self,
+        key: K,
+        expected: &V,
+        new: V,
+    ) -> Result<Result<(), Option<V>>, BwError>
+    where
+        V: PartialEq,
+    {
+        let _guard = self.epoch.pin();
+        let mut actual = None;
+        let Some((node_id, delta)) = self.push_if(DeltaRecord::Update(key, new), |current| {
+            actual = current.cloned();
+            current == Some(expected)
+        })?
+        else {
+            return Ok(Err(actual));
+        };
+        self.maybe_consolidate(node_id, delta);
+        Ok(Ok(()))
+    }
+
+    /// Returns a copy of the value of `key`, inserting the value returned by
+    /// `f` first if the key is absent.
+    ///
+    /// `f` is only called on a miss, and its value is installed with
+    /// `insert_if_absent`. Concurrent callers that miss the same key each call
+    /// their `f`, so a value may be constructed more than once, but only the
+    /// first one to be installed is kept, and every caller returns it. The
+    /// same goes for a concurrent `insert_if_absent`, while a concurrent
+    /// `insert` may replace the installed value at any time.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `insert`.
+    pub fn get_or_insert_with(&self, key: K, f: impl FnOnce() -> V) -> Result<V, BwError> {
+        if let Some(value) = self.get_owned(key.clone()) {
+            return Ok(value);
+        }
+        let value = f();
+        match self.insert_if_absent(key, value.clone())? {
+            Ok(()) => Ok(value),
+            Err(existing) => Ok(existing),
         }
     }
 
-    fn insert(&self, key: K, value: V) {
-        self.records.push_front(DeltaRecord::Insert(key, value));
+    /// Replaces the value of an existing `key`, returning the previous value.
+    ///
+    /// If `key` is not present, the tree is left unchanged and `None` is
+    /// returned. The update record is only prepended on top of the value it
+    /// returns, so when multiple updates to the same key race, each of them
+    /// observes the value written by the one before it.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `insert`.
+    pub fn update(&self, key: K, value: V) -> Result<Option<V>, BwError> {
+        let _guard = self.epoch.pin();
+        let mut old = None;
+        let Some((node_id, delta)) = self.push_if(DeltaRecord::Update(key, value), |current| {
+            old = current.cloned();
+            old.is_some()
+        })?
+        else {
+            return Ok(None);
+        };
+        self.maybe_consolidate(node_id, delta);
+        Ok(old)
     }
 
-    fn get(&self, key: &K) -> Option<&V> {
-        for ref record in self.records.iter() {
-            match record {
-                DeltaRecord::Insert(k, v) => {
-                    if key == k {
-                        return Some(v);
+    /// Consolidates every leaf-level delta chain that holds any records, for
+    /// maintenance while the tree is idle.
+    ///
+    /// A write only consolidates the chain it extends once the chain grows
+    /// longer than the consolidation threshold, so the chains of nodes that
+    /// stopped being written to are walked by every read of them. This visits
+    /// the leaf-level nodes from left to right and replaces their chains with
+    /// consolidated leaves, splitting or merging them as a write would, and
+    /// the superseded nodes are reclaimed once no reader can observe them.
+    /// Reads and writes can run concurrently, in which case they may leave new
+    /// records behind.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BwError::MappingTableFull` if a node had to be split but no
+    /// node ID was left, after visiting the remaining nodes.
+    pub fn compact(&self) -> Result<(), BwError> {
+        let mut result = Ok(());
+        let mut next = Some(self.order.minimum());
+        while let Some(low) = next {
+            // Pin per node, so that the chains replaced along the way can be
+            // reclaimed.
+            let _guard = self.epoch.pin();
+            let (node_id, upper_bound) = self.find_leaf_with_upper_bound(&low);
+            if let Node::Delta(delta) = self.mapping_table.get(node_id) {
+                if delta.records.len() > 0 {
+                    result = result.and(self.consolidate(node_id));
+                }
+            }
+            next = upper_bound;
+        }
+        result
+    }
+
+    /// Deletes every live entry for which `f` returns `false`.
+    ///
+    /// The leaf-level nodes are visited once, from left to right, and the
+    /// rejected entries of each node are deleted through its delta chain
+    /// after a single descent. An entry is only deleted if `f` rejects the
+    /// value it has when the delete record is prepended, so `f` is called
+    /// again on the newer value if another writer changed it in the meantime.
+    /// Entries written concurrently to nodes that were already visited are
+    /// kept.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `insert`, in which case only the entries below some key
+    /// have been visited.
+    pub fn retain(&self, mut f: impl FnMut(&K, &V) -> bool) -> Result<(), BwError> {
+        let mut next = Some(self.order.minimum());
+        while let Some(low) = next {
+            // Pin per node rather than for the whole traversal, so that the
+            // delta chains consolidated along the way can be reclaimed.
+            let _guard = self.epoch.pin();
+            let (node_id, delta) = self.delta_for(&low)?;
+            let leaf = delta.consolidate(&self.order);
+            // A node that took over the range of its left sibling in a merge
+            // also holds keys that were already visited.
+            for key in leaf.keys.into_iter().filter(|key| self.order.le(&low, key)) {
+                let mut accept = |current: Option<&V>| current.is_some_and(|value| !f(&key, value));
+                let record = DeltaRecord::Delete(key.clone());
+                let deleted =
+                    match delta.push_if(&self.order, record, &self.mapping_table, &mut accept) {
+                        Ok(()) => true,
+                        Err(Refused::Rejected(_)) => false,
+                        // The node is being consolidated, so the key is looked up
+                        // again in the node that replaces it.
+                        Err(Refused::Frozen(record)) => self.push_if(record, accept)?.is_some(),
+                    };
+                if deleted {
+                    self.len.fetch_sub(1, Ordering::SeqCst);
+                }
+            }
+            next = leaf.high_key;
+            self.maybe_consolidate(node_id, delta);
+        }
+        Ok(())
+    }
+
+    /// Deletes every live entry with a key in `start..end`, returning how many
+    /// were deleted.
+    ///
+    /// The leaf-level nodes covering the range are visited like in `retain`,
+    /// and the keys of each node are deleted through its delta chain after a
+    /// single descent. The range is not deleted atomically as a whole: every
+    /// key is deleted on its own, and entries written concurrently to nodes
+    /// that were already visited are kept. Nodes that end up empty or nearly
+    /// so are merged into their left siblings when they are consolidated.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `insert`, in which case only the keys below some key have
+    /// been deleted.
+    pub fn delete_range(&self, start: K, end: K) -> Result<usize, BwError> {
+        let mut deleted = 0;
+        let mut next = Some(start);
+        while let Some(low) = next.take().filter(|low| self.order.lt(low, &end)) {
+            let _guard = self.epoch.pin();
+            let (node_id, delta) = self.delta_for(&low)?;
+            let leaf = delta.consolidate(&self.order);
+            let range = leaf.range(&self.order, &low, Some(&end));
+            for key in leaf.keys[range].iter().cloned() {
+                let accept = |current: Option<&V>| current.is_some();
+                let record = DeltaRecord::Delete(key);
+                let removed = match delta.push_if(&self.order, record, &self.mapping_table, accept)
+                {
+                    Ok(()) => true,
+                    Err(Refused::Rejected(_)) => false,
+                    Err(Refused::Frozen(record)) => self.push_if(record, accept)?.is_some(),
+                };
+                if removed {
+                    self.len.fetch_sub(1, Ordering::SeqCst);
+                    deleted += 1;
+                }
+            }
+            next = leaf.high_key;
+            self.maybe_consolidate(node_id, delta);
+        }
+        Ok(deleted)
+    }
+
+    /// Removes every entry from the tree, leaving an empty leaf under the
+    /// root as in a new tree.
+    ///
+    /// The left-most leaf-level node is replaced with an empty leaf, which
+    /// becomes the only child of the root. Every other node is replaced with
+    /// a remove-node delta that redirects to it, and its ID is retired, so
+    /// readers that are still traversing the old nodes observe either the
+    /// old contents or the empty tree. Entries written concurrently with
+    /// `clear` may or may not survive it.
+    ///
+    /// Every leaf's delta chain is frozen before it is replaced, so a
+    /// concurrent write either lands in the chain before the freeze, and is
+    /// cleared with it, or is refused and goes to the emptied tree.
+    pub fn clear(&self) {
+        let _guard = self.epoch.pin();
+        let _smo = loop {
+            if let Some(smo) = self.try_begin_smo() {
+                break smo;
+            }
+            std::hint::spin_loop();
+        };
+        let mut inner = Vec::new();
+        let mut leaves = Vec::new();
+        let mut pending = vec![self.root_id()];
+        while let Some(node_id) = pending.pop() {
+            match self.mapping_table.get(node_id).inner(&self.order) {
+                Some(node) => {
+                    inner.push(node_id);
+                    pending.extend(node.children.iter().rev());
+                }
+                None => leaves.push(node_id),
+            }
+        }
+        let (&first_leaf_id, rest) = leaves.split_first().expect("tree without leaves");
+        let cleared: usize = leaves
+            .iter()
+            .map(|&node_id| self.freeze_leaf(node_id))
+            .sum();
+        // The emptied leaf takes over the keys of every other leaf, so its
+        // version has to be above all of theirs.
+        let version = leaves
+            .iter()
+            .map(|&node_id| self.mapping_table.get(node_id).version())
+            .max()
+            .unwrap_or(0)
+            + 1;
+
+        let retire =
+            |ptr: *mut Node<K, V>| unsafe { self.epoch.defer(ptr as *mut u8, free_node::<K, V>) };
+        let first_leaf = LeafNode {
+            version,
+            ..LeafNode::new()
+        };
+        retire(
+            self.mapping_table
+                .swap(first_leaf_id, Node::Leaf(first_leaf)),
+        );
+        let mut root = InnerNode::new();
+        root.insert(&self.order, self.order.minimum(), first_leaf_id);
+        retire(self.mapping_table.swap(self.root_id(), Node::Inner(root)));
+        for &node_id in rest {
+            let base = Box::into_raw(Box::new(Node::Leaf(LeafNode::new())));
+            let removal = DeltaNode::new(unsafe { &*base });
+            removal.remove(first_leaf_id);
+            retire(self.mapping_table.swap(node_id, Node::Delta(removal)));
+        }
+        for &node_id in rest.iter().chain(&inner[1..]) {
+            self.retire_node_id(node_id);
+        }
+        // Writes that landed before the freezes may not have counted
+        // themselves yet, so only the entries that were cleared are taken
+        // off.
+        self.len.fetch_sub(cleared, Ordering::SeqCst);
+    }
+
+    /// Freezes the delta chain of leaf `node_id` so that no write can land in
+    /// it anymore, and returns the number of entries it holds.
+    ///
+    /// A leaf without a chain gets an empty one first, and a chain that is
+    /// frozen already is waited out until its consolidation replaces it.
+    fn freeze_leaf(&self, node_id: NodeID) -> usize {
+        loop {
+            let ptr = self.mapping_table.load(node_id);
+            let node = unsafe { &*ptr };
+            match node {
+                Node::Delta(delta) if delta.is_frozen() => {
+                    while self.mapping_table.load(node_id) == ptr {
+                        std::thread::yield_now();
+                    }
+                }
+                Node::Delta(delta) => {
+                    if delta.freeze() {
+                        return delta.consolidate(&self.order).count;
                     }
                 }
+                Node::Leaf(_) => {
+                    let delta = DeltaNode::new(node);
+                    let _ = self.mapping_table.cas(node_id, ptr, Node::Delta(delta));
+                }
+                Node::Inner(_) | Node::IndexDelta(_) => unreachable!("inner node at leaf level"),
             }
         }
-        None
     }
-}
 
-#[derive(Debug)]
-enum DeltaRecord<K, V> {
-    Insert(K, V),
-}
+    /// Returns the entry for `key`, for an in-place read-modify-write.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BwError::Retry` if the node responsible for `key` could not
+    /// be reached.
+    pub fn entry(&self, key: K) -> Result<Entry<'_, K, V>, BwError> {
+        Entry::new(self, key)
+    }
 
-#[derive(Debug)]
-struct LeafNode<K, V> {
-    /// The number of keys stored in the node.
-    count: usize,
-    /// The key ranges stored in the children.
-    keys: Vec<K>,
-    /// The values stored in the node.
-    values: Vec<V>,
-}
+    /// Returns a reference to the value of `key`.
+    ///
+    /// The returned guard keeps the epoch pinned, so the node holding the
+    /// value is not freed while the reference is in use, even if another
+    /// thread overwrites the key or consolidates the node. Memory retired in
+    /// the meantime is not reclaimed until the guard is dropped, so avoid
+    /// holding on to it for long.
+    ///
+    /// Every write returns only once its record is published in the mapping
+    /// table, and lookups load the table with acquire ordering, so a lookup
+    /// observes every write that returned before it on the same thread, or
+    /// on another thread it synchronized with.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lookup reaches a node ID that is not mapped, which only
+    /// happens if the tree is corrupted. Use `try_get` to get an error instead.
+    pub fn get(&self, key: K) -> Option<Guard<'_, V>> {
+        self.try_get(key).expect("dangling node ID")
+    }
 
-impl<K, V> LeafNode<K, V>
-where
-    K: KeyType,
-{
-    fn new() -> Self {
-        LeafNode {
-            count: 0,
-            keys: Vec::new(),
-            values: Vec::new(),
+    /// Returns a reference to the value of `key` like `get`, but fails with
+    /// `BwError::DanglingNode` instead of panicking if the lookup reaches a
+    /// node ID that is not mapped.
+    pub fn try_get(&self, key: K) -> Result<Option<Guard<'_, V>>, BwError> {
+        let guard = self.epoch.pin();
+        let root = self.mapping_table.try_get(self.root_id())?;
+        let value = root.get(&self.order, &key, &self.mapping_table)?;
+        Ok(value.map(|value| Guard::new(guard, value)))
+    }
+
+    /// Returns a copy of the value of `key`.
+    ///
+    /// The value is cloned while the epoch is pinned, so this is safe to call
+    /// while other threads modify the tree.
+    ///
+    /// # Panics
+    ///
+    /// Panics like `get`.
+    pub fn get_owned(&self, key: K) -> Option<V> {
+        self.get(key).map(|value| V::clone(&value))
+    }
+
+    /// Returns a copy of the value of `key` like `get_owned`, but fails like
+    /// `try_get` instead of panicking if the tree is corrupted.
+    pub fn try_get_owned(&self, key: K) -> Result<Option<V>, BwError> {
+        Ok(self.try_get(key)?.map(|value| V::clone(&value)))
+    }
+
+    /// Returns a copy of the value of `key` along with its version, which
+    /// `validate` checks later to tell whether the value may have changed.
+    ///
+    /// Versions are kept per leaf-level node rather than per key, so writes
+    /// to other keys in the same node, and splits and merges of the node,
+    /// change the version too. A version that validates guarantees that the
+    /// value is unchanged, while one that doesn't may be a false alarm.
+    ///
+    /// # Panics
+    ///
+    /// Panics like `get`.
+    pub fn get_with_version(&self, key: K) -> Option<(V, u64)> {
+        let _guard = self.epoch.pin();
+        let (value, version) = self.get_versioned(&key);
+        value.map(|value| (value.clone(), version))
+    }
+
+    /// Returns `true` if `key` still has the value `get_with_version` returned
+    /// along with `version`.
+    ///
+    /// # Panics
+    ///
+    /// Panics like `get`.
+    pub fn validate(&self, key: K, version: u64) -> bool {
+        let _guard = self.epoch.pin();
+        self.get_versioned(&key).1 == version
+    }
+
+    /// Descends the tree to the leaf-level node responsible for `key`, and
+    /// returns it with the epoch pinned, for reads that the other lookups
+    /// don't cover.
+    ///
+    /// Every lookup through the returned guard goes to that one node, without
+    /// descending the tree again. The node keeps taking writes and may be
+    /// consolidated, split or merged away while the guard is alive, so a read
+    /// made up of several lookups should check `LeafGuard::is_current` once
+    /// it is done, and descend again if the node has changed in between. Only
+    /// keys from `key` up to `LeafGuard::upper_bound` are guaranteed to be in
+    /// the node, and the epoch stays pinned until the guard is dropped, which
+    /// holds back the reclamation of every node retired in the meantime.
+    ///
+    /// # Panics
+    ///
+    /// Panics like `get`.
+    pub fn descend_to_leaf(&self, key: &K) -> LeafGuard<'_, K, V> {
+        LeafGuard::new(self, key)
+    }
+
+    /// Returns copies of the values of `keys`, in the order of `keys`.
+    ///
+    /// The keys are looked up in ascending order, so that consecutive keys
+    /// that fall into the same leaf-level node are looked up after a single
+    /// descent, like the entries written by `batch_insert`.
+    ///
+    /// # Panics
+    ///
+    /// Panics like `get`.
+    pub fn get_many(&self, keys: &[K]) -> Vec<Option<V>> {
+        let mut positions: Vec<_> = (0..keys.len()).collect();
+        positions.sort_by(|&a, &b| self.order.cmp(&keys[a], &keys[b]));
+        let mut values = vec![None; keys.len()];
+        let mut positions = positions.into_iter().peekable();
+        while let Some(&first) = positions.peek() {
+            // Pin per node rather than for the whole batch, like the scans.
+            let _guard = self.epoch.pin();
+            let (node_id, upper_bound) = self.find_leaf_with_upper_bound(&keys[first]);
+            let node = self.mapping_table.get(node_id);
+            let in_node = |&i: &usize| {
+                upper_bound
+                    .as_ref()
+                    .is_none_or(|upper| self.order.lt(&keys[i], upper))
+            };
+            while let Some(i) = positions.next_if(in_node) {
+                let value = node.get(&self.order, &keys[i], &self.mapping_table);
+                values[i] = value.expect("dangling node ID").cloned();
+            }
         }
+        values
     }
 
-    fn get(&self, key: &K) -> Option<&V> {
-        for i in 0..self.count {
-            if key == &self.keys[i] {
-                return Some(&self.values[i]);
+    /// Returns the number of live entries in the tree.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::SeqCst)
+    }
+
+    /// Returns `true` if the tree contains no live entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns `true` if the tree contains a live entry for `key`.
+    ///
+    /// # Panics
+    ///
+    /// Panics like `get`.
+    pub fn contains_key(&self, key: K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Returns statistics about the nodes and delta chains of the tree, for
+    /// tuning the consolidation, split and merge thresholds.
+    ///
+    /// This walks every node, so it is meant for diagnostics rather than for
+    /// use on a hot path.
+    pub fn stats(&self) -> Stats {
+        let _guard = self.epoch.pin();
+        let mut stats = Stats::default();
+        let mut first_leaf_id = None;
+        let mut pending = vec![self.root_id()];
+        while let Some(node_id) = pending.pop() {
+            match self.mapping_table.get(node_id).inner(&self.order) {
+                Some(node) => {
+                    stats.inner_count += 1;
+                    pending.extend(node.children.iter().rev());
+                }
+                None => {
+                    first_leaf_id.get_or_insert(node_id);
+                }
             }
         }
-        None
+        // Walk the leaf level along the sibling links, which also reach the
+        // right halves of splits whose index term is not posted yet.
+        let mut next = first_leaf_id;
+        while let Some(node_id) = next {
+            let node = self.mapping_table.get(node_id);
+            stats.leaf_count += 1;
+            if let Node::Delta(delta) = node {
+                let records = delta.records.len();
+                stats.total_delta_records += records;
+                stats.longest_delta_chain = stats.longest_delta_chain.max(records);
+            }
+            stats.live_keys += node.entries(&self.order).len();
+            next = node.split().map(|(_, right)| right);
+        }
+        stats.node_count = stats.inner_count + stats.leaf_count;
+        stats
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
+    /// Returns the number of live entries in every leaf-level node, from the
+    /// left-most node to the right-most one.
+    ///
+    /// Like `stats`, this walks the whole leaf level, and shows how evenly
+    /// the splits divided the keys for any type of key.
+    pub fn leaf_histogram(&self) -> Vec<usize> {
+        let _guard = self.epoch.pin();
+        let mut histogram = Vec::new();
+        let mut next = Some(self.leftmost_leaf(self.root_id()));
+        while let Some(node_id) = next {
+            let node = self.mapping_table.get(node_id);
+            histogram.push(node.entries(&self.order).len());
+            next = node.split().map(|(_, right)| right);
+        }
+        histogram
+    }
 
-    #[test]
-    fn test_insert_and_get() {
-        let tree = BwTree::new();
-        assert!(tree.insert(1, "A"));
-        assert_eq!(tree.get(1), Some(&"A"));
-        assert!(tree.insert(2, "B"));
-        assert_eq!(tree.get(2), Some(&"B"));
-        assert!(tree.insert(3, "C"));
-        assert_eq!(tree.get(3), Some(&"C"));
-        assert!(tree.insert(4, "D"));
-        assert_eq!(tree.get(4), Some(&"D"));
+    /// Returns the number of levels of the tree, from the root down to and
+    /// including the leaf level, which is the number of nodes a lookup
+    /// descends through.
+    ///
+    /// The levels are counted along the left-most path, as every leaf-level
+    /// node is at the same depth.
+    pub fn height(&self) -> usize {
+        let _guard = self.epoch.pin();
+        let mut height = 1;
+        let mut node_id = self.root_id();
+        while let Some(child) = self
+            .mapping_table
+            .get(node_id)
+            .find_child(&self.order, &self.order.minimum())
+        {
+            height += 1;
+            node_id = child;
+        }
+        height
     }
 
-    #[test]
-    fn test_insert_retains_existing_entries() {
-        // The Bw-Tree stores insertions into a delta chain. Let's make sure
-        // that `insert()` doesn't lose existing entries.
-        let tree = BwTree::new();
-        assert!(tree.insert(1, "A"));
-        assert_eq!(tree.get(1), Some(&"A"));
-        assert!(tree.insert(2, "B"));
-        assert_eq!(tree.get(1), Some(&"A"));
-        assert!(tree.insert(2, "B"));
+    /// Returns an estimate of the heap memory used by the tree, in bytes.
+    ///
+    /// The estimate covers the mapping table and every node mapped in it,
+    /// including the spare capacity of their vectors and the records of their
+    /// delta chains, but not heap memory owned by the keys and values
+    /// themselves. Nodes that were replaced but not reclaimed yet are not
+    /// included either. Like `stats`, the estimate is only a consistent
+    /// snapshot if the tree is not modified concurrently.
+    pub fn memory_bytes(&self) -> usize {
+        let _guard = self.epoch.pin();
+        self.mapping_table.memory_bytes()
+    }
+
+    /// Checks the structural invariants of the tree, returning a description
+    /// of the first violation found.
+    ///
+    /// Inner nodes must have one sorted key per child, starting at the lower
+    /// bound of their key range, and every child must be mapped. Leaf-level
+    /// nodes must hold sorted keys within the key range that their parent
+    /// routes to them, and the sibling links of the leaf-level nodes under a
+    /// parent must lead from each child to the next one. Finally, the length
+    /// of the tree must match the number of live entries.
+    ///
+    /// Concurrent writers can make a correct tree appear broken, so this is
+    /// meant for tests and for debugging a quiescent tree.
+    pub fn verify(&self) -> Result<(), String> {
+        let _guard = self.epoch.pin();
+        let live = self.verify_inner(self.root_id(), &self.order.minimum(), None, None)?;
+        if live != self.len() {
+            return Err(format!(
+                "length is {} but the tree holds {live} live entries",
+                self.len()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Verifies the subtree under inner node `node_id`, which covers the keys
+    /// in `low..high` and is followed by the node `next` at its level, and
+    /// returns the number of live entries in it.
+    fn verify_inner(
+        &self,
+        node_id: NodeID,
+        low: &K,
+        high: Option<&K>,
+        next: Option<NodeID>,
+    ) -> Result<usize, String> {
+        let Some(node) = self.mapping_table.get(node_id).inner(&self.order) else {
+            return Err(format!("node {node_id} is not an inner node"));
+        };
+        if node.keys.len() != node.children.len() {
+            return Err(format!(
+                "inner node {node_id} has {} keys but {} children",
+                node.keys.len(),
+                node.children.len()
+            ));
+        }
+        if node
+            .keys
+            .first()
+            .is_none_or(|first| !self.order.eq(first, low))
+        {
+            return Err(format!(
+                "inner node {node_id} starts at {:?} instead of {low:?}",
+                node.keys.first()
+            ));
+        }
+        if let Some(idx) = node
+            .keys
+            .windows(2)
+            .position(|w| !self.order.lt(&w[0], &w[1]))
+        {
+            return Err(format!(
+                "keys of inner node {node_id} are not sorted at index {}",
+                idx + 1
+            ));
+        }
+        if let Some(high) = high.filter(|high| {
+            node.keys
+                .last()
+                .is_some_and(|last| self.order.le(high, last))
+        }) {
+            return Err(format!(
+                "inner node {node_id} routes keys at or above its upper bound {high:?}"
+            ));
+        }
+        let mut live = 0;
+        for (idx, &child) in node.children.iter().enumerate() {
+            let child_low = &node.keys[idx];
+            let child_high = node.keys.get(idx + 1).or(high);
+            let child_next = node
+                .children
+                .get(idx + 1)
+                .map(|&next| self.leftmost_leaf(next))
+                .or(next);
+            if self.mapping_table.load(child).is_null() {
+                return Err(format!(
+                    "child {child} of inner node {node_id} is not mapped"
+                ));
+            }
+            live += if self.mapping_table.get(child).is_inner() {
+                self.verify_inner(child, child_low, child_high, child_high.and(child_next))?
+            } else {
+                self.verify_leaf_level(child, child_low, child_high, child_next)?
+            };
+        }
+        Ok(live)
+    }
+
+    /// Verifies the leaf-level node `node_id` and the right siblings split
+    /// off it, which together cover the keys in `low..high` and are followed
+    /// by the node `next`, and returns the number of live entries in them.
+    fn verify_leaf_level(
+        &self,
+        node_id: NodeID,
+        low: &K,
+        high: Option<&K>,
+        next: Option<NodeID>,
+    ) -> Result<usize, String> {
+        let mut live = 0;
+        let mut node_id = node_id;
+        let mut low = low.clone();
+        loop {
+            if self.mapping_table.load(node_id).is_null() {
+                return Err(format!("leaf-level node {node_id} is not mapped"));
+            }
+            let node = self.mapping_table.get(node_id);
+            if node.left_link().is_some() {
+                return Err(format!(
+                    "leaf-level node {node_id} was merged but is still reachable"
+                ));
+            }
+            let base = match node {
+                Node::Delta(delta) => unsafe { &*delta.base },
+                node => node,
+            };
+            let Node::Leaf(leaf) = base else {
+                return Err(format!("base of leaf-level node {node_id} is not a leaf"));
+            };
+            if leaf.keys.len() != leaf.count || leaf.values.len() != leaf.count {
+                return Err(format!(
+                    "leaf of node {node_id} has {} keys and {} values but a count of {}",
+                    leaf.keys.len(),
+                    leaf.values.len(),
+                    leaf.count
+                ));
+            }
+            if let Some(idx) = leaf
+                .keys
+                .windows(2)
+                .position(|w| !self.order.lt(&w[0], &w[1]))
+            {
+                return Err(format!(
+                    "keys of leaf-level node {node_id} are not sorted at index {}",
+                    idx + 1
+                ));
+            }
+
+            let bound = node
+                .split()
+                .map(|(high_key, right)| (high_key.clone(), right));
+            let node_high = bound.as_ref().map(|(high_key, _)| high_key).or(high);
+            let entries = node.entries(&self.order);
+            let out_of_range = entries.iter().find(|(key, _)| {
+                self.order.lt(key, &low) || node_high.is_some_and(|high| self.order.le(high, key))
+            });
+            if let Some((key, _)) = out_of_range {
+                return Err(format!(
+                    "leaf-level node {node_id} holds key {key:?} outside of {low:?}..{node_high:?}"
+                ));
+            }
+            live += entries.len();
+
+            match (bound, high) {
+                // A split whose index term is not posted yet.
+                (Some((high_key, right)), high)
+                    if high.is_none_or(|high| self.order.lt(&high_key, high)) =>
+                {
+                    low = high_key;
+                    node_id = right;
+                }
+                (Some((high_key, right)), Some(high)) if self.order.eq(&high_key, high) => {
+                    if Some(right) != next {
+                        return Err(format!(
+                            "leaf-level node {node_id} links to {right} instead of {next:?}"
+                        ));
+                    }
+                    return Ok(live);
+                }
+                (None, None) => return Ok(live),
+                (bound, high) => {
+                    return Err(format!(
+                        "leaf-level node {node_id} is bounded by {:?} instead of {high:?}",
+                        bound.map(|(high_key, _)| high_key)
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Returns the number of live entries with keys in `start..end`.
+    ///
+    /// The leaf-level nodes are visited like in `range`, but their entries
+    /// are counted in place rather than copied out.
+    pub fn count_range(&self, start: K, end: K) -> usize {
+        let mut count = 0;
+        let mut next = Some(start);
+        while let Some(low) = next.take().filter(|low| self.order.lt(low, &end)) {
+            let _guard = self.epoch.pin();
+            let (node_id, upper_bound) = self.find_leaf_with_upper_bound(&low);
+            count += self.mapping_table.get(node_id).count_range(
+                &self.order,
+                &low,
+                Some(&end),
+                usize::MAX,
+            );
+            next = upper_bound;
+        }
+        count
+    }
+
+    /// Returns `true` if the tree contains a live entry with a key in
+    /// `start..end`.
+    ///
+    /// The leaf-level nodes are visited like in `count_range`, but the scan
+    /// stops at the first live entry, so it usually visits a single node.
+    pub fn contains_range(&self, start: K, end: K) -> bool {
+        let mut next = Some(start);
+        while let Some(low) = next.take().filter(|low| self.order.lt(low, &end)) {
+            let _guard = self.epoch.pin();
+            let (node_id, upper_bound) = self.find_leaf_with_upper_bound(&low);
+            if self
+                .mapping_table
+                .get(node_id)
+                .count_range(&self.order, &low, Some(&end), 1)
+                > 0
+            {
+                return true;
+            }
+            next = upper_bound;
+        }
+        false
+    }
+
+    /// Returns an iterator over the entries with keys in `start..end`, in
+    /// ascending key order.
+    pub fn range(&self, start: K, end: K) -> RangeIter<'_, K, V> {
+        RangeIter::new(self, Bound::Included(start), Bound::Excluded(end))
+    }
+
+    /// Returns an iterator over the entries with keys in `start..=end`, in
+    /// ascending key order.
+    pub fn range_inclusive(&self, start: K, end: K) -> RangeIter<'_, K, V> {
+        RangeIter::new(self, Bound::Included(start), Bound::Included(end))
+    }
+
+    /// Returns an iterator over the entries with keys in `start..end` in
+    /// pages of at most `page_size` entries, for callers that send results in
+    /// batches rather than one entry at a time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `page_size` is zero.
+    pub fn range_pages(&self, start: K, end: K, page_size: usize) -> RangePages<'_, K, V> {
+        RangePages::new(self.range(start, end), page_size)
+    }
+
+    /// Folds the live entries with keys in `start..end` into an accumulator,
+    /// in ascending key order, starting from `init`.
+    ///
+    /// The leaf-level nodes are visited like in `count_range`, and their
+    /// entries are folded in place rather than copied out, so `f` is called
+    /// while the node is pinned.
+    pub fn range_fold<B>(&self, start: K, end: K, init: B, f: impl Fn(B, &K, &V) -> B) -> B {
+        let mut acc = init;
+        let mut next = Some(start);
+        while let Some(low) = next.take().filter(|low| self.order.lt(low, &end)) {
+            let _guard = self.epoch.pin();
+            let (node_id, upper_bound) = self.find_leaf_with_upper_bound(&low);
+            acc =
+                self.mapping_table
+                    .get(node_id)
+                    .fold_range(&self.order, &low, Some(&end), acc, &f);
+            next = upper_bound;
+        }
+        acc
+    }
+
+    /// Returns an iterator over the entries with keys at or above `key`, in
+    /// ascending key order.
+    ///
+    /// Together with `upper_bound`, this lets a scan be resumed from the last
+    /// key it returned without keeping the iterator around.
+    pub fn lower_bound(&self, key: K) -> RangeIter<'_, K, V> {
+        RangeIter::new(self, Bound::Included(key), Bound::Unbounded)
+    }
+
+    /// Returns an iterator over the entries with keys above `key`, in
+    /// ascending key order.
+    pub fn upper_bound(&self, key: K) -> RangeIter<'_, K, V> {
+        RangeIter::new(self, Bound::Excluded(key), Bound::Unbounded)
+    }
+
+    /// Returns an iterator over all entries in ascending key order.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter::new(self)
+    }
+
+    /// Returns an iterator over all keys in ascending order.
+    pub fn keys(&self) -> Keys<'_, K, V> {
+        Keys::new(self)
+    }
+
+    /// Returns an iterator over all values in ascending order of their keys.
+    pub fn values(&self) -> Values<'_, K, V> {
+        Values::new(self)
+    }
+
+    /// Returns an iterator that deletes every live entry and yields it, in
+    /// ascending key order.
+    ///
+    /// The tree is empty once the iterator is consumed, unless entries were
+    /// written concurrently, and only the yielded entries are deleted if it
+    /// is dropped early.
+    pub fn drain(&self) -> Drain<'_, K, V> {
+        Drain::new(self)
+    }
+
+    /// Moves every live entry with a key at or above `key` into a new tree,
+    /// like `BTreeMap::split_off`.
+    ///
+    /// The entries are deleted one at a time like in `drain`, and the new
+    /// tree, which has the thresholds and the key order of `self`, is
+    /// bulk-loaded from them like in `from_sorted`. Entries written
+    /// concurrently at or above `key` may stay in `self`.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `delete`, in which case the entries that were already
+    /// deleted are written back to `self`, unless their keys were written to
+    /// in the meantime. If an entry cannot be written back, the error of the
+    /// first such write is returned instead, and the entries that could not
+    /// be written back are lost.
+    pub fn split_off(&self, key: K) -> Result<Self, BwError> {
+        let mut moved = Vec::new();
+        for (key, _) in self.lower_bound(key) {
+            match self.remove_if(key.clone(), |_| true) {
+                Ok(Some(value)) => moved.push((key, value)),
+                Ok(None) => {}
+                Err(err) => return Err(self.restore(moved, err)),
+            }
+        }
+        Ok(Self::load_sorted(self.empty_copy(), moved).expect("range scan out of order"))
+    }
+
+    /// Writes back `entries` that were deleted by a write that then failed
+    /// with `err`, keeping the keys that were written to in the meantime, and
+    /// returns the error to report for the write.
+    ///
+    /// That is `err`, unless an entry could not be written back, in which case
+    /// it is the error of the first entry that could not.
+    fn restore(&self, entries: Vec<(K, V)>, err: BwError) -> BwError {
+        let mut first = None;
+        for (key, value) in entries {
+            if let Err(restore) = self.insert_if_absent(key, value) {
+                first.get_or_insert(restore);
+            }
+        }
+        first.unwrap_or(err)
+    }
+
+    /// Returns copies of all live entries in ascending key order.
+    ///
+    /// Like `iter`, this is not a snapshot: entries written concurrently may
+    /// or may not be included.
+    pub fn into_sorted_vec(&self) -> Vec<(K, V)> {
+        self.iter().collect()
+    }
+
+    /// Returns an iterator over the entries with keys in `start..end`, in
+    /// descending key order.
+    pub fn range_rev(&self, start: K, end: K) -> RevRangeIter<'_, K, V> {
+        RevRangeIter::new(self, start, Some(end))
+    }
+
+    /// Returns an iterator over all entries in descending key order.
+    pub fn iter_rev(&self) -> RevIter<'_, K, V> {
+        RevIter::new(self)
+    }
+
+    /// Returns a cursor before the first entry, which can be moved in either
+    /// direction and repositioned with `Cursor::seek`.
+    ///
+    /// Unlike the iterators, the cursor buffers nothing between steps, so
+    /// every step costs a descent of the tree, but sees all writes that
+    /// landed before it.
+    pub fn cursor(&self) -> Cursor<'_, K, V> {
+        Cursor::new(self)
+    }
+
+    /// Returns a read-only view of the tree that is not affected by later
+    /// writes.
+    ///
+    /// Taking a snapshot doesn't copy any entries, but it keeps the epoch
+    /// pinned until it is dropped, so memory retired in the meantime is not
+    /// reclaimed. See `Snapshot` for the consistency it provides.
+    pub fn snapshot(&self) -> Snapshot<'_, K, V> {
+        Snapshot::new(self)
+    }
+
+    /// Returns a copy of the live entry with the smallest key.
+    ///
+    /// Leaf-level nodes are visited from the left-most one until one holds a
+    /// live entry, so deleted keys are skipped.
+    pub fn first_key_value(&self) -> Option<(K, V)> {
+        self.iter().next()
+    }
+
+    /// Returns a copy of the live entry with the largest key.
+    ///
+    /// Leaf-level nodes are visited from the right-most one until one holds a
+    /// live entry, so deleted keys are skipped.
+    pub fn last_key_value(&self) -> Option<(K, V)> {
+        let _guard = self.epoch.pin();
+        self.last_key_value_under(self.root_id(), None)
+    }
+
+    /// Returns the live entry with the largest key under `node_id`.
+    ///
+    /// Right siblings of a leaf-level node that its parent doesn't know about
+    /// yet are visited as well, up to `stop`, the left-most leaf-level node of
+    /// the subtree to the right of it.
+    fn last_key_value_under(&self, node_id: NodeID, stop: Option<NodeID>) -> Option<(K, V)> {
+        if let Some(node) = self.mapping_table.get(node_id).inner(&self.order) {
+            let mut stop = stop;
+            for &child in node.children.iter().rev() {
+                if let Some(entry) = self.last_key_value_under(child, stop) {
+                    return Some(entry);
+                }
+                stop = Some(self.leftmost_leaf(child));
+            }
+            return None;
+        }
+        let mut last = None;
+        let mut node_id = node_id;
+        loop {
+            let node = self.mapping_table.get(node_id);
+            // The entries of a removed node are covered by its left sibling.
+            if node.left_link().is_some() {
+                return last;
+            }
+            if let Some(entry) = node.entries(&self.order).pop() {
+                last = Some(entry);
+            }
+            match node.split() {
+                Some((_, right)) if Some(right) != stop => node_id = right,
+                _ => return last,
+            }
+        }
+    }
+
+    /// Returns the ID of the root node.
+    fn root_id(&self) -> NodeID {
+        self.root_id.load(Ordering::Acquire)
+    }
+
+    /// Returns the ID of the left-most leaf-level node under `node_id`.
+    fn leftmost_leaf(&self, node_id: NodeID) -> NodeID {
+        let mut node_id = node_id;
+        while let Some(node) = self.mapping_table.get(node_id).inner(&self.order) {
+            node_id = node.children[0];
+        }
+        node_id
+    }
+
+    /// Returns the ID of the leaf-level node responsible for `key`.
+    fn find_leaf(&self, key: &K) -> NodeID {
+        let mut node_id = self.root_id();
+        while let Some(child) = self.mapping_table.get(node_id).find_child(&self.order, key) {
+            node_id = child;
+        }
+        node_id
+    }
+
+    /// Returns the ID of the leaf-level node responsible for `key`, along with
+    /// the lowest key of the next leaf-level node, if there is one.
+    ///
+    /// Unlike `find_leaf`, this follows right siblings of split nodes whose
+    /// separator has not been posted to the parent yet.
+    fn find_leaf_with_upper_bound(&self, key: &K) -> (NodeID, Option<K>) {
+        #[cfg(test)]
+        self.descents.fetch_add(1, Ordering::Relaxed);
+        let mut node_id = self.root_id();
+        let mut upper_bound = None;
+        while let Some((child, high)) = self.mapping_table.get(node_id).route(&self.order, key) {
+            if let Some(separator) = high {
+                upper_bound = Some(separator.clone());
+            }
+            node_id = child;
+        }
+        self.move_right(node_id, key, upper_bound)
+    }
+
+    /// Returns the ID of the leaf-level node responsible for `key` along with
+    /// its upper bound, like `find_leaf_with_upper_bound`, by following the
+    /// right sibling link of `prev`, the node whose keys end right below
+    /// `key`, instead of descending the tree.
+    ///
+    /// Returns `None` if `prev` doesn't end right below `key` anymore, which
+    /// includes it having been merged away or its ID having been reused, in
+    /// which case the tree has to be descended after all.
+    fn find_leaf_after(&self, prev: NodeID, key: &K) -> Option<(NodeID, Option<K>)> {
+        let node = self.mapping_table.try_get(prev).ok()?;
+        if !matches!(node, Node::Delta(_) | Node::Leaf(_)) || node.left_link().is_some() {
+            return None;
+        }
+        let (high_key, right) = node.split()?;
+        // A leaf-level node that ends right below `key` is the left sibling
+        // of the node responsible for `key`, whichever node it is.
+        self.order
+            .eq(high_key, key)
+            .then(|| self.move_right(right, key, None))
+    }
+
+    /// Moves from the leaf-level node `node_id` to the one responsible for
+    /// `key`, following the links of merged and split nodes, and returns its
+    /// ID along with its upper bound, or `upper_bound` if it doesn't know
+    /// its own.
+    fn move_right(
+        &self,
+        mut node_id: NodeID,
+        key: &K,
+        mut upper_bound: Option<K>,
+    ) -> (NodeID, Option<K>) {
+        loop {
+            let node = self.mapping_table.get(node_id);
+            match node
+                .left_link()
+                .or_else(|| node.right_link(&self.order, key))
+            {
+                Some(next) => node_id = next,
+                None => break,
+            }
+        }
+        if let Some((high_key, _)) = self.mapping_table.get(node_id).split() {
+            upper_bound = Some(high_key.clone());
+        }
+        (node_id, upper_bound)
+    }
+
+    /// Returns the ID of the leaf-level node responsible for the keys right
+    /// below `bound`, or for the largest keys if there is no bound, along with
+    /// the lowest key of that node.
+    fn find_leaf_before(&self, bound: Option<&K>) -> (NodeID, K) {
+        'descend: loop {
+            let mut node_id = self.root_id();
+            let mut low = self.order.minimum();
+            while let Some((child, separator)) = self
+                .mapping_table
+                .get(node_id)
+                .route_before(&self.order, bound)
+            {
+                low = separator.clone();
+                node_id = child;
+            }
+            loop {
+                let node = self.mapping_table.get(node_id);
+                // The left sibling of a removed node takes over its range, but
+                // its lowest key is only known to the parent, so wait for the
+                // index term of the removed node to be deleted.
+                if node.left_link().is_some() {
+                    continue 'descend;
+                }
+                match node.split() {
+                    Some((high_key, right))
+                        if bound.is_none_or(|bound| self.order.lt(high_key, bound)) =>
+                    {
+                        low = high_key.clone();
+                        node_id = right;
+                    }
+                    _ => return (node_id, low),
+                }
+            }
+        }
+    }
+
+    /// Returns the ID of the inner node that points to the leaf-level node
+    /// responsible for `key`.
+    fn find_parent(&self, key: &K) -> NodeID {
+        let mut node_id = self.root_id();
+        loop {
+            let child = match self.mapping_table.get(node_id).find_child(&self.order, key) {
+                Some(child) => child,
+                None => unreachable!("leaf-level node above the leaf level"),
+            };
+            if !self.mapping_table.get(child).is_inner() {
+                return node_id;
+            }
+            node_id = child;
+        }
+    }
+
+    /// Looks up `key` in the leaf-level node responsible for it, and returns
+    /// the value along with the version of the node it was read from.
+    ///
+    /// The version is read before and after the value, and the lookup is
+    /// repeated if a record was prepended in between.
+    fn get_versioned(&self, key: &K) -> (Option<&V>, u64) {
+        let mut node_id = self.find_leaf(key);
+        loop {
+            let node = self.mapping_table.get(node_id);
+            if let Some(next) = node
+                .left_link()
+                .or_else(|| node.right_link(&self.order, key))
+            {
+                node_id = next;
+                continue;
+            }
+            let version = node.version();
+            let value = node
+                .get(&self.order, key, &self.mapping_table)
+                .expect("dangling node ID");
+            if node.version() == version {
+                return (value, version);
+            }
+        }
+    }
+
+    /// Returns the delta chain of the leaf-level node responsible for `key`,
+    /// along with the ID of that node.
+    ///
+    /// If the node is still a bare leaf, a fresh delta node is chained on top
+    /// of it with the leaf as its base, and if its delta chain is frozen, the
+    /// node that replaces the chain is waited for. Gives up with
+    /// `BwError::Retry` if the node keeps changing, or keeps redirecting to a
+    /// sibling, after `MAX_RETRIES` attempts.
+    fn delta_for(&self, key: &K) -> Result<(NodeID, &DeltaNode<K, V>), BwError> {
+        let mut node_id = self.find_leaf(key);
+        for _ in 0..MAX_RETRIES {
+            let ptr = self.mapping_table.load(node_id);
+            let node = unsafe { &*ptr };
+            // The node may have been split or merged after the parent was
+            // read, in which case the key now lives in a sibling.
+            if let Some(next) = node
+                .left_link()
+                .or_else(|| node.right_link(&self.order, key))
+            {
+                node_id = next;
+                continue;
+            }
+            match node {
+                Node::Inner(_) | Node::IndexDelta(_) => unreachable!("inner node at leaf level"),
+                Node::Delta(delta) if delta.is_frozen() => {
+                    while self.mapping_table.load(node_id) == ptr {
+                        std::thread::yield_now();
+                    }
+                }
+                // A chain at the cap is consolidated before it grows any
+                // longer. If the consolidated leaf can't be split for lack of
+                // node IDs, it is installed oversized, and the write goes to
+                // it with the split deferred as in `maybe_consolidate`.
+                Node::Delta(delta) if delta.records.len() >= self.max_chain_len => {
+                    let _ = self.consolidate(node_id);
+                }
+                Node::Delta(delta) => return Ok((node_id, delta)),
+                Node::Leaf(_) => {
+                    // If another thread replaced the leaf in the meantime,
+                    // retry against whatever the node is mapped to now.
+                    let delta = DeltaNode::new(node);
+                    let _ = self.mapping_table.cas(node_id, ptr, Node::Delta(delta));
+                }
+            }
+        }
+        Err(BwError::Retry)
+    }
+
+    /// Returns the delta chain of the leaf-level node responsible for `key`
+    /// like `delta_for`, or `None` where `delta_for` would wait or retry.
+    fn try_delta_for(&self, key: &K) -> Option<(NodeID, &DeltaNode<K, V>)> {
+        let mut node_id = self.find_leaf(key);
+        loop {
+            let ptr = self.mapping_table.load(node_id);
+            let node = unsafe { &*ptr };
+            if let Some(next) = node
+                .left_link()
+                .or_else(|| node.right_link(&self.order, key))
+            {
+                node_id = next;
+                continue;
+            }
+            return match node {
+                Node::Inner(_) | Node::IndexDelta(_) => unreachable!("inner node at leaf level"),
+                Node::Delta(delta) if delta.is_frozen() => None,
+                Node::Delta(delta) if delta.records.len() >= self.max_chain_len => None,
+                Node::Delta(delta) => Some((node_id, delta)),
+                Node::Leaf(_) => {
+                    let delta = DeltaNode::new(node);
+                    self.mapping_table
+                        .cas(node_id, ptr, Node::Delta(delta))
+                        .ok()?;
+                    match self.mapping_table.get(node_id) {
+                        Node::Delta(delta) => Some((node_id, delta)),
+                        _ => None,
+                    }
+                }
+            };
+        }
+    }
+
+    /// Consolidates `delta`, the delta chain of `node_id` that a write just
+    /// extended, if it has grown long enough.
+    ///
+    /// The write has landed already, so it succeeds whatever happens here. If
+    /// the consolidated leaf has to be split but no node ID is left, it is
+    /// installed oversized, and the split is deferred to a later write.
+    fn maybe_consolidate(&self, node_id: NodeID, delta: &DeltaNode<K, V>) {
+        if self.needs_consolidation(delta) {
+            // A consolidation only fails when it defers a split.
+            let _ = self.consolidate(node_id);
+        }
+    }
+
+    /// Returns `true` if `delta` is longer than the consolidation threshold,
+    /// or has reached the maximum chain length.
+    fn needs_consolidation(&self, delta: &DeltaNode<K, V>) -> bool {
+        let len = delta.records.len();
+        len > self.consolidation_threshold || len >= self.max_chain_len
+    }
+
+    /// Consolidates the delta chain of `node_id` into a fresh leaf node.
+    ///
+    /// The delta records are applied newest-first on top of the base leaf, and
+    /// the resulting leaf replaces the delta chain in the mapping table. The
+    /// replaced delta chain is retired and freed once no reader can observe it.
+    /// If the resulting leaf is too large or too small, it is split or merged
+    /// into its left sibling instead, unless another structure modification
+    /// is in progress.
+    ///
+    /// The delta chain is frozen first, so that no write can slip in while
+    /// the chain is read and be lost with it. Writers that find the chain
+    /// frozen wait for its replacement and write to that instead, and only
+    /// the thread that froze the chain consolidates it.
+    ///
+    /// If the leaf has to be split but no node ID is left, it is installed
+    /// oversized and `BwError::MappingTableFull` is returned.
+    fn consolidate(&self, node_id: NodeID) -> Result<(), BwError> {
+        let _guard = self.epoch.pin();
+        let ptr = self.mapping_table.load(node_id);
+        let node = unsafe { &*ptr };
+        let delta = match node {
+            // A removed node is frozen until its ID is reused.
+            Node::Delta(_) if node.left_link().is_some() => return Ok(()),
+            Node::Delta(delta) => delta,
+            _ => return Ok(()),
+        };
+        if !delta.freeze() {
+            return Ok(());
+        }
+        // Not counting the freeze record.
+        let chain_len = delta.records.len() - 1;
+        let mut leaf = delta.consolidate(&self.order);
+        let mut result = Ok(());
+        if leaf.count > self.split_threshold {
+            if let Some(_smo) = self.try_begin_smo() {
+                match self.get_next_node_id() {
+                    Ok(right_id) => {
+                        self.split_leaf(node_id, ptr, leaf, right_id);
+                        return Ok(());
+                    }
+                    Err(err) => result = Err(err),
+                }
+            }
+        } else if leaf.count < self.merge_threshold {
+            if let Some(_smo) = self.try_begin_smo() {
+                match self.merge_leaf(node_id, ptr, delta, leaf) {
+                    Ok(()) => return Ok(()),
+                    Err(unmerged) => leaf = unmerged,
+                }
+            }
+        }
+        // Consolidation is opportunistic: if another thread replaced the
+        // node concurrently, leave it to them.
+        if self
+            .mapping_table
+            .cas(node_id, ptr, Node::Leaf(leaf))
+            .is_ok()
+        {
+            unsafe { self.epoch.defer(ptr as *mut u8, free_node::<K, V>) };
+            self.emit(SmoEvent::Consolidation {
+                node: node_id,
+                chain_len,
+            });
+        }
+        result
+    }
+
+    /// Splits `node_id`, replacing the delta chain at `ptr` with a split delta
+    /// on top of `leaf`, its consolidated contents.
+    ///
+    /// The upper half of the entries is copied into a new right sibling with
+    /// ID `right_id`, and
+    /// the split delta redirects keys at or above the separator to it. The
+    /// separator is then posted to the parent, so that later descents route
+    /// such keys directly to the right sibling.
+    fn split_leaf(
+        &self,
+        node_id: NodeID,
+        ptr: *mut Node<K, V>,
+        leaf: LeafNode<K, V>,
+        right_id: NodeID,
+    ) {
+        let mid = leaf.count / 2;
+        let separator = leaf.keys[mid].clone();
+        let right = LeafNode {
+            count: leaf.count - mid,
+            keys: leaf.keys[mid..].to_vec(),
+            values: leaf.values[mid..].to_vec(),
+            high_key: leaf.high_key.clone(),
+            right_sibling: leaf.right_sibling,
+            version: leaf.version,
+        };
+        self.mapping_table.insert(right_id, Node::Leaf(right));
+
+        let base = Box::into_raw(Box::new(Node::Leaf(leaf)));
+        let split = DeltaNode::new(unsafe { &*base });
+        split.split(separator.clone(), right_id);
+        if self
+            .mapping_table
+            .cas(node_id, ptr, Node::Delta(split))
+            .is_err()
+        {
+            // Another thread replaced the node concurrently. Nothing points to
+            // the right sibling yet, so it can be freed right away.
+            unsafe {
+                free_node::<K, V>(base as *mut u8);
+                free_node::<K, V>(self.mapping_table.remove(right_id) as *mut u8);
+            }
+            self.free_node_id(right_id);
+            return;
+        }
+        unsafe { self.epoch.defer(ptr as *mut u8, free_node::<K, V>) };
+        self.post_index_term(separator, right_id);
+        self.emit(SmoEvent::LeafSplit {
+            node: node_id,
+            new_sibling: right_id,
+        });
+    }
+
+    /// Merges `node_id` into its left sibling, replacing the delta chain at
+    /// `ptr` with a remove-node delta on top of `leaf`, its consolidated
+    /// contents.
+    ///
+    /// The remove-node delta freezes the node and redirects writers to the
+    /// left sibling. A merge delta carrying a copy of the contents is then
+    /// posted on the left sibling, which takes over the key range, and the
+    /// index term of the node is deleted from the parent. If the node has no
+    /// left sibling under the same parent, `leaf` is handed back.
+    fn merge_leaf(
+        &self,
+        node_id: NodeID,
+        ptr: *mut Node<K, V>,
+        delta: &DeltaNode<K, V>,
+        leaf: LeafNode<K, V>,
+    ) -> Result<(), LeafNode<K, V>> {
+        // Any key written to the node is in its range, and so routes to it.
+        let key = delta
+            .records
+            .iter()
+            .find_map(|record| record.key().cloned());
+        let Some(key) = key else {
+            return Err(leaf);
+        };
+        let (separator, left_id) = match self
+            .mapping_table
+            .get(self.find_parent(&key))
+            .inner(&self.order)
+        {
+            Some(parent) => {
+                let idx = parent.find_child_index(&self.order, &key);
+                if idx == 0 || parent.children[idx] != node_id {
+                    return Err(leaf);
+                }
+                (parent.keys[idx].clone(), parent.children[idx - 1])
+            }
+            None => unreachable!("parent is not an inner node"),
+        };
+
+        let merged = leaf.clone();
+        let base = Box::into_raw(Box::new(Node::Leaf(leaf)));
+        let removal = DeltaNode::new(unsafe { &*base });
+        removal.remove(left_id);
+        if self
+            .mapping_table
+            .cas(node_id, ptr, Node::Delta(removal))
+            .is_err()
+        {
+            // Another thread replaced the node concurrently.
+            unsafe { free_node::<K, V>(base as *mut u8) };
+            return Ok(());
+        }
+        unsafe { self.epoch.defer(ptr as *mut u8, free_node::<K, V>) };
+
+        loop {
+            let left_ptr = self.mapping_table.load(left_id);
+            let left = match unsafe { &*left_ptr } {
+                Node::Delta(left) => left,
+                node => {
+                    let delta = DeltaNode::new(node);
+                    let _ = self
+                        .mapping_table
+                        .cas(left_id, left_ptr, Node::Delta(delta));
+                    continue;
+                }
+            };
+            // If the left sibling is being consolidated, the merge delta is
+            // posted on the node that replaces it.
+            if left.merge(separator.clone(), merged.clone()) {
+                break;
+            }
+            std::thread::yield_now();
+        }
+
+        self.delete_index_term(&separator, node_id);
+        self.retire_node_id(node_id);
+        self.emit(SmoEvent::LeafMerge {
+            node: node_id,
+            into: left_id,
+        });
+        Ok(())
+    }
+
+    /// Deletes the index term routing keys at or above `separator` to
+    /// `node_id` from the parent of the node.
+    ///
+    /// The parent is rewritten copy-on-write, which also consolidates its
+    /// index-term deltas, and the rewrite is retried if the parent changes
+    /// concurrently.
+    fn delete_index_term(&self, separator: &K, node_id: NodeID) {
+        loop {
+            let parent_id = self.find_parent(separator);
+            let ptr = self.mapping_table.load(parent_id);
+            let mut parent = match unsafe { &*ptr }.inner(&self.order) {
+                Some(node) => node.into_owned(),
+                None => unreachable!("parent is not an inner node"),
+            };
+            let idx = parent.find_child_index(&self.order, separator);
+            assert_eq!(parent.children[idx], node_id);
+            parent.keys.remove(idx);
+            parent.children.remove(idx);
+            if self
+                .mapping_table
+                .cas(parent_id, ptr, Node::Inner(parent))
+                .is_ok()
+            {
+                unsafe { self.epoch.defer(ptr as *mut u8, free_node::<K, V>) };
+                return;
+            }
+        }
+    }
+
+    /// Posts an index term routing keys at or above `separator` to `node_id`
+    /// to the parent of the node that was split.
+    ///
+    /// The index term is prepended to the index-term delta chain of the
+    /// parent, which is chained on top of it first if the parent is still a
+    /// bare inner node. Once the chain grows beyond the consolidation
+    /// threshold, it is consolidated into a fresh inner node. Index terms are
+    /// only posted and consolidated during a structure modification, so no
+    /// index term is lost to a concurrent consolidation.
+    fn post_index_term(&self, separator: K, node_id: NodeID) {
+        let parent_id = self.find_parent(&separator);
+        self.post_index_term_to(parent_id, separator, node_id);
+    }
+
+    /// Posts an index term routing keys at or above `separator` to `node_id`
+    /// to the inner node `parent_id`, and splits the parent if it ends up
+    /// with more children than the split threshold.
+    fn post_index_term_to(&self, parent_id: NodeID, separator: K, node_id: NodeID) {
+        loop {
+            let ptr = self.mapping_table.load(parent_id);
+            let node = unsafe { &*ptr };
+            match node {
+                Node::IndexDelta(delta) => {
+                    delta.post(separator, node_id);
+                    if delta.terms.len() > self.consolidation_threshold {
+                        self.consolidate_inner(parent_id, ptr, delta);
+                    }
+                    break;
+                }
+                Node::Inner(_) => {
+                    let delta = IndexDeltaNode::new(node);
+                    let _ = self
+                        .mapping_table
+                        .cas(parent_id, ptr, Node::IndexDelta(delta));
+                }
+                _ => unreachable!("parent is not an inner node"),
+            }
+        }
+        let children = match self.mapping_table.get(parent_id).inner(&self.order) {
+            Some(parent) => parent.children.len(),
+            None => unreachable!("parent is not an inner node"),
+        };
+        if children > self.inner_split_threshold {
+            self.split_inner(parent_id);
+        }
+    }
+
+    /// Splits the inner node `node_id`, moving the upper half of its children
+    /// to a new right sibling.
+    ///
+    /// The separator is posted to the parent of the node, or to a new root
+    /// above it if the node is the root, before the node is shrunk to its
+    /// lower half, so that the upper half stays reachable throughout. A
+    /// descent that read the parent before the post but reaches the node
+    /// after it lands on the last child of the lower half, left of its
+    /// target, and follows the right siblings of the leaf level from there.
+    /// The node is left oversized if the mapping table is full.
+    fn split_inner(&self, node_id: NodeID) {
+        let parent_id = self.find_parent_of(node_id);
+        let Ok(right_id) = self.get_next_node_id() else {
+            return;
+        };
+        let root_id = match parent_id {
+            Some(_) => None,
+            None => match self.get_next_node_id() {
+                Ok(root_id) => Some(root_id),
+                Err(_) => {
+                    self.free_node_id(right_id);
+                    return;
+                }
+            },
+        };
+        let ptr = self.mapping_table.load(node_id);
+        let mut left = match unsafe { &*ptr }.inner(&self.order) {
+            Some(node) => node.into_owned(),
+            None => unreachable!("split node is not an inner node"),
+        };
+        let mid = left.children.len() / 2;
+        let right = InnerNode {
+            keys: left.keys.split_off(mid),
+            children: left.children.split_off(mid),
+        };
+        let separator = right.keys[0].clone();
+        self.mapping_table.insert(right_id, Node::Inner(right));
+        match (parent_id, root_id) {
+            (Some(parent_id), _) => self.post_index_term_to(parent_id, separator, right_id),
+            (None, Some(root_id)) => self.split_root(node_id, root_id, separator, right_id),
+            (None, None) => unreachable!("split of the root without a new root"),
+        }
+        // Inner nodes only change during a structure modification, so the
+        // node is still the one that was split.
+        let shrunk = self.mapping_table.cas(node_id, ptr, Node::Inner(left));
+        assert!(shrunk.is_ok(), "inner node changed during its split");
+        unsafe { self.epoch.defer(ptr as *mut u8, free_node::<K, V>) };
+        self.emit(match root_id {
+            Some(new_root) => SmoEvent::RootSplit {
+                old_root: node_id,
+                new_sibling: right_id,
+                new_root,
+            },
+            None => SmoEvent::InnerSplit {
+                node: node_id,
+                new_sibling: right_id,
+            },
+        });
+    }
+
+    /// Grows the tree by one level, installing the new root `root_id` above
+    /// the old root `node_id` and its new right sibling `right_id`, which
+    /// takes over the keys at or above `separator`.
+    ///
+    /// Descents that loaded the old root ID keep descending from the old
+    /// root, which still covers every key until it is shrunk.
+    fn split_root(&self, node_id: NodeID, root_id: NodeID, separator: K, right_id: NodeID) {
+        let mut root = InnerNode::new();
+        root.insert(&self.order, self.order.minimum(), node_id);
+        root.insert(&self.order, separator, right_id);
+        self.mapping_table.insert(root_id, Node::Inner(root));
+        let published =
+            self.root_id
+                .compare_exchange(node_id, root_id, Ordering::AcqRel, Ordering::Relaxed);
+        assert!(published.is_ok(), "root changed during its split");
+    }
+
+    /// Returns the ID of the inner node that points to the inner node
+    /// `node_id`, or `None` if it is the root.
+    ///
+    /// The parent is found by descending from the root towards the lowest key
+    /// of the node, which is only stable during a structure modification.
+    fn find_parent_of(&self, node_id: NodeID) -> Option<NodeID> {
+        let low = match self.mapping_table.get(node_id).inner(&self.order) {
+            Some(node) => node.keys[0].clone(),
+            None => unreachable!("node is not an inner node"),
+        };
+        let mut parent_id = self.root_id();
+        if parent_id == node_id {
+            return None;
+        }
+        loop {
+            match self
+                .mapping_table
+                .get(parent_id)
+                .find_child(&self.order, &low)
+            {
+                Some(child) if child == node_id => return Some(parent_id),
+                Some(child) => parent_id = child,
+                None => unreachable!("inner node {node_id} is not reachable"),
+            }
+        }
+    }
+
+    /// Consolidates the index-term delta chain at `ptr`, which `node_id` is
+    /// mapped to, into a fresh inner node.
+    fn consolidate_inner(
+        &self,
+        node_id: NodeID,
+        ptr: *mut Node<K, V>,
+        delta: &IndexDeltaNode<K, V>,
+    ) {
+        let node = Node::Inner(delta.consolidate(&self.order));
+        if self.mapping_table.cas(node_id, ptr, node).is_ok() {
+            unsafe { self.epoch.defer(ptr as *mut u8, free_node::<K, V>) };
+        }
+    }
+}
+
+/// Copies the live entries into a new tree with the same thresholds, which
+/// shares no nodes with the original.
+///
+/// The entries are bulk-loaded like in `BwTree::from_sorted`, so the copy is
+/// fully consolidated whatever the shape of the original. Like `iter`, the
+/// copy is not a snapshot if the original is written concurrently, and the
+/// callback registered with `BwTree::on_smo` is not copied.
+impl<K, V> Clone for BwTree<K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    fn clone(&self) -> Self {
+        Self::load_sorted(self.empty_copy(), self.iter()).expect("range scan out of order")
+    }
+}
+
+/// Builds a tree from unsorted entries with the default thresholds.
+///
+/// The entries are sorted and bulk-loaded like in `BwTree::from_sorted`. If a
+/// key occurs more than once, its value that comes last wins, like with
+/// `BwTree::insert_batch_unsorted`.
+impl<K, V> FromIterator<(K, V)> for BwTree<K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut items: Vec<_> = iter.into_iter().collect();
+        // The sort is stable, so after reversing, the last value of every key
+        // is the first one of its run, which is the one that dedup keeps.
+        items.reverse();
+        items.sort_by(|a, b| a.0.cmp(&b.0));
+        items.dedup_by(|a, b| a.0 == b.0);
+        Self::from_sorted(items).expect("entries are sorted and deduplicated")
+    }
+}
+
+/// Prints the live entries in ascending key order, followed by the statistics
+/// of the tree.
+///
+/// At most 32 entries are printed, unless a precision is given, as in
+/// `{:.100?}`, which sets the maximum number of entries instead.
+impl<K, V> Debug for BwTree<K, V>
+where
+    K: KeyType + Debug,
+    V: Clone + Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        struct Entries<'a, K, V>(&'a BwTree<K, V>, usize)
+        where
+            K: KeyType + Debug,
+            V: Clone;
+
+        impl<K, V> Debug for Entries<'_, K, V>
+        where
+            K: KeyType + Debug,
+            V: Clone + Debug,
+        {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                let Entries(tree, limit) = *self;
+                let mut map = f.debug_map();
+                let mut entries = tree.iter();
+                for (key, value) in entries.by_ref().take(limit) {
+                    map.entry(&key, &value);
+                }
+                if entries.next().is_some() {
+                    return map.finish_non_exhaustive();
+                }
+                map.finish()
+            }
+        }
+
+        let limit = f.precision().unwrap_or(DEFAULT_DEBUG_ENTRIES);
+        f.debug_struct("BwTree")
+            .field("len", &self.len())
+            .field("entries", &Entries(self, limit))
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+impl<V> BwTree<Vec<u8>, V>
+where
+    V: Clone,
+{
+    /// Returns an iterator over the entries whose keys start with `prefix`,
+    /// in ascending key order.
+    pub fn prefix_scan(&self, prefix: &[u8]) -> RangeIter<'_, Vec<u8>, V> {
+        let end = prefix_successor(prefix).map_or(Bound::Unbounded, Bound::Excluded);
+        RangeIter::new(self, Bound::Included(prefix.to_vec()), end)
+    }
+}
+
+impl<K> BwTree<K, Arc<[u8]>>
+where
+    K: KeyType + Debug,
+{
+    /// Inserts `value` for `key`, returning the previous value of `key`, or
+    /// `None` if the key was not present.
+    ///
+    /// The bytes are copied into a shared buffer, unless `value` already is
+    /// one.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `insert`.
+    pub fn insert_bytes(
+        &self,
+        key: K,
+        value: impl Into<Arc<[u8]>>,
+    ) -> Result<Option<Arc<[u8]>>, BwError> {
+        self.insert(key, value.into())
+    }
+
+    /// Returns the value of `key`, sharing its buffer instead of copying it.
+    pub fn get_bytes(&self, key: K) -> Option<Arc<[u8]>> {
+        self.get_owned(key)
+    }
+}
+
+impl<K, V> BwTree<K, V>
+where
+    K: KeyType + Debug + Copy + Into<i128>,
+    V: Clone,
+{
+    /// Returns the number of live keys in each of `buckets` equal intervals
+    /// between the smallest and the largest live key, in ascending order.
+    ///
+    /// The keys are counted by scanning the whole tree. Every bucket is empty
+    /// if the tree is, and the lengths of the intervals differ by at most one
+    /// if the number of buckets doesn't divide the span of the keys.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buckets` is zero.
+    pub fn key_histogram(&self, buckets: usize) -> Vec<usize> {
+        assert!(buckets > 0, "bucket count must be positive");
+        let mut histogram = vec![0; buckets];
+        let (Some((first, _)), Some((last, _))) = (self.first_key_value(), self.last_key_value())
+        else {
+            return histogram;
+        };
+        let (first, last) = (first.into(), last.into());
+        // The span of a 64-bit key type fits in a `u128`, and so does its
+        // product with the number of buckets.
+        let span = last.abs_diff(first) + 1;
+        for key in self.keys() {
+            // Keys inserted during the scan may be out of the initial bounds.
+            let offset = (key.into() - first).max(0) as u128;
+            let bucket = (offset * buckets as u128 / span).min(buckets as u128 - 1);
+            histogram[bucket as usize] += 1;
+        }
+        histogram
+    }
+}
+
+/// Returns the smallest key greater than every key that starts with `prefix`,
+/// or `None` if there is no such key because the prefix is all `0xFF` bytes.
+fn prefix_successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let last = prefix.iter().rposition(|&byte| byte != 0xFF)?;
+    let mut successor = prefix[..=last].to_vec();
+    successor[last] += 1;
+    Some(successor)
+}
+
+/// The right to perform a structure modification, released when dropped.
+struct SmoGuard<'a> {
+    flag: &'a AtomicBool,
+}
+
+impl Drop for SmoGuard<'_> {
+    fn drop(&mut self) {
+        self.flag.store(false, Ordering::Release);
+    }
+}
+
+/// The number of entries in the first mapping table segment.
+const FIRST_SEGMENT_SIZE: usize = 1 << 6;
+
+/// The number of mapping table segments. Every segment is twice the size of
+/// the previous one, so together they can address every `usize` node ID.
+const SEGMENT_COUNT: usize = (usize::BITS - FIRST_SEGMENT_SIZE.trailing_zeros()) as usize;
+
+/// The largest node ID the mapping table can address.
+const MAX_NODE_ID: NodeID = usize::MAX - FIRST_SEGMENT_SIZE;
+
+/// A mapping table entry.
+type Slot<K, V> = AtomicPtr<Node<K, V>>;
+
+/// The ID of a leaf-level node, along with its delta chain.
+type LeafDelta<'a, K, V> = (NodeID, &'a DeltaNode<K, V>);
+
+/// Mapping from logical node IDs to physical pointers.
+///
+/// The table is split into segments that are allocated on first use, so that
+/// it starts small and grows with the tree. Segment `i` holds
+/// `FIRST_SEGMENT_SIZE << i` entries, and a segment is published with a
+/// compare-and-swap on its slot, so growing the table doesn't need a lock.
+pub struct MappingTable<K: Ord, V: Clone> {
+    /// Pointers to the first entry of every allocated segment.
+    segments: Box<[AtomicPtr<Slot<K, V>>]>,
+}
+
+impl<K: Ord, V: Clone> MappingTable<K, V> {
+    pub fn new() -> Self {
+        let mut segments = Vec::default();
+        segments.resize_with(SEGMENT_COUNT, AtomicPtr::default);
+        MappingTable {
+            segments: segments.into_boxed_slice(),
+        }
+    }
+
+    fn get(&self, id: usize) -> &Node<K, V> {
+        self.try_get(id).expect("dangling node ID")
+    }
+
+    /// Returns the node `id` is mapped to, or an error if `id` is beyond the
+    /// IDs the table can address or not mapped.
+    fn try_get(&self, id: NodeID) -> Result<&Node<K, V>, MappingError> {
+        if id > MAX_NODE_ID {
+            return Err(MappingError::OutOfRange(id));
+        }
+        unsafe { self.load(id).as_ref() }.ok_or(MappingError::Unmapped(id))
+    }
+
+    /// Returns the pointer `id` is currently mapped to.
+    fn load(&self, id: NodeID) -> *mut Node<K, V> {
+        let (segment, offset) = Self::locate(id);
+        let entries = self.segments[segment].load(Ordering::Acquire);
+        if entries.is_null() {
+            return std::ptr::null_mut();
+        }
+        unsafe { (*entries.add(offset)).load(Ordering::Acquire) }
+    }
+
+    /// Maps `id` to `node`, returning the pointer it was previously mapped to,
+    /// or `None` if the entry changed concurrently.
+    ///
+    /// The caller is responsible for retiring the returned pointer unless the
+    /// new node still references it.
+    fn insert(&self, id: usize, node: Node<K, V>) -> Option<*mut Node<K, V>> {
+        let old = self.load(id);
+        self.cas(id, old, node).ok().map(|_| old)
+    }
+
+    /// Maps `id` to `new` only if it is still mapped to `expected`.
+    ///
+    /// On failure, `new` is dropped and the pointer `id` is currently mapped
+    /// to is returned, so that the caller can retry against it. On success,
+    /// the caller is responsible for retiring `expected` unless the new node
+    /// still references it.
+    fn cas(
+        &self,
+        id: NodeID,
+        expected: *mut Node<K, V>,
+        new: Node<K, V>,
+    ) -> Result<(), *mut Node<K, V>> {
+        let new = Box::into_raw(Box::new(new));
+        match self
+            .entry(id)
+            .compare_exchange(expected, new, Ordering::SeqCst, Ordering::SeqCst)
+        {
+            Ok(_) => Ok(()),
+            Err(actual) => {
+                std::mem::drop(unsafe { Box::from_raw(new) });
+                Err(actual)
+            }
+        }
+    }
+
+    /// Maps `id` to `node` regardless of what it is mapped to, returning the
+    /// pointer it was previously mapped to.
+    ///
+    /// The caller is responsible for retiring the returned pointer.
+    fn swap(&self, id: NodeID, node: Node<K, V>) -> *mut Node<K, V> {
+        let node = Box::into_raw(Box::new(node));
+        self.entry(id).swap(node, Ordering::SeqCst)
+    }
+
+    /// Unmaps `id`, returning the pointer it was mapped to.
+    ///
+    /// The caller is responsible for retiring the returned pointer.
+    fn remove(&self, id: NodeID) -> *mut Node<K, V> {
+        self.entry(id).swap(std::ptr::null_mut(), Ordering::SeqCst)
+    }
+
+    /// Returns an estimate of the heap memory used by the table and the nodes
+    /// mapped in it.
+    fn memory_bytes(&self) -> usize
+    where
+        K: KeyType,
+    {
+        let mut bytes = self.segments.len() * std::mem::size_of::<AtomicPtr<Slot<K, V>>>();
+        for (segment, slot) in self.segments.iter().enumerate() {
+            let entries = slot.load(Ordering::Acquire);
+            if entries.is_null() {
+                continue;
+            }
+            bytes += Self::segment_size(segment) * std::mem::size_of::<Slot<K, V>>();
+            for offset in 0..Self::segment_size(segment) {
+                let node = unsafe { (*entries.add(offset)).load(Ordering::Acquire) };
+                if let Some(node) = unsafe { node.as_ref() } {
+                    bytes += node.memory_bytes();
+                }
+            }
+        }
+        bytes
+    }
+
+    /// Returns the entry for `id`, allocating its segment if needed.
+    fn entry(&self, id: NodeID) -> &Slot<K, V> {
+        let (segment, offset) = Self::locate(id);
+        let slot = &self.segments[segment];
+        let mut entries = slot.load(Ordering::Acquire);
+        if entries.is_null() {
+            let new = Self::alloc_segment(segment);
+            entries = match slot.compare_exchange(
+                std::ptr::null_mut(),
+                new,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => new,
+                Err(winner) => {
+                    // Another thread published the segment first.
+                    unsafe { Self::free_segment(new, segment) };
+                    winner
+                }
+            };
+        }
+        unsafe { &*entries.add(offset) }
+    }
+
+    /// Returns the segment holding `id` and the offset of `id` in it.
+    fn locate(id: NodeID) -> (usize, usize) {
+        let n = id
+            .checked_add(FIRST_SEGMENT_SIZE)
+            .expect("node ID out of range");
+        let segment = (n.ilog2() - FIRST_SEGMENT_SIZE.ilog2()) as usize;
+        (segment, n - (FIRST_SEGMENT_SIZE << segment))
+    }
+
+    fn segment_size(segment: usize) -> usize {
+        FIRST_SEGMENT_SIZE << segment
+    }
+
+    /// Allocates segment `segment` zeroed, which leaves the placement of its
+    /// pages to the threads that first write to them.
+    #[cfg(all(feature = "numa", not(loom)))]
+    fn alloc_segment(segment: usize) -> *mut Slot<K, V> {
+        let layout = std::alloc::Layout::array::<Slot<K, V>>(Self::segment_size(segment))
+            .expect("segment too large");
+        // A null pointer is all zeroes.
+        let entries = unsafe { std::alloc::alloc_zeroed(layout) };
+        if entries.is_null() {
+            std::alloc::handle_alloc_error(layout);
+        }
+        entries.cast()
+    }
+
+    #[cfg(not(all(feature = "numa", not(loom))))]
+    fn alloc_segment(segment: usize) -> *mut Slot<K, V> {
+        let entries: Box<[Slot<K, V>]> = (0..Self::segment_size(segment))
+            .map(|_| AtomicPtr::default())
+            .collect();
+        Box::into_raw(entries) as *mut Slot<K, V>
+    }
+
+    unsafe fn free_segment(entries: *mut Slot<K, V>, segment: usize) {
+        let entries = std::ptr::slice_from_raw_parts_mut(entries, Self::segment_size(segment));
+        drop(Box::from_raw(entries));
+    }
+}
+
+/// The error returned when a node ID does not lead to a node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum MappingError {
+    /// The ID is beyond the IDs the mapping table can address.
+    OutOfRange(NodeID),
+    /// The ID is not mapped to a node.
+    Unmapped(NodeID),
+}
+
+impl From<MappingError> for BwError {
+    fn from(err: MappingError) -> Self {
+        match err {
+            MappingError::OutOfRange(id) | MappingError::Unmapped(id) => BwError::DanglingNode(id),
+        }
+    }
+}
+
+impl<K: Ord, V: Clone> Default for MappingTable<K, V> {
+    fn default() -> Self {
+        MappingTable::new()
+    }
+}
+
+impl<K: Ord, V: Clone> Drop for MappingTable<K, V> {
+    fn drop(&mut self) {
+        for (segment, slot) in self.segments.iter_mut().enumerate() {
+            let entries = slot.load(Ordering::Acquire);
+            if entries.is_null() {
+                continue;
+            }
+            for offset in 0..Self::segment_size(segment) {
+                let node = unsafe { (*entries.add(offset)).load(Ordering::Acquire) };
+                if !node.is_null() {
+                    unsafe { free_node::<K, V>(node as *mut u8) };
+                }
+            }
+            unsafe { Self::free_segment(entries, segment) };
+        }
+    }
+}
+
+/// Frees a node that is no longer mapped, along with the base node of a delta
+/// chain.
+unsafe fn free_node<K, V>(ptr: *mut u8) {
+    let node = Box::from_raw(ptr as *mut Node<K, V>);
+    match &*node {
+        Node::Delta(delta) => free_node::<K, V>(delta.base as *mut u8),
+        Node::IndexDelta(delta) => free_node::<K, V>(delta.base as *mut u8),
+        _ => {}
+    }
+}
+
+#[derive(Debug)]
+enum Node<K, V> {
+    Inner(InnerNode<K>),
+    IndexDelta(IndexDeltaNode<K, V>),
+    Delta(DeltaNode<K, V>),
+    Leaf(LeafNode<K, V>),
+}
+
+impl<K, V> Node<K, V>
+where
+    K: KeyType,
+{
+    /// Returns an estimate of the heap memory owned by the node: its own
+    /// allocation, its vectors, and for a delta chain, the records and the
+    /// base node.
+    fn memory_bytes(&self) -> usize {
+        let owned = match self {
+            Node::Inner(inner) => inner.memory_bytes(),
+            Node::IndexDelta(delta) => {
+                delta.terms.memory_bytes() + unsafe { &*delta.base }.memory_bytes()
+            }
+            Node::Delta(delta) => {
+                let merged: usize = delta
+                    .records
+                    .iter()
+                    .map(|record| match record {
+                        DeltaRecord::Merge(_, merged) => merged.memory_bytes(),
+                        _ => 0,
+                    })
+                    .sum();
+                delta.records.memory_bytes() + merged + unsafe { &*delta.base }.memory_bytes()
+            }
+            Node::Leaf(leaf) => leaf.memory_bytes(),
+        };
+        std::mem::size_of::<Self>() + owned
+    }
+
+    /// Returns the version of a leaf-level node.
+    fn version(&self) -> u64
+    where
+        V: Clone,
+    {
+        match self {
+            Node::Delta(delta) => delta.version(),
+            Node::Leaf(leaf) => leaf.version,
+            Node::Inner(_) | Node::IndexDelta(_) => unreachable!("version of an inner node"),
+        }
+    }
+
+    /// Looks up `key`, descending through inner nodes via the mapping table
+    /// until a delta chain or a leaf node is reached.
+    fn get<'a>(
+        &'a self,
+        order: &KeyOrder<K>,
+        key: &K,
+        mapping_table: &'a MappingTable<K, V>,
+    ) -> Result<Option<&'a V>, MappingError>
+    where
+        V: Clone,
+    {
+        match self {
+            Node::Inner(_) | Node::IndexDelta(_) => {
+                let Some(child) = self.find_child(order, key) else {
+                    return Ok(None);
+                };
+                mapping_table.try_get(child)?.get(order, key, mapping_table)
+            }
+            Node::Delta(node) => node.get(order, key, mapping_table),
+            Node::Leaf(node) => node.get(order, key, mapping_table),
+        }
+    }
+
+    /// Returns `true` for a node at an inner level of the tree.
+    fn is_inner(&self) -> bool {
+        matches!(self, Node::Inner(_) | Node::IndexDelta(_))
+    }
+
+    /// Returns the contents of an inner-level node with its index-term deltas
+    /// applied, or `None` for a leaf-level node.
+    fn inner(&self, order: &KeyOrder<K>) -> Option<Cow<'_, InnerNode<K>>> {
+        match self {
+            Node::Inner(node) => Some(Cow::Borrowed(node)),
+            Node::IndexDelta(node) => Some(Cow::Owned(node.consolidate(order))),
+            _ => None,
+        }
+    }
+
+    /// Returns the child of an inner-level node whose key range covers `key`,
+    /// or `None` for a leaf-level node.
+    fn find_child(&self, order: &KeyOrder<K>, key: &K) -> Option<NodeID> {
+        self.route(order, key).map(|(child, _)| child)
+    }
+
+    /// Returns the child of an inner-level node whose key range covers `key`,
+    /// along with the exclusive upper bound of that range, if the child is
+    /// not the last one.
+    fn route(&self, order: &KeyOrder<K>, key: &K) -> Option<(NodeID, Option<&K>)> {
+        match self {
+            Node::Inner(node) => {
+                let idx = node.find_child_index(order, key);
+                Some((node.children[idx], node.keys.get(idx + 1)))
+            }
+            Node::IndexDelta(node) => Some(node.route(order, key)),
+            _ => None,
+        }
+    }
+
+    /// Returns the child of an inner-level node that covers the keys right
+    /// below `bound`, or the last child if there is no bound, along with the
+    /// lower bound of its key range.
+    fn route_before(&self, order: &KeyOrder<K>, bound: Option<&K>) -> Option<(NodeID, &K)> {
+        match self {
+            Node::Inner(node) => Some(node.route_before(order, bound)),
+            Node::IndexDelta(node) => Some(node.route_before(order, bound)),
+            _ => None,
+        }
+    }
+
+    /// Returns the exclusive upper bound of a leaf-level node's key range and
+    /// the right sibling that holds the keys above it, if the node was split.
+    fn split(&self) -> Option<(&K, NodeID)> {
+        match self {
+            Node::Inner(_) | Node::IndexDelta(_) => unreachable!("inner node at leaf level"),
+            Node::Delta(node) => DeltaNode::split_records(node.records.iter(), node.base()),
+            Node::Leaf(node) => node.split(),
+        }
+    }
+
+    /// Returns the left sibling a leaf-level node was merged into, if any.
+    fn left_link(&self) -> Option<NodeID> {
+        match self {
+            Node::Delta(node) => node.records.iter().find_map(|record| match record {
+                DeltaRecord::Remove(left) => Some(*left),
+                _ => None,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Returns the right sibling of a leaf-level node if `key` is beyond the
+    /// node's key range.
+    fn right_link(&self, order: &KeyOrder<K>, key: &K) -> Option<NodeID> {
+        self.split()
+            .filter(|(high_key, _)| order.le(high_key, key))
+            .map(|(_, right)| right)
+    }
+
+    /// Returns the number of live entries of a leaf-level node with keys in
+    /// `start..end`, counting no further than `max`.
+    fn count_range(&self, order: &KeyOrder<K>, start: &K, end: Option<&K>, max: usize) -> usize
+    where
+        V: Clone,
+    {
+        match self {
+            Node::Inner(_) | Node::IndexDelta(_) => unreachable!("inner node at leaf level"),
+            Node::Delta(node) => node.count_range(order, start, end, max),
+            Node::Leaf(node) => node.range(order, start, end).len().min(max),
+        }
+    }
+
+    /// Folds the live entries of a leaf-level node with keys in `start..end`
+    /// into `acc` with `f`, in ascending key order, without copying them.
+    fn fold_range<B>(
+        &self,
+        order: &KeyOrder<K>,
+        start: &K,
+        end: Option<&K>,
+        acc: B,
+        f: impl Fn(B, &K, &V) -> B,
+    ) -> B
+    where
+        V: Clone,
+    {
+        let in_range = |k: &K| order.le(start, k) && end.is_none_or(|end| order.lt(k, end));
+        match self {
+            Node::Inner(_) | Node::IndexDelta(_) => unreachable!("inner node at leaf level"),
+            Node::Delta(node) => DeltaNode::live_entries(order, node.records.iter(), node.base())
+                .skip_while(|(k, _)| !in_range(k))
+                .take_while(|(k, _)| in_range(k))
+                .fold(acc, |acc, (k, v)| f(acc, k, v)),
+            Node::Leaf(node) => {
+                let range = node.range(order, start, end);
+                node.keys[range.clone()]
+                    .iter()
+                    .zip(&node.values[range])
+                    .fold(acc, |acc, (k, v)| f(acc, k, v))
+            }
+        }
+    }
+
+    /// Returns the live entries of a leaf-level node in ascending key order.
+    fn entries(&self, order: &KeyOrder<K>) -> Vec<(K, V)>
+    where
+        V: Clone,
+    {
+        let leaf = match self {
+            Node::Inner(_) | Node::IndexDelta(_) => unreachable!("inner node at leaf level"),
+            Node::Delta(node) => node.consolidate(order),
+            Node::Leaf(node) => {
+                return node
+                    .keys
+                    .iter()
+                    .cloned()
+                    .zip(node.values.iter().cloned())
+                    .collect()
+            }
+        };
+        leaf.keys.into_iter().zip(leaf.values).collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+struct InnerNode<K> {
+    /// The key ranges stored in the children.
+    keys: Vec<K>,
+    /// Pointers to the children.
+    children: Vec<NodeID>,
+}
+
+impl<K> InnerNode<K> {
+    fn new() -> Self {
+        InnerNode {
+            keys: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    /// Adds `node_id` as the child covering the keys from `key` up to the
+    /// next separator, keeping the separators sorted.
+    fn insert(&mut self, order: &KeyOrder<K>, key: K, node_id: NodeID)
+    where
+        K: KeyType,
+    {
+        let idx = order.lower_bound(&self.keys, &key);
+        self.keys.insert(idx, key);
+        self.children.insert(idx, node_id);
+    }
+
+    /// Returns the number of bytes allocated for the vectors of the node.
+    fn memory_bytes(&self) -> usize {
+        self.keys.capacity() * std::mem::size_of::<K>()
+            + self.children.capacity() * std::mem::size_of::<NodeID>()
+    }
+
+    /// Returns the index of the child whose key range covers `key`.
+    ///
+    /// Each separator in `keys` is the lower bound of the corresponding child,
+    /// so the child is the one with the greatest separator less than or equal
+    /// to `key`.
+    fn find_child_index(&self, order: &KeyOrder<K>, key: &K) -> usize
+    where
+        K: KeyType,
+    {
+        match order.binary_search(&self.keys, key) {
+            Ok(idx) => idx,
+            Err(0) => 0,
+            Err(idx) => idx - 1,
+        }
+    }
+
+    /// Returns the child that covers the keys right below `bound`, or the
+    /// last child if there is no bound, along with its separator.
+    fn route_before(&self, order: &KeyOrder<K>, bound: Option<&K>) -> (NodeID, &K)
+    where
+        K: KeyType,
+    {
+        let idx = match bound {
+            Some(bound) => order.lower_bound(&self.keys, bound).saturating_sub(1),
+            None => self.children.len() - 1,
+        };
+        (self.children[idx], &self.keys[idx])
+    }
+}
+
+/// A chain of index-term deltas layered on top of an inner node.
+///
+/// Each record publishes a new separator of the node, so that a split can be
+/// posted to the parent without rewriting it. Routing consults the records
+/// before the base node, whose keys and children are never modified.
+#[derive(Debug)]
+struct IndexDeltaNode<K, V> {
+    terms: LinkedList<IndexTerm<K>>,
+    /// The inner node the delta chain is layered on top of.
+    base: *const Node<K, V>,
+}
+
+/// Routes the keys from `separator` up to the next separator of the node to
+/// `child`.
+#[derive(Debug)]
+struct IndexTerm<K> {
+    separator: K,
+    child: NodeID,
+}
+
+impl<K, V> IndexDeltaNode<K, V>
+where
+    K: KeyType,
+{
+    fn new(base: &Node<K, V>) -> Self {
+        debug_assert!(matches!(base, Node::Inner(_)));
+        IndexDeltaNode {
+            terms: LinkedList::new(),
+            base,
+        }
+    }
+
+    fn base(&self) -> &InnerNode<K> {
+        match unsafe { &*self.base } {
+            Node::Inner(node) => node,
+            _ => unreachable!("index-term delta on top of a leaf-level node"),
+        }
+    }
+
+    /// Prepends an index term routing keys at or above `separator` to
+    /// `child`.
+    fn post(&self, separator: K, child: NodeID) {
+        self.terms.push_front(IndexTerm { separator, child });
+    }
+
+    /// Returns the child whose key range covers `key`, along with the
+    /// exclusive upper bound of that range, if the child is not the last one.
+    ///
+    /// The child is the one with the greatest separator less than or equal to
+    /// `key`, among the separators of the base node and the index terms.
+    fn route(&self, order: &KeyOrder<K>, key: &K) -> (NodeID, Option<&K>) {
+        let base = self.base();
+        let idx = base.find_child_index(order, key);
+        let mut low = &base.keys[idx];
+        let mut child = base.children[idx];
+        let mut high = base.keys.get(idx + 1);
+        for term in self.terms.iter() {
+            if order.le(&term.separator, key) {
+                if order.lt(low, &term.separator) {
+                    (low, child) = (&term.separator, term.child);
+                }
+            } else if high.is_none_or(|high| order.lt(&term.separator, high)) {
+                high = Some(&term.separator);
+            }
+        }
+        (child, high)
+    }
+
+    /// Returns the child that covers the keys right below `bound`, or the
+    /// last child if there is no bound, along with its separator.
+    fn route_before(&self, order: &KeyOrder<K>, bound: Option<&K>) -> (NodeID, &K) {
+        let (mut child, mut low) = self.base().route_before(order, bound);
+        for term in self.terms.iter() {
+            if order.lt(low, &term.separator)
+                && bound.is_none_or(|bound| order.lt(&term.separator, bound))
+            {
+                (child, low) = (term.child, &term.separator);
+            }
+        }
+        (child, low)
+    }
+
+    /// Returns a copy of the base node with the index terms applied.
+    fn consolidate(&self, order: &KeyOrder<K>) -> InnerNode<K> {
+        let mut node = self.base().clone();
+        for term in self.terms.iter() {
+            let idx = order.lower_bound(&node.keys, &term.separator);
+            node.keys.insert(idx, term.separator.clone());
+            node.children.insert(idx, term.child);
+        }
+        node
+    }
+}
+
+#[derive(Debug)]
+struct DeltaNode<K, V> {
+    records: LinkedList<DeltaRecord<K, V>>,
+    /// The node the delta chain is layered on top of.
+    base: *const Node<K, V>,
+}
+
+impl<K, V> DeltaNode<K, V>
+where
+    K: KeyType,
+{
+    fn base(&self) -> &LeafNode<K, V> {
+        match unsafe { &*self.base } {
+            Node::Leaf(leaf) => leaf,
+            _ => unreachable!("delta chain base is not a leaf"),
+        }
+    }
+
+    /// Returns the exclusive upper bound of the node's key range and its
+    /// right sibling as of `records`, the contents of the delta chain at some
+    /// point in time, if the node was split.
+    fn split_records<'a>(
+        records: linked_list::Iter<'a, DeltaRecord<K, V>>,
+        base: &'a LeafNode<K, V>,
+    ) -> Option<(&'a K, NodeID)> {
+        for record in records {
+            match record {
+                DeltaRecord::Split(separator, right) => return Some((separator, *right)),
+                DeltaRecord::Merge(_, merged) => return merged.split(),
+                _ => {}
+            }
+        }
+        base.split()
+    }
+}
+
+impl<K, V> DeltaNode<K, V>
+where
+    K: KeyType,
+    V: Clone,
+{
+    /// Creates an empty delta chain on top of `base`.
+    ///
+    /// The records of small values without drop glue, which includes all
+    /// small `Copy` values, are packed into blocks, so that a write only
+    /// allocates whenever a block fills up. Larger values are left to box
+    /// their records, so as not to leave much memory unused in the blocks of
+    /// short chains.
+    fn new(base: &Node<K, V>) -> Self {
+        let records =
+            if !std::mem::needs_drop::<V>() && std::mem::size_of::<V>() <= MAX_PACKED_VALUE_SIZE {
+                LinkedList::packed()
+            } else {
+                LinkedList::new()
+            };
+        DeltaNode { records, base }
+    }
+
+    fn split(&self, separator: K, right: NodeID) {
+        self.records
+            .push_front(DeltaRecord::Split(separator, right));
+    }
+
+    /// Prepends a merge record unless the delta chain is frozen, and returns
+    /// whether it did.
+    fn merge(&self, separator: K, merged: LeafNode<K, V>) -> bool {
+        self.records
+            .push_front_if(DeltaRecord::Merge(separator, merged), |_, records| {
+                !Self::frozen(records)
+            })
+            .is_ok()
+    }
+
+    fn remove(&self, left: NodeID) {
+        self.records.push_front(DeltaRecord::Remove(left));
+    }
+
+    /// Freezes the delta chain for consolidation, so that no record can be
+    /// prepended to it anymore, and returns whether this call froze it.
+    fn freeze(&self) -> bool {
+        self.records
+            .push_front_if(DeltaRecord::Freeze, |_, records| !Self::frozen(records))
+            .is_ok()
+    }
+
+    /// Returns `true` if `records`, the contents of a delta chain, start
+    /// with a freeze record.
+    fn frozen(mut records: linked_list::Iter<'_, DeltaRecord<K, V>>) -> bool {
+        matches!(records.next(), Some(DeltaRecord::Freeze))
+    }
+
+    fn is_frozen(&self) -> bool {
+        Self::frozen(self.records.iter())
+    }
+
+    /// Prepends an insert record for `key` unless the key has a live entry,
+    /// in which case a copy of its value is returned.
+    ///
+    /// If the delta chain is frozen, the key and value are handed back.
+    fn insert_if_absent(
+        &self,
+        order: &KeyOrder<K>,
+        key: K,
+        value: V,
+        mapping_table: &MappingTable<K, V>,
+    ) -> Result<Result<(), V>, (K, V)> {
+        let mut existing = None;
+        let record = DeltaRecord::Insert(key, value);
+        match self.push_if(order, record, mapping_table, |current| {
+            existing = current.cloned();
+            current.is_none()
+        }) {
+            Ok(()) => Ok(Ok(())),
+            Err(Refused::Rejected(_)) => {
+                Ok(Err(existing.expect("rejected without an existing value")))
+            }
+            Err(Refused::Frozen(record)) => Err(record.into_entry()),
+        }
+    }
+
+    /// Prepends an update record for `key` if the key has a live entry, or an
+    /// insert record otherwise, and returns a copy of the replaced value.
+    ///
+    /// If the delta chain is frozen, the key and value are handed back.
+    fn upsert(
+        &self,
+        order: &KeyOrder<K>,
+        key: K,
+        value: V,
+        mapping_table: &MappingTable<K, V>,
+    ) -> Result<Option<V>, (K, V)> {
+        let mut record = DeltaRecord::Update(key, value);
+        loop {
+            let mut old = None;
+            record = match self.push_if(order, record, mapping_table, |current| {
+                old = current.cloned();
+                old.is_some()
+            }) {
+                Ok(()) => return Ok(old),
+                Err(Refused::Rejected(DeltaRecord::Update(key, value))) => {
+                    DeltaRecord::Insert(key, value)
+                }
+                Err(Refused::Rejected(_)) => unreachable!("not an update record"),
+                Err(Refused::Frozen(record)) => return Err(record.into_entry()),
+            };
+            record = match self.push_if(order, record, mapping_table, |current| current.is_none()) {
+                Ok(()) => return Ok(None),
+                Err(Refused::Rejected(DeltaRecord::Insert(key, value))) => {
+                    DeltaRecord::Update(key, value)
+                }
+                Err(Refused::Rejected(_)) => unreachable!("not an insert record"),
+                Err(Refused::Frozen(record)) => return Err(record.into_entry()),
+            };
+        }
+    }
+
+    /// Prepends `record`, an insert, update, or delete record, if `accept`
+    /// returns `true` for the value of its key on top of which it would be
+    /// prepended, and the delta chain is not frozen. Otherwise, the record is
+    /// handed back.
+    ///
+    /// `accept` is called again whenever another record was prepended in the
+    /// meantime.
+    fn push_if(
+        &self,
+        order: &KeyOrder<K>,
+        record: DeltaRecord<K, V>,
+        mapping_table: &MappingTable<K, V>,
+        mut accept: impl FnMut(Option<&V>) -> bool,
+    ) -> Result<(), Refused<K, V>> {
+        let mut frozen = false;
+        self.records
+            .push_front_if(record, |record, records| {
+                frozen = Self::frozen(records.clone());
+                if frozen {
+                    return false;
+                }
+                let key = record
+                    .key()
+                    .expect("not an insert, update, or delete record");
+                let current = self.lookup(order, records, key, mapping_table);
+                accept(current.expect("dangling node ID"))
+            })
+            .map_err(|record| {
+                if frozen {
+                    Refused::Frozen(record)
+                } else {
+                    Refused::Rejected(record)
+                }
+            })
+    }
+
+    /// Looks up `key` in the delta chain, falling through to the base node if
+    /// no record in the chain mentions the key.
+    fn get<'a>(
+        &'a self,
+        order: &KeyOrder<K>,
+        key: &K,
+        mapping_table: &'a MappingTable<K, V>,
+    ) -> Result<Option<&'a V>, MappingError> {
+        self.lookup(order, self.records.iter(), key, mapping_table)
+    }
+
+    /// Looks up `key` in `records`, the contents of the delta chain at some
+    /// point in time.
+    fn lookup<'a>(
+        &'a self,
+        order: &KeyOrder<K>,
+        records: linked_list::Iter<'a, DeltaRecord<K, V>>,
+        key: &K,
+        mapping_table: &'a MappingTable<K, V>,
+    ) -> Result<Option<&'a V>, MappingError> {
+        for ref record in records {
+            match record {
+                DeltaRecord::Insert(k, v) | DeltaRecord::Update(k, v) => {
+                    if order.eq(key, k) {
+                        return Ok(Some(v));
+                    }
+                }
+                DeltaRecord::Delete(k) => {
+                    if order.eq(key, k) {
+                        return Ok(None);
+                    }
+                }
+                DeltaRecord::Split(separator, right) => {
+                    if order.le(separator, key) {
+                        return mapping_table
+                            .try_get(*right)?
+                            .get(order, key, mapping_table);
+                    }
+                }
+                DeltaRecord::Merge(separator, merged) => {
+                    if order.le(separator, key) {
+                        return merged.get(order, key, mapping_table);
+                    }
+                }
+                DeltaRecord::Remove(left) => {
+                    // Until the merge delta is posted, the left sibling still
+                    // links here, and the frozen base holds the keys.
+                    let left = mapping_table.try_get(*left)?;
+                    let merged = match left.right_link(order, key) {
+                        Some(right) => !matches!(
+                            mapping_table.try_get(right)?,
+                            Node::Delta(node) if std::ptr::eq(node, self)
+                        ),
+                        None => true,
+                    };
+                    if merged {
+                        return left.get(order, key, mapping_table);
+                    }
+                }
+                DeltaRecord::Freeze => {}
+            }
+        }
+        let base = unsafe { &*self.base };
+        base.get(order, key, mapping_table)
+    }
+
+    /// Returns the number of live entries with keys in `start..end` without
+    /// consolidating the delta chain.
+    ///
+    /// An entry is counted at the newest record or leaf that holds its key,
+    /// following the same rules as `consolidate`. Counting stops once `max`
+    /// entries have been found.
+    fn count_range(&self, order: &KeyOrder<K>, start: &K, end: Option<&K>, max: usize) -> usize {
+        let in_range = |k: &K| order.le(start, k) && end.is_none_or(|end| order.lt(k, end));
+        let below = |k: &K, limit: Option<&K>| limit.is_none_or(|limit| order.lt(k, limit));
+        let records = self.records.iter();
+        let mut count = 0;
+        let mut limit = None;
+        for (pos, record) in records.clone().enumerate() {
+            match record {
+                DeltaRecord::Insert(k, _) | DeltaRecord::Update(k, _) => {
+                    if in_range(k) && !Self::mentions(order, records.clone().take(pos), k) {
+                        count += 1;
+                    }
+                }
+                DeltaRecord::Split(separator, _) => {
+                    if below(separator, limit) {
+                        limit = Some(separator);
+                    }
+                }
+                DeltaRecord::Merge(_, merged) => {
+                    count += merged.keys[merged.range(order, start, end)]
+                        .iter()
+                        .filter(|k| {
+                            below(k, limit) && !Self::mentions(order, records.clone().take(pos), k)
+                        })
+                        .take(max - count)
+                        .count();
+                }
+                DeltaRecord::Delete(_) | DeltaRecord::Remove(_) | DeltaRecord::Freeze => {}
+            }
+            if count >= max {
+                return count;
+            }
+        }
+        let base = self.base();
+        count
+            + base.keys[base.range(order, start, end)]
+                .iter()
+                .filter(|k| below(k, limit) && !Self::mentions(order, records.clone(), k))
+                .take(max - count)
+                .count()
+    }
+
+    /// Returns `true` if one of `records` holds an entry for `key`, or
+    /// deletes it.
+    fn mentions<'a>(
+        order: &KeyOrder<K>,
+        records: impl Iterator<Item = &'a DeltaRecord<K, V>>,
+        key: &K,
+    ) -> bool
+    where
+        K: 'a,
+        V: 'a,
+    {
+        let mut limit = None;
+        for record in records {
+            match record {
+                DeltaRecord::Insert(k, _) | DeltaRecord::Update(k, _) | DeltaRecord::Delete(k) => {
+                    if order.eq(k, key) {
+                        return true;
+                    }
+                }
+                DeltaRecord::Split(separator, _) => {
+                    if limit.is_none_or(|limit| order.lt(separator, limit)) {
+                        limit = Some(separator);
+                    }
+                }
+                DeltaRecord::Merge(_, merged) => {
+                    if limit.is_none_or(|limit| order.lt(key, limit))
+                        && order.binary_search(&merged.keys, key).is_ok()
+                    {
+                        return true;
+                    }
+                }
+                DeltaRecord::Remove(_) | DeltaRecord::Freeze => {}
+            }
+        }
+        false
+    }
+
+    /// Merges the delta chain and its base leaf into a new leaf node.
+    fn consolidate(&self, order: &KeyOrder<K>) -> LeafNode<K, V> {
+        Self::consolidate_records(order, self.records.iter(), self.base())
+    }
+
+    /// Returns the version of the node, which grows with every record
+    /// prepended to the chain and is kept by consolidation.
+    ///
+    /// Every record but a freeze record adds one to the version of the base
+    /// leaf, and a merge record also adds the version of the merged node, so
+    /// that the keys taken over from it never go back to a version they had
+    /// before.
+    fn version(&self) -> u64 {
+        Self::records_version(self.records.iter(), self.base())
+    }
+
+    /// Returns the version of a node with the delta chain `records` on top of
+    /// `base`.
+    fn records_version(
+        records: linked_list::Iter<'_, DeltaRecord<K, V>>,
+        base: &LeafNode<K, V>,
+    ) -> u64 {
+        records.fold(base.version, |version, record| match record {
+            DeltaRecord::Merge(_, merged) => version + 1 + merged.version,
+            DeltaRecord::Freeze => version,
+            _ => version + 1,
+        })
+    }
+
+    /// Merges `records`, the contents of a delta chain at some point in time,
+    /// and `base`, the base leaf of the chain, into a new leaf node.
+    fn consolidate_records(
+        order: &KeyOrder<K>,
+        records: linked_list::Iter<'_, DeltaRecord<K, V>>,
+        base: &LeafNode<K, V>,
+    ) -> LeafNode<K, V> {
+        let mut leaf = LeafNode::new();
+        leaf.version = Self::records_version(records.clone(), base);
+        // The newest split or merge delta determines the bounds of the node.
+        (leaf.high_key, leaf.right_sibling) = Self::split_records(records.clone(), base)
+            .map(|(high_key, right)| (high_key.clone(), right))
+            .unzip();
+        for (k, v) in Self::live_entries(order, records, base) {
+            leaf.keys.push(k.clone());
+            leaf.values.push(v.clone());
+        }
+        leaf.count = leaf.keys.len();
+        leaf
+    }
+
+    /// Returns the live entries of the node with the delta chain `records` on
+    /// top of `base`, in ascending key order, as references into the records
+    /// and leaves that hold them.
+    fn live_entries<'a>(
+        order: &KeyOrder<K>,
+        records: linked_list::Iter<'a, DeltaRecord<K, V>>,
+        base: &'a LeafNode<K, V>,
+    ) -> impl Iterator<Item = (&'a K, &'a V)> {
+        // Every entry is gathered newest first, so that after a stable sort,
+        // the first of the entries for a key is the one that determines its
+        // fate.
+        let mut entries = Vec::new();
+        // A split delta hands the keys at or above its separator over to the
+        // right sibling, even though older entries still hold them.
+        let mut limit: Option<&K> = None;
+        let below = |k: &K, limit: Option<&K>| limit.is_none_or(|limit| order.lt(k, limit));
+        for record in records {
+            match record {
+                DeltaRecord::Insert(k, v) | DeltaRecord::Update(k, v) => {
+                    entries.push((k, Some(v)));
+                }
+                DeltaRecord::Delete(k) => {
+                    entries.push((k, None));
+                }
+                DeltaRecord::Split(separator, _) => {
+                    if below(separator, limit) {
+                        limit = Some(separator);
+                    }
+                }
+                DeltaRecord::Merge(_, merged) => {
+                    for (k, v) in merged.keys.iter().zip(&merged.values) {
+                        if below(k, limit) {
+                            entries.push((k, Some(v)));
+                        }
+                    }
+                }
+                DeltaRecord::Remove(_) | DeltaRecord::Freeze => {}
+            }
+        }
+        for (k, v) in base.keys.iter().zip(&base.values) {
+            if below(k, limit) {
+                entries.push((k, Some(v)));
+            }
+        }
+        entries.sort_by(|(a, _), (b, _)| order.cmp(a, b));
+        entries.dedup_by(|(a, _), (b, _)| order.eq(a, b));
+        entries.into_iter().filter_map(|(k, v)| Some((k, v?)))
+    }
+}
+
+#[derive(Debug)]
+enum DeltaRecord<K, V> {
+    Insert(K, V),
+    /// Replaces the value of a key that has a live entry below the record.
+    Update(K, V),
+    Delete(K),
+    /// Keys at or above the separator have moved to the right sibling.
+    Split(K, NodeID),
+    /// Keys at or above the separator are held by the right sibling that was
+    /// merged into this node.
+    Merge(K, LeafNode<K, V>),
+    /// The node was merged into its left sibling.
+    Remove(NodeID),
+    /// The delta chain is being consolidated and accepts no more records.
+    Freeze,
+}
+
+/// A record that was not prepended to a delta chain.
+enum Refused<K, V> {
+    /// The condition of the write does not hold for the value of the key.
+    Rejected(DeltaRecord<K, V>),
+    /// The delta chain is frozen, so the record has to be prepended to the
+    /// node that replaces it instead.
+    Frozen(DeltaRecord<K, V>),
+}
+
+impl<K, V> DeltaRecord<K, V> {
+    /// Returns the key of an insert, update, or delete record.
+    fn key(&self) -> Option<&K> {
+        match self {
+            DeltaRecord::Insert(key, _)
+            | DeltaRecord::Update(key, _)
+            | DeltaRecord::Delete(key) => Some(key),
+            _ => None,
+        }
+    }
+
+    /// Returns the key and value of an insert or update record.
+    fn into_entry(self) -> (K, V) {
+        match self {
+            DeltaRecord::Insert(key, value) | DeltaRecord::Update(key, value) => (key, value),
+            _ => unreachable!("not an insert or update record"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct LeafNode<K, V> {
+    /// The number of keys stored in the node.
+    count: usize,
+    /// The key ranges stored in the children.
+    keys: Vec<K>,
+    /// The values stored in the node.
+    values: Vec<V>,
+    /// The exclusive upper bound of the keys in the node, if it was split.
+    high_key: Option<K>,
+    /// The node holding the keys at or above `high_key`.
+    right_sibling: Option<NodeID>,
+    /// The version of the node when it was consolidated, see
+    /// `DeltaNode::version`.
+    version: u64,
+}
+
+impl<K, V> LeafNode<K, V>
+where
+    K: KeyType,
+{
+    fn new() -> Self {
+        LeafNode {
+            count: 0,
+            keys: Vec::new(),
+            values: Vec::new(),
+            high_key: None,
+            right_sibling: None,
+            version: 0,
+        }
+    }
+
+    /// Returns the number of bytes allocated for the vectors of the node.
+    fn memory_bytes(&self) -> usize {
+        self.keys.capacity() * std::mem::size_of::<K>()
+            + self.values.capacity() * std::mem::size_of::<V>()
+    }
+
+    /// Looks up `key`, following the right sibling if the key is beyond the
+    /// range of the node.
+    fn get<'a>(
+        &'a self,
+        order: &KeyOrder<K>,
+        key: &K,
+        mapping_table: &'a MappingTable<K, V>,
+    ) -> Result<Option<&'a V>, MappingError>
+    where
+        V: Clone,
+    {
+        if let Some((high_key, right)) = self.split() {
+            if order.le(high_key, key) {
+                return mapping_table.try_get(right)?.get(order, key, mapping_table);
+            }
+        }
+        for i in 0..self.count {
+            if order.eq(key, &self.keys[i]) {
+                return Ok(Some(&self.values[i]));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the exclusive upper bound of the keys in the node and its right
+    /// sibling, if the node was split.
+    fn split(&self) -> Option<(&K, NodeID)> {
+        self.high_key.as_ref().zip(self.right_sibling)
+    }
+
+    /// Returns the range of indices of the keys in `start..end`.
+    fn range(&self, order: &KeyOrder<K>, start: &K, end: Option<&K>) -> std::ops::Range<usize> {
+        let lo = order.lower_bound(&self.keys, start);
+        let hi = end.map_or(self.count, |end| order.lower_bound(&self.keys, end));
+        lo..hi.max(lo)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_insert_and_get() {
+        let tree = BwTree::new();
+        assert_eq!(tree.insert(1, "A"), Ok(None));
+        assert_eq!(tree.get(1).as_deref(), Some(&"A"));
+        assert_eq!(tree.insert(2, "B"), Ok(None));
+        assert_eq!(tree.get(2).as_deref(), Some(&"B"));
+        assert_eq!(tree.insert(3, "C"), Ok(None));
+        assert_eq!(tree.get(3).as_deref(), Some(&"C"));
+        assert_eq!(tree.insert(4, "D"), Ok(None));
+        assert_eq!(tree.get(4).as_deref(), Some(&"D"));
+    }
+
+    #[test]
+    fn test_insert_retains_existing_entries() {
+        // The Bw-Tree stores insertions into a delta chain. Let's make sure
+        // that `insert()` doesn't lose existing entries.
+        let tree = BwTree::new();
+        assert_eq!(tree.insert(1, "A"), Ok(None));
+        assert_eq!(tree.get(1).as_deref(), Some(&"A"));
+        assert_eq!(tree.insert(2, "B"), Ok(None));
+        assert_eq!(tree.get(1).as_deref(), Some(&"A"));
+        assert_eq!(tree.insert(2, "B"), Ok(Some("B")));
+    }
+
+    #[test]
+    fn test_delete() {
+        let tree = BwTree::new();
+        assert_eq!(tree.insert(1, "A"), Ok(None));
+        assert_eq!(tree.insert(2, "B"), Ok(None));
+        assert_eq!(tree.delete(1), Ok(true));
+        assert_eq!(tree.get(1).as_deref(), None);
+        assert_eq!(tree.get(2).as_deref(), Some(&"B"));
+    }
+
+    #[test]
+    fn test_delete_then_reinsert() {
+        // The delta chain is traversed newest-first, so a re-insert after a
+        // delete must shadow the delete record.
+        let tree = BwTree::new();
+        assert_eq!(tree.insert(1, "A"), Ok(None));
+        assert_eq!(tree.delete(1), Ok(true));
+        assert_eq!(tree.get(1).as_deref(), None);
+        assert_eq!(tree.insert(1, "B"), Ok(None));
+        assert_eq!(tree.get(1).as_deref(), Some(&"B"));
+    }
+
+    #[test]
+    fn test_delete_missing_key() {
+        let tree: BwTree<u64, &str> = BwTree::new();
+        assert_eq!(tree.delete(1), Ok(false));
+        assert_eq!(tree.insert(1, "A"), Ok(None));
+        assert_eq!(tree.delete(2), Ok(false));
+        assert_eq!(tree.get(1).as_deref(), Some(&"A"));
+    }
+
+    #[test]
+    fn test_update() {
+        let tree = BwTree::new();
+        assert_eq!(tree.insert(1, "A"), Ok(None));
+        assert_eq!(tree.update(1, "B"), Ok(Some("A")));
+        assert_eq!(tree.get(1).as_deref(), Some(&"B"));
+        assert_eq!(tree.update(1, "C"), Ok(Some("B")));
+        assert_eq!(tree.get(1).as_deref(), Some(&"C"));
+    }
+
+    #[test]
+    fn test_newest_record_wins_regardless_of_variant() {
+        let tree = BwTree::with_consolidation_threshold(100);
+        let records = |key| {
+            let (_, delta) = tree.delta_for(&key).unwrap();
+            delta
+                .records
+                .iter()
+                .filter(|r| r.key() == Some(&key))
+                .count()
+        };
+        // An update record masks the insert record below it, also for readers
+        // on other threads.
+        assert_eq!(tree.insert(1, 10), Ok(None));
+        assert_eq!(tree.update(1, 11), Ok(Some(10)));
+        assert_eq!(records(1), 2);
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                assert_eq!(tree.get(1).as_deref(), Some(&11));
+                assert_eq!(tree.get_owned(1), Some(11));
+            });
+        });
+        // A delete record masks the insert below it, and is masked in turn by
+        // the insert above it.
+        assert_eq!(tree.insert(2, 20), Ok(None));
+        assert_eq!(tree.delete(2), Ok(true));
+        assert_eq!(tree.get_owned(2), None);
+        assert_eq!(tree.insert(2, 21), Ok(None));
+        assert_eq!(records(2), 3);
+        assert_eq!(tree.get_owned(2), Some(21));
+
+        // The same holds for records on top of a consolidated leaf.
+        tree.compact().unwrap();
+        assert_eq!(tree.update(2, 22), Ok(Some(21)));
+        assert_eq!(tree.delete(1), Ok(true));
+        assert_eq!(tree.get_owned(1), None);
+        assert_eq!(tree.get_owned(2), Some(22));
+        assert_eq!(tree.insert(1, 12), Ok(None));
+        assert_eq!(tree.get_owned(1), Some(12));
+        let entries: Vec<_> = tree.iter().collect();
+        assert_eq!(entries, vec![(1, 12), (2, 22)]);
+    }
+
+    #[test]
+    fn test_update_missing_key() {
+        let tree = BwTree::new();
+        assert_eq!(tree.update(1, "A"), Ok(None));
+        assert_eq!(tree.insert(1, "A"), Ok(None));
+        assert_eq!(tree.update(2, "B"), Ok(None));
+        assert_eq!(tree.get(1).as_deref(), Some(&"A"));
+        assert_eq!(tree.get(2).as_deref(), None);
+    }
+
+    #[test]
+    fn test_get_routes_to_leaf() {
+        let tree: BwTree<u64, &str> = BwTree::new();
+        let second_leaf_id = tree.get_next_node_id().unwrap();
+        let first_leaf = LeafNode {
+            count: 2,
+            keys: vec![1, 5],
+            values: vec!["A", "B"],
+            high_key: None,
+            right_sibling: None,
+            version: 0,
+        };
+        let second_leaf = LeafNode {
+            count: 2,
+            keys: vec![10, 15],
+            values: vec!["C", "D"],
+            high_key: None,
+            right_sibling: None,
+            version: 0,
+        };
+        let mut root = InnerNode::new();
+        root.insert(&tree.order, KeyType::minimum(), FIRST_LEAF_NODE_ID);
+        root.insert(&tree.order, 10, second_leaf_id);
+        tree.mapping_table
+            .insert(FIRST_LEAF_NODE_ID, Node::Leaf(first_leaf));
+        tree.mapping_table
+            .insert(second_leaf_id, Node::Leaf(second_leaf));
+        tree.mapping_table.insert(tree.root_id(), Node::Inner(root));
+
+        assert_eq!(tree.get(1).as_deref(), Some(&"A"));
+        assert_eq!(tree.get(5).as_deref(), Some(&"B"));
+        assert_eq!(tree.get(10).as_deref(), Some(&"C"));
+        assert_eq!(tree.get(15).as_deref(), Some(&"D"));
+        assert_eq!(tree.get(0).as_deref(), None);
+        assert_eq!(tree.get(7).as_deref(), None);
+        assert_eq!(tree.get(20).as_deref(), None);
+    }
+
+    #[test]
+    fn test_insert_on_top_of_leaf() {
+        let tree = BwTree::new();
+        let leaf = LeafNode {
+            count: 1,
+            keys: vec![1],
+            values: vec!["A"],
+            high_key: None,
+            right_sibling: None,
+            version: 0,
+        };
+        tree.mapping_table
+            .insert(FIRST_LEAF_NODE_ID, Node::Leaf(leaf));
+        assert_eq!(tree.insert(2, "B"), Ok(None));
+        assert!(matches!(
+            tree.mapping_table.get(FIRST_LEAF_NODE_ID),
+            Node::Delta(_)
+        ));
+        assert_eq!(tree.get(1).as_deref(), Some(&"A"));
+        assert_eq!(tree.get(2).as_deref(), Some(&"B"));
+    }
+
+    #[test]
+    fn test_consolidate() {
+        let tree = BwTree::with_consolidation_threshold(usize::MAX);
+        for i in 0..10 {
+            assert_eq!(tree.insert(i, i * 10), Ok(None));
+        }
+        assert_eq!(tree.delete(3), Ok(true));
+        assert_eq!(tree.update(5, 55), Ok(Some(50)));
+        assert_eq!(tree.delete(8), Ok(true));
+        assert_eq!(tree.insert(8, 88), Ok(None));
+        let before: Vec<_> = (0..12).map(|i| tree.get_owned(i)).collect();
+
+        tree.consolidate(FIRST_LEAF_NODE_ID).unwrap();
+        assert!(matches!(
+            tree.mapping_table.get(FIRST_LEAF_NODE_ID),
+            Node::Leaf(_)
+        ));
+        let after: Vec<_> = (0..12).map(|i| tree.get_owned(i)).collect();
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn test_consolidate_insert_update_delete_records() {
+        let tree = BwTree::with_consolidation_threshold(usize::MAX);
+        assert_eq!(tree.insert(1, 10), Ok(None));
+        assert_eq!(tree.insert(2, 20), Ok(None));
+        assert_eq!(tree.insert(3, 30), Ok(None));
+        assert_eq!(tree.insert(2, 21), Ok(Some(20)));
+        assert_eq!(tree.update(3, 31), Ok(Some(30)));
+        assert_eq!(tree.delete(1), Ok(true));
+        assert_eq!(tree.update(1, 11), Ok(None));
+        assert_eq!(tree.insert(1, 12), Ok(None));
+        assert_eq!(tree.delete(4), Ok(false));
+
+        let Node::Delta(delta) = tree.mapping_table.get(FIRST_LEAF_NODE_ID) else {
+            panic!("node has no delta chain");
+        };
+        let kinds: Vec<_> = delta
+            .records
+            .iter()
+            .map(|record| match record {
+                DeltaRecord::Insert(..) => "insert",
+                DeltaRecord::Update(..) => "update",
+                DeltaRecord::Delete(..) => "delete",
+                _ => "other",
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            ["insert", "delete", "update", "update", "insert", "insert", "insert"]
+        );
+
+        tree.consolidate(FIRST_LEAF_NODE_ID).unwrap();
+        match tree.mapping_table.get(FIRST_LEAF_NODE_ID) {
+            Node::Leaf(leaf) => {
+                assert_eq!(leaf.count, 3);
+                assert_eq!(leaf.keys, vec![1, 2, 3]);
+                assert_eq!(leaf.values, vec![12, 21, 31]);
+            }
+            _ => panic!("node was not consolidated"),
+        }
+        assert_eq!(tree.len(), 3);
+    }
+
+    #[test]
+    fn test_len_is_exact_under_racing_writes() {
+        let tree = BwTree::with_consolidation_threshold(8);
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for i in 0..2000u64 {
+                        let key = i % 50;
+                        if i % 3 == 0 {
+                            let _ = tree.delete(key).unwrap();
+                        } else {
+                            let _ = tree.insert(key, i).unwrap();
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(tree.len(), tree.iter().count());
+    }
+
+    #[test]
+    fn test_racing_consolidations_keep_every_insert() {
+        // Every other insert consolidates the node that all of them go to, so
+        // consolidations keep racing with the inserts of the other threads.
+        for _ in 0..200 {
+            let tree = BwTree::with_consolidation_threshold(2);
+            std::thread::scope(|s| {
+                for t in 0..4u64 {
+                    let tree = &tree;
+                    s.spawn(move || {
+                        for i in 0..50 {
+                            tree.insert(i * 4 + t, i).unwrap();
+                        }
+                    });
+                }
+            });
+            assert!(tree.iter().map(|(key, _)| key).eq(0..200));
+            assert_eq!(tree.len(), 200);
+        }
+    }
+
+    #[test]
+    fn test_frozen_delta_chain_hands_writes_back() {
+        let tree = BwTree::with_consolidation_threshold(usize::MAX);
+        tree.insert(1, 10).unwrap();
+        let (_, delta) = tree.delta_for(&1).unwrap();
+        assert!(delta.freeze());
+        assert!(!delta.freeze());
+        assert_eq!(
+            delta.upsert(&tree.order, 2, 20, &tree.mapping_table),
+            Err((2, 20))
+        );
+        let record = DeltaRecord::Delete(1);
+        assert!(matches!(
+            delta.push_if(&tree.order, record, &tree.mapping_table, |_| true),
+            Err(Refused::Frozen(DeltaRecord::Delete(1)))
+        ));
+        assert_eq!(
+            delta.get(&tree.order, &1, &tree.mapping_table),
+            Ok(Some(&10))
+        );
+        assert_eq!(delta.consolidate(&tree.order).keys, vec![1]);
+    }
+
+    #[test]
+    fn test_try_insert() {
+        let tree = BwTree::with_consolidation_threshold(4);
+        assert_eq!(tree.try_insert(1, 10), Ok(None));
+        assert_eq!(tree.try_insert(1, 11), Ok(Some(10)));
+        for key in 2..100 {
+            assert_eq!(tree.try_insert(key, key * 10), Ok(None));
+        }
+        assert_eq!(tree.len(), 99);
+        assert_eq!(tree.get(1).as_deref(), Some(&11));
+        assert!(tree.iter().map(|(key, _)| key).eq(1..100));
+        tree.verify().unwrap();
+    }
+
+    #[test]
+    fn test_try_insert_hands_back_on_frozen_chain() {
+        let tree = BwTree::with_consolidation_threshold(usize::MAX);
+        tree.insert(1, 10).unwrap();
+        let (_, delta) = tree.delta_for(&1).unwrap();
+        // A consolidation in progress, which `insert` would wait out.
+        assert!(delta.freeze());
+        assert_eq!(tree.try_insert(1, 11), Err((1, 11)));
+        assert_eq!(tree.try_insert(2, 20), Err((2, 20)));
+        assert_eq!(tree.get(1).as_deref(), Some(&10));
+        assert_eq!(tree.len(), 1);
+    }
+
+    #[test]
+    fn test_try_insert_under_contention() {
+        let tree = BwTree::new();
+        let rejected = AtomicUsize::new(0);
+        std::thread::scope(|s| {
+            for t in 0..4u64 {
+                let (tree, rejected) = (&tree, &rejected);
+                s.spawn(move || {
+                    for i in 0..10_000u64 {
+                        let key = i % 16;
+                        match tree.try_insert(key, key * 4 + t) {
+                            Ok(_) => {}
+                            Err(pair) => {
+                                assert_eq!(pair, (key, key * 4 + t));
+                                rejected.fetch_add(1, Ordering::SeqCst);
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        // Whichever writes went through, every key holds one of them.
+        assert!(tree.iter().all(|(key, value)| value / 4 == key));
+        assert_eq!(tree.len(), 16);
+        assert!(rejected.load(Ordering::SeqCst) < 40_000);
+    }
+
+    #[test]
+    fn test_insert_triggers_consolidation() {
+        let tree = BwTree::with_consolidation_threshold(4);
+        for i in 0..4 {
+            assert_eq!(tree.insert(i, i), Ok(None));
+        }
+        assert!(matches!(
+            tree.mapping_table.get(FIRST_LEAF_NODE_ID),
+            Node::Delta(_)
+        ));
+        assert_eq!(tree.insert(4, 4), Ok(None));
+        assert!(matches!(
+            tree.mapping_table.get(FIRST_LEAF_NODE_ID),
+            Node::Leaf(_)
+        ));
+        for i in 0..5 {
+            assert_eq!(tree.get(i).as_deref(), Some(&i));
+        }
+    }
+
+    /// A value that records when it is dropped. Every clone gets its own flag.
+    #[derive(Debug)]
+    struct DropFlag(Arc<AtomicBool>);
+
+    impl DropFlag {
+        fn new() -> Self {
+            DropFlag(Arc::new(AtomicBool::new(false)))
+        }
+    }
+
+    impl Clone for DropFlag {
+        fn clone(&self) -> Self {
+            DropFlag::new()
+        }
+    }
+
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_background_consolidation() {
+        let tree = Arc::new(
+            BwTree::builder()
+                .consolidation_threshold(100_000)
+                .max_chain_len(100_000)
+                .build(),
+        );
+        for i in 0..1000u64 {
+            tree.insert(i % 16, i).unwrap();
+        }
+        assert_eq!(tree.stats().longest_delta_chain, 1000);
+        let consolidator = BwTree::spawn_consolidator(&tree, Duration::from_millis(1));
+        let done = AtomicBool::new(false);
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let mut i = 1000;
+                while !done.load(Ordering::SeqCst) {
+                    tree.insert(i % 16, i).unwrap();
+                    i += 1;
+                }
+            });
+            // The writers alone would only ever grow the chain.
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while tree.stats().longest_delta_chain >= 1000 {
+                assert!(Instant::now() < deadline, "chain was never consolidated");
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            done.store(true, Ordering::SeqCst);
+        });
+        consolidator.shutdown();
+        let values: Vec<_> = tree.values().collect();
+        assert_eq!(values.len(), 16);
+        // The last value written to every key survived the consolidations.
+        let last = values.iter().max().unwrap();
+        assert!(values.iter().all(|value| last - value < 16));
+        tree.verify().unwrap();
+    }
+
+    #[test]
+    fn test_consolidator_shuts_down_while_sleeping() {
+        let tree: Arc<BwTree<u64, u64>> = Arc::new(BwTree::new());
+        let consolidator = BwTree::spawn_consolidator(&tree, Duration::from_secs(3600));
+        let start = Instant::now();
+        consolidator.shutdown();
+        assert!(start.elapsed() < Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_overwrites_reclaim_memory() {
+        let tree = BwTree::with_consolidation_threshold(4);
+        for i in 0..100_000 {
+            let old = if i < 16 { None } else { Some(i - 16) };
+            assert_eq!(tree.insert(i % 16, i), Ok(old));
+            assert!(tree.epoch.pending() <= 2);
+        }
+        for i in 0..16 {
+            assert_eq!(tree.get(i).as_deref(), Some(&(99_984 + i)));
+        }
+    }
+
+    #[test]
+    fn test_guard_defers_reclamation() {
+        let tree = BwTree::with_consolidation_threshold(0);
+        assert!(tree.insert(1, DropFlag::new()).unwrap().is_none());
+        // The insert is consolidated right away into a leaf that holds a clone
+        // of the value.
+        let value = tree.get(1).unwrap();
+        let dropped = value.0.clone();
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for _ in 0..10 {
+                    assert!(tree.insert(1, DropFlag::new()).unwrap().is_some());
+                }
+            });
+        });
+        assert!(!dropped.load(Ordering::SeqCst));
+        drop(value);
+        for _ in 0..10 {
+            assert!(tree.insert(1, DropFlag::new()).unwrap().is_some());
+        }
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_open_scan_does_not_defer_reclamation() {
+        let tree = BwTree::with_consolidation_threshold(4);
+        for key in 0..1000u64 {
+            tree.insert(key, key).unwrap();
+        }
+        let mut scan = tree.iter();
+        let mut rev_scan = tree.iter_rev();
+        assert_eq!(scan.next(), Some((0, 0)));
+        assert_eq!(rev_scan.next(), Some((999, 999)));
+        // The scans are suspended in the middle of a node, which must not
+        // keep the overwritten nodes from being freed.
+        for i in 0..10_000 {
+            tree.insert(i % 16, i).unwrap();
+            assert!(tree.epoch.pending() <= 2);
+        }
+        assert_eq!(scan.next(), Some((1, 1)));
+        assert_eq!(rev_scan.next(), Some((998, 998)));
+    }
+
+    #[test]
+    fn test_read_your_writes() {
+        // Small thresholds make writes land on fresh delta chains, frozen
+        // chains, split nodes and grown roots alike.
+        let tree = BwTree::with_thresholds(2, 4, 1);
+        for i in 0..20_000u64 {
+            let key = i * 7919 % 2000;
+            match i % 4 {
+                0 | 1 => {
+                    tree.insert(key, i).unwrap();
+                    assert_eq!(tree.get(key).as_deref(), Some(&i));
+                }
+                2 => {
+                    if tree.update(key, i).unwrap().is_some() {
+                        assert_eq!(tree.get(key).as_deref(), Some(&i));
+                    }
+                }
+                _ => {
+                    tree.delete(key).unwrap();
+                    assert_eq!(tree.get(key).as_deref(), None);
+                    assert!(!tree.contains_key(key));
+                }
+            }
+        }
+        tree.verify().unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_reader_never_observes_freed_memory() {
+        let tree = BwTree::with_consolidation_threshold(2);
+        assert_eq!(tree.insert(1, format!("value-{}", 0)), Ok(None));
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for i in 1..10_000 {
+                    assert!(tree.insert(1, format!("value-{}", i)).unwrap().is_some());
+                }
+            });
+            s.spawn(|| {
+                for _ in 0..10_000 {
+                    let value = tree.get(1).unwrap();
+                    assert!(value.starts_with("value-"));
+                }
+            });
+        });
+    }
+
+    #[test]
+    fn test_free_node_ids_are_reused() {
+        let tree: BwTree<u64, u64> = BwTree::new();
+        let a = tree.get_next_node_id().unwrap();
+        let b = tree.get_next_node_id().unwrap();
+        let c = tree.get_next_node_id().unwrap();
+        tree.free_node_id(a);
+        tree.free_node_id(c);
+        // Freed IDs are reused, most recently freed first.
+        assert_eq!(tree.get_next_node_id().unwrap(), c);
+        assert_eq!(tree.get_next_node_id().unwrap(), a);
+        // Once the free list is exhausted, new IDs are allocated.
+        let d = tree.get_next_node_id().unwrap();
+        assert!(d > b && d > c);
+    }
+
+    #[test]
+    fn test_inner_node_insert_out_of_order() {
+        let order = KeyOrder::natural();
+        let mut node = InnerNode::new();
+        for (separator, child) in [(30, 3), (0, 0), (20, 2), (10, 1), (40, 4)] {
+            node.insert(&order, separator, child);
+        }
+        assert_eq!(node.keys, vec![0, 10, 20, 30, 40]);
+        assert_eq!(node.children, vec![0, 1, 2, 3, 4]);
+        let node: Node<u64, u64> = Node::Inner(node);
+        for (key, child) in [
+            (0, 0),
+            (9, 0),
+            (10, 1),
+            (25, 2),
+            (30, 3),
+            (39, 3),
+            (1000, 4),
+        ] {
+            assert_eq!(node.find_child(&order, &key), Some(child));
+        }
+    }
+
+    /// Builds a tree with one leaf per slice in `leaves` under the root, with
+    /// every key mapped to itself.
+    fn tree_with_leaves(leaves: &[&[u64]]) -> BwTree<u64, u64> {
+        let tree = BwTree::new();
+        let mut root = InnerNode::new();
+        let mut ids = Vec::new();
+        for (i, keys) in leaves.iter().enumerate() {
+            let leaf_id = if i == 0 {
+                root.insert(&tree.order, KeyType::minimum(), FIRST_LEAF_NODE_ID);
+                FIRST_LEAF_NODE_ID
+            } else {
+                let leaf_id = tree.get_next_node_id().unwrap();
+                root.insert(&tree.order, keys[0], leaf_id);
+                leaf_id
+            };
+            ids.push(leaf_id);
+        }
+        for (i, keys) in leaves.iter().enumerate() {
+            let leaf = LeafNode {
+                count: keys.len(),
+                keys: keys.to_vec(),
+                values: keys.to_vec(),
+                high_key: leaves.get(i + 1).map(|keys| keys[0]),
+                right_sibling: ids.get(i + 1).copied(),
+                version: 0,
+            };
+            tree.mapping_table.insert(ids[i], Node::Leaf(leaf));
+            tree.len.fetch_add(keys.len(), Ordering::SeqCst);
+        }
+        tree.mapping_table.insert(tree.root_id(), Node::Inner(root));
+        tree
+    }
+
+    #[test]
+    fn test_range() {
+        let tree = tree_with_leaves(&[&[1, 3, 5], &[10, 12, 14], &[20, 22]]);
+        assert_eq!(tree.insert(4, 4), Ok(None));
+        assert_eq!(tree.delete(12), Ok(true));
+        assert_eq!(tree.insert(21, 21), Ok(None));
+        let keys = |start, end| tree.range(start, end).map(|(k, _)| k).collect::<Vec<_>>();
+        assert_eq!(keys(0, 100), vec![1, 3, 4, 5, 10, 14, 20, 21, 22]);
+        assert_eq!(keys(3, 21), vec![3, 4, 5, 10, 14, 20]);
+        assert_eq!(keys(6, 10), Vec::<u64>::new());
+        assert_eq!(keys(11, 15), vec![14]);
+        assert_eq!(keys(22, 23), vec![22]);
+        let entries: Vec<_> = tree.range(4, 11).collect();
+        assert_eq!(entries, vec![(4, 4), (5, 5), (10, 10)]);
+    }
+
+    #[test]
+    fn test_range_empty() {
+        let tree = tree_with_leaves(&[&[1, 3, 5], &[10, 12, 14]]);
+        assert_eq!(tree.range(5, 5).count(), 0);
+        assert_eq!(tree.range(10, 3).count(), 0);
+        assert_eq!(tree.range(100, 200).count(), 0);
+        let tree: BwTree<u64, u64> = BwTree::new();
+        assert_eq!(tree.range(0, 100).count(), 0);
+    }
+
+    #[test]
+    fn test_range_from_minimum() {
+        let tree = tree_with_leaves(&[&[0, 3, 5], &[10, 12, 14]]);
+        let keys: Vec<_> = tree.range(KeyType::minimum(), 11).map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![0, 3, 5, 10]);
+    }
+
+    #[test]
+    fn test_range_pages() {
+        let tree = BwTree::with_split_threshold(16);
+        for key in 0..1000u64 {
+            tree.insert(key, key * 2).unwrap();
+        }
+        let pages: Vec<_> = tree.range_pages(10, 500, 64).collect();
+        let sizes: Vec<_> = pages.iter().map(Vec::len).collect();
+        assert_eq!(sizes, [vec![64; 7], vec![42]].concat());
+        assert!(pages.concat().into_iter().eq(tree.range(10, 500)));
+
+        let exact: Vec<_> = tree.range_pages(0, 100, 50).collect();
+        assert_eq!(exact.len(), 2);
+        assert!(exact.iter().all(|page| page.len() == 50));
+        assert_eq!(tree.range_pages(2000, 3000, 10).next(), None);
+    }
+
+    #[test]
+    fn test_range_scan_over_many_leaves() {
+        // Every leaf but the last is split, so the scan prefetches the right
+        // sibling of each one it reads, including siblings it never reaches.
+        let tree = BwTree::with_split_threshold(16);
+        for key in 0..10_000u64 {
+            tree.insert(key, key).unwrap();
+        }
+        for key in (0..10_000u64).step_by(3) {
+            tree.delete(key).unwrap();
+        }
+        assert!(tree.stats().leaf_count > 100);
+        let expected = |start, end| (start..end).filter(|key| key % 3 != 0);
+        assert!(tree
+            .range(0, 10_000)
+            .map(|(k, _)| k)
+            .eq(expected(0, 10_000)));
+        assert!(tree
+            .range(4_321, 4_400)
+            .map(|(k, _)| k)
+            .eq(expected(4_321, 4_400)));
+        assert!(tree
+            .range(9_990, u64::MAX)
+            .map(|(k, _)| k)
+            .eq(expected(9_990, 10_000)));
+    }
+
+    #[test]
+    fn test_range_scan_follows_sibling_links() {
+        let tree = BwTree::with_split_threshold(16);
+        for key in 0..1_000u64 {
+            tree.insert(key, key).unwrap();
+        }
+        assert!(tree.stats().leaf_count > 10);
+        // Only the first leaf of a scan is found by descending the tree.
+        let descents = tree.descents.load(Ordering::Relaxed);
+        assert!(tree.range(0, 1_000).map(|(k, _)| k).eq(0..1_000));
+        assert_eq!(tree.descents.load(Ordering::Relaxed), descents + 1);
+        let descents = tree.descents.load(Ordering::Relaxed);
+        assert_eq!(tree.range(100, 900).count(), 800);
+        assert_eq!(tree.descents.load(Ordering::Relaxed), descents + 1);
+    }
+
+    #[test]
+    fn test_range_scan_descends_past_merged_leaf() {
+        let tree = BwTree::with_split_and_merge_thresholds(16, 4);
+        for key in 0..200u64 {
+            tree.insert(key, key).unwrap();
+        }
+        let mut scan = tree.range(100, 200);
+        let first = scan.next().unwrap().0;
+        let (first_leaf, high_key) = tree.find_leaf_with_upper_bound(&first);
+        let high_key = high_key.unwrap();
+        // Emptying the leaf the scan is in merges it into its left sibling,
+        // so its link can't be followed anymore.
+        for key in first..high_key {
+            tree.delete(key).unwrap();
+        }
+        tree.compact().unwrap();
+        let descents = tree.descents.load(Ordering::Relaxed);
+        // The rest of the emptied leaf was buffered before it was emptied.
+        assert!(scan.map(|(k, _)| k).eq(first + 1..200));
+        assert_eq!(tree.descents.load(Ordering::Relaxed), descents + 1);
+        assert_ne!(tree.find_leaf_with_upper_bound(&first).0, first_leaf);
+    }
+
+    #[test]
+    fn test_clone() {
+        let tree = BwTree::with_split_threshold(8);
+        for key in 0..100u64 {
+            tree.insert(key, key).unwrap();
+        }
+        for key in (0..100u64).step_by(2) {
+            tree.delete(key).unwrap();
+        }
+        let copy = tree.clone();
+        assert!(copy.iter().eq(tree.iter()));
+        assert_eq!(copy.len(), tree.len());
+        assert_eq!(copy.split_threshold, 8);
+        for id in 0..tree.next_unused_node_id.load(Ordering::SeqCst) {
+            let original = tree.mapping_table.load(id);
+            assert!(original.is_null() || !std::ptr::eq(copy.mapping_table.load(id), original));
+        }
+
+        for key in 0..50u64 {
+            copy.delete(key).unwrap();
+        }
+        copy.insert(1_000, 1_000).unwrap();
+        assert!(tree.iter().map(|(k, _)| k).eq((1..100).step_by(2)));
+        assert_eq!(tree.get_owned(1_000), None);
+        assert!(copy
+            .iter()
+            .map(|(k, _)| k)
+            .eq((51..100).step_by(2).chain([1_000])));
+    }
+
+    #[test]
+    fn test_from_iter() {
+        let tree: BwTree<u64, u64> = (0..300).map(|i| ((i * 37) % 100, i)).collect();
+        assert_eq!(tree.len(), 100);
+        for (key, value) in tree.iter() {
+            let last = (0..300).filter(|i| i * 37 % 100 == key).max();
+            assert_eq!(Some(value), last);
+        }
+        assert!(tree.iter().map(|(k, _)| k).eq(0..100));
+        assert!(tree.stats().leaf_count > 1);
+
+        let tree: BwTree<u64, &str> = [(2, "b"), (1, "a"), (2, "c")].into_iter().collect();
+        assert!(tree.iter().eq([(1, "a"), (2, "c")]));
+        assert!(std::iter::empty::<(u64, u64)>()
+            .collect::<BwTree<_, _>>()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_range_fold() {
+        let tree = BwTree::with_split_threshold(8);
+        for key in 0..100u64 {
+            tree.insert(key, key * 2).unwrap();
+        }
+        for key in (0..100u64).step_by(5) {
+            tree.delete(key).unwrap();
+        }
+        assert!(tree.stats().leaf_count > 2);
+        let expected = (10..90u64).filter(|key| key % 5 != 0);
+        let sum = tree.range_fold(10, 90, 0, |sum, _, value| sum + value);
+        assert_eq!(sum, expected.clone().map(|key| key * 2).sum::<u64>());
+        let max = tree.range_fold(10, 90, None, |max, key, value| {
+            assert_ne!(key % 5, 0);
+            max.max(Some(*value))
+        });
+        assert_eq!(max, expected.map(|key| key * 2).max());
+        assert_eq!(tree.range_fold(95, 96, 7, |_, _, _| unreachable!()), 7);
+    }
+
+    #[test]
+    fn test_range_fold_does_not_clone() {
+        static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+        struct Counted(u64);
+
+        impl Clone for Counted {
+            fn clone(&self) -> Self {
+                CLONES.fetch_add(1, Ordering::SeqCst);
+                Counted(self.0)
+            }
+        }
+
+        let tree = BwTree::with_consolidation_threshold(usize::MAX);
+        for key in 0..40u64 {
+            tree.insert(key, Counted(key)).unwrap();
+        }
+        tree.delete(20).unwrap();
+        let sum = |tree: &BwTree<u64, Counted>| {
+            let before = CLONES.load(Ordering::SeqCst);
+            let sum = tree.range_fold(10, 30, 0, |sum, _, value| sum + value.0);
+            assert_eq!(CLONES.load(Ordering::SeqCst), before);
+            sum
+        };
+        let expected = (10..30).filter(|&key| key != 20).sum::<u64>();
+        // Folded over the delta chain, and then over the consolidated leaf.
+        assert_eq!(sum(&tree), expected);
+        tree.compact().unwrap();
+        assert_eq!(sum(&tree), expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "page size must be positive")]
+    fn test_range_pages_rejects_empty_pages() {
+        let tree: BwTree<u64, u64> = BwTree::new();
+        let _ = tree.range_pages(0, 10, 0);
+    }
+
+    #[test]
+    fn test_range_inclusive() {
+        let tree = tree_with_leaves(&[&[1, 3, 5], &[10, 12, 14]]);
+        let keys = |iter: RangeIter<'_, u64, u64>| iter.map(|(k, _)| k).collect::<Vec<_>>();
+        assert_eq!(keys(tree.range(3, 12)), vec![3, 5, 10]);
+        assert_eq!(keys(tree.range_inclusive(3, 12)), vec![3, 5, 10, 12]);
+        // The end key is the lowest key of the second leaf.
+        assert_eq!(keys(tree.range(1, 10)), vec![1, 3, 5]);
+        assert_eq!(keys(tree.range_inclusive(1, 10)), vec![1, 3, 5, 10]);
+        assert_eq!(keys(tree.range_inclusive(5, 5)), vec![5]);
+        assert_eq!(keys(tree.range_inclusive(6, 6)), Vec::<u64>::new());
+        assert_eq!(keys(tree.range_inclusive(12, 10)), Vec::<u64>::new());
+        assert_eq!(
+            keys(tree.range_inclusive(0, u64::MAX)),
+            vec![1, 3, 5, 10, 12, 14]
+        );
+
+        let tree = BwTree::new();
+        tree.insert(u64::MAX, 1).unwrap();
+        tree.insert(u64::MAX - 1, 2).unwrap();
+        assert_eq!(tree.range(0, u64::MAX).count(), 1);
+        assert_eq!(
+            tree.range_inclusive(u64::MAX, u64::MAX).collect::<Vec<_>>(),
+            vec![(u64::MAX, 1)]
+        );
+    }
+
+    #[test]
+    fn test_lower_and_upper_bound() {
+        let tree = tree_with_leaves(&[&[1, 3, 5], &[10, 12, 14]]);
+        let keys = |iter: RangeIter<'_, u64, u64>| iter.map(|(k, _)| k).collect::<Vec<_>>();
+        assert_eq!(keys(tree.lower_bound(3)), vec![3, 5, 10, 12, 14]);
+        assert_eq!(keys(tree.upper_bound(3)), vec![5, 10, 12, 14]);
+        // Keys that are not in the tree resume from the next greater key.
+        assert_eq!(keys(tree.lower_bound(6)), vec![10, 12, 14]);
+        assert_eq!(keys(tree.upper_bound(6)), vec![10, 12, 14]);
+        assert_eq!(keys(tree.lower_bound(0)), vec![1, 3, 5, 10, 12, 14]);
+        assert_eq!(keys(tree.upper_bound(14)), Vec::<u64>::new());
+        assert_eq!(keys(tree.upper_bound(u64::MAX)), Vec::<u64>::new());
+
+        // Paginate through the tree, resuming after the last key of a page.
+        let mut pages = Vec::new();
+        let mut page: Vec<_> = tree.lower_bound(0).take(2).map(|(k, _)| k).collect();
+        while let Some(&last) = page.last() {
+            pages.push(page);
+            page = tree.upper_bound(last).take(2).map(|(k, _)| k).collect();
+        }
+        assert_eq!(pages, vec![vec![1, 3], vec![5, 10], vec![12, 14]]);
+    }
+
+    #[test]
+    fn test_snapshot_is_unaffected_by_writes() {
+        let tree = BwTree::with_split_threshold(8);
+        for i in 0..100u64 {
+            tree.insert(i, i).unwrap();
+        }
+        let snapshot = tree.snapshot();
+        let before: Vec<_> = tree.iter().collect();
+        // Overwrite, delete, and add enough keys to consolidate, split, and
+        // merge the nodes the snapshot refers to.
+        for i in 0..100u64 {
+            if i % 2 == 0 {
+                tree.delete(i).unwrap();
+            } else {
+                tree.insert(i, i * 10).unwrap();
+            }
+        }
+        for i in 100..300u64 {
+            tree.insert(i, i).unwrap();
+        }
+        tree.verify().unwrap();
+
+        assert_eq!(snapshot.iter().collect::<Vec<_>>(), before);
+        assert!(snapshot.range(40, 60).map(|(k, _)| k).eq(40..60));
+        for i in 0..100 {
+            assert_eq!(snapshot.get(i), Some(&i));
+        }
+        assert!(!snapshot.contains_key(100));
+        drop(snapshot);
+
+        let snapshot = tree.snapshot();
+        assert!(snapshot.iter().eq(tree.iter()));
+        assert_eq!(snapshot.get(1), Some(&10));
+        assert_eq!(snapshot.get(2), None);
+    }
+
+    #[test]
+    fn test_snapshot_during_concurrent_writes() {
+        let tree = BwTree::new();
+        for i in 0..200u64 {
+            tree.insert(i, 0).unwrap();
+        }
+        let done = AtomicBool::new(false);
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for round in 1..50 {
+                    for i in 0..200u64 {
+                        tree.insert(i, round).unwrap();
+                    }
+                }
+                done.store(true, Ordering::SeqCst);
+            });
+            while !done.load(Ordering::SeqCst) {
+                let snapshot = tree.snapshot();
+                let first: Vec<_> = snapshot.iter().collect();
+                assert_eq!(first.len(), 200);
+                assert!(snapshot.iter().eq(first.iter().cloned()));
+            }
+        });
+    }
+
+    #[test]
+    fn test_iter() {
+        let tree = BwTree::new();
+        // Insert the keys 0..1000 in a scrambled order.
+        for i in 0..1000u64 {
+            let key = (i * 7919) % 1000;
+            assert_eq!(tree.insert(key, key * 2), Ok(None));
+        }
+        for key in (0..1000).filter(|key| key % 3 == 0) {
+            assert_eq!(tree.delete(key), Ok(true));
+        }
+        let expected: Vec<_> = (0..1000)
+            .filter(|key| key % 3 != 0)
+            .map(|key| (key, key * 2))
+            .collect();
+        assert_eq!(tree.iter().collect::<Vec<_>>(), expected);
+        assert_eq!((&tree).into_iter().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_iter_multiple_leaves() {
+        let tree = tree_with_leaves(&[&[1, 3, 5], &[10, 12, 14], &[20, 22]]);
+        assert_eq!(tree.delete(10), Ok(true));
+        assert_eq!(tree.insert(2, 2), Ok(None));
+        let keys: Vec<_> = tree.iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![1, 2, 3, 5, 12, 14, 20, 22]);
+    }
+
+    #[test]
+    fn test_into_sorted_vec() {
+        let tree = BwTree::with_split_threshold(8);
+        let mut expected = BTreeMap::new();
+        for key in (0..200u64).map(|i| i * 37 % 200) {
+            tree.insert(key, key * 2).unwrap();
+            expected.insert(key, key * 2);
+        }
+        for key in (0..200).step_by(3) {
+            assert_eq!(tree.delete(key), Ok(true));
+            expected.remove(&key);
+        }
+        let entries = tree.into_sorted_vec();
+        assert!(entries.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(entries, expected.into_iter().collect::<Vec<_>>());
+        assert!(BwTree::<u64, u64>::new().into_sorted_vec().is_empty());
+    }
+
+    #[test]
+    fn test_keys_and_values() {
+        let tree = BwTree::with_split_threshold(8);
+        for key in (0..200u64).map(|i| i * 37 % 200) {
+            tree.insert(key, key * 2).unwrap();
+        }
+        for key in (0..200).step_by(3) {
+            assert_eq!(tree.delete(key), Ok(true));
+        }
+        assert!(tree.stats().leaf_count > 1);
+        let keys: Vec<_> = tree.keys().collect();
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(
+            keys,
+            (0..200).filter(|key| key % 3 != 0).collect::<Vec<_>>()
+        );
+        let values: Vec<_> = tree.values().collect();
+        assert_eq!(values, keys.iter().map(|key| key * 2).collect::<Vec<_>>());
+        let tree: BwTree<u64, u64> = BwTree::new();
+        assert_eq!(tree.keys().next(), None);
+        assert_eq!(tree.values().next(), None);
+    }
+
+    #[test]
+    fn test_keys_do_not_clone_values() {
+        static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+        struct Counted(u64);
+
+        impl Clone for Counted {
+            fn clone(&self) -> Self {
+                CLONES.fetch_add(1, Ordering::SeqCst);
+                Counted(self.0)
+            }
+        }
+
+        let tree = BwTree::with_split_threshold(8);
+        for key in 0..100u64 {
+            tree.insert(key, Counted(key)).unwrap();
+        }
+        tree.delete(50).unwrap();
+        let before = CLONES.load(Ordering::SeqCst);
+        assert!(tree.keys().eq((0..100).filter(|&key| key != 50)));
+        assert_eq!(CLONES.load(Ordering::SeqCst), before);
+        // The values are cloned as they are yielded, and only then.
+        assert_eq!(tree.values().map(|value| value.0).sum::<u64>(), 4900);
+        assert_eq!(CLONES.load(Ordering::SeqCst), before + 99);
+    }
+
+    #[test]
+    fn test_key_histogram() {
+        let tree = BwTree::with_split_threshold(8);
+        // Nine out of ten keys fall into the lowest tenth of the key range.
+        for key in 0..900u64 {
+            tree.insert(key, key).unwrap();
+        }
+        for key in (1000..10_000u64).step_by(90) {
+            tree.insert(key, key).unwrap();
+        }
+        let histogram = tree.key_histogram(10);
+        assert_eq!(histogram.iter().sum::<usize>(), 1000);
+        assert!(histogram[0] >= 900);
+        assert!(histogram[1..].iter().all(|&count| count <= 20));
+
+        assert_eq!(tree.key_histogram(1), vec![1000]);
+        let empty: BwTree<i32, ()> = BwTree::new();
+        assert_eq!(empty.key_histogram(4), vec![0; 4]);
+        let signed: BwTree<i64, ()> = BwTree::new();
+        for key in [i64::MIN, -1, 0, 1, i64::MAX] {
+            signed.insert(key, ()).unwrap();
+        }
+        assert_eq!(signed.key_histogram(2), vec![2, 3]);
+    }
+
+    #[test]
+    fn test_leaf_histogram() {
+        let tree = BwTree::with_split_threshold(8);
+        for key in 0..1000u64 {
+            tree.insert(key, key).unwrap();
+        }
+        let histogram = tree.leaf_histogram();
+        assert_eq!(histogram.len(), tree.stats().leaf_count);
+        assert_eq!(histogram.iter().sum::<usize>(), 1000);
+        // A leaf may exceed the split threshold until its chain is consolidated.
+        let max = 8 + DEFAULT_CONSOLIDATION_THRESHOLD;
+        assert!(histogram.iter().all(|&count| count <= max));
+        // Deleting a range empties the leaves in the middle, which are merged
+        // once they are consolidated, leaving the skew visible at the ends.
+        tree.delete_range(8, 992).unwrap();
+        let histogram = tree.leaf_histogram();
+        assert_eq!(histogram.iter().sum::<usize>(), 16);
+        assert!(histogram.len() < 1000 / 8);
+        assert_eq!(BwTree::<String, ()>::new().leaf_histogram(), vec![0]);
+    }
+
+    #[test]
+    fn test_drain() {
+        let tree = BwTree::with_split_threshold(8);
+        for key in (0..200u64).map(|i| i * 37 % 200) {
+            tree.insert(key, key * 2).unwrap();
+        }
+        for key in (0..200).step_by(3) {
+            assert_eq!(tree.delete(key), Ok(true));
+        }
+        let entries: Vec<_> = tree.drain().map(Result::unwrap).collect();
+        let expected: Vec<_> = (0..200)
+            .filter(|key| key % 3 != 0)
+            .map(|key| (key, key * 2))
+            .collect();
+        assert_eq!(entries, expected);
+        assert!(tree.is_empty());
+        assert_eq!(tree.iter().next(), None);
+        assert_eq!(tree.drain().next(), None);
+        tree.verify().unwrap();
+        // The tree is still usable after being drained.
+        tree.insert(7, 7).unwrap();
+        assert_eq!(tree.get(7).as_deref(), Some(&7));
+    }
+
+    #[test]
+    fn test_split_off() {
+        let tree = BwTree::with_split_threshold(8);
+        for key in (0..200u64).map(|i| i * 37 % 200) {
+            tree.insert(key, key * 2).unwrap();
+        }
+        for key in (0..200).step_by(3) {
+            assert_eq!(tree.delete(key), Ok(true));
+        }
+        let original = tree.into_sorted_vec();
+        let upper = tree.split_off(100).unwrap();
+        let (below, above): (Vec<_>, Vec<_>) = original.into_iter().partition(|(k, _)| *k < 100);
+        assert_eq!(tree.into_sorted_vec(), below);
+        assert_eq!(upper.into_sorted_vec(), above);
+        assert_eq!(tree.len() + upper.len(), 133);
+        assert_eq!(tree.get(99).as_deref(), None);
+        assert_eq!(tree.get(98).as_deref(), Some(&196));
+        assert_eq!(upper.get(100).as_deref(), Some(&200));
+        tree.verify().unwrap();
+        upper.verify().unwrap();
+
+        // Both trees take writes on either side of the boundary afterwards.
+        tree.insert(150, 1).unwrap();
+        upper.insert(50, 1).unwrap();
+        assert_eq!(tree.get(150).as_deref(), Some(&1));
+        assert_eq!(upper.get(50).as_deref(), Some(&1));
+    }
+
+    #[test]
+    fn test_split_off_with_full_mapping_table() {
+        let tree = BwTree::with_split_threshold(4);
+        tree.exhaust_node_ids();
+        // The leaf can't be split, so it grows oversized, and the deletes of
+        // the split only defer its consolidation.
+        for key in 0..40u64 {
+            assert_eq!(tree.insert(key, key), Ok(None));
+        }
+        let upper = tree.split_off(10).unwrap();
+        assert!(tree.iter().eq((0..10).map(|key| (key, key))));
+        assert!(upper.iter().eq((10..40).map(|key| (key, key))));
+        assert_eq!(tree.len(), 10);
+    }
+
+    #[test]
+    fn test_split_off_at_the_ends() {
+        let tree = BwTree::with_split_threshold(8);
+        for key in 10..50u64 {
+            tree.insert(key, key).unwrap();
+        }
+        let empty = tree.split_off(50).unwrap();
+        assert!(empty.is_empty());
+        assert_eq!(tree.len(), 40);
+        let everything = tree.split_off(KeyType::minimum()).unwrap();
+        assert!(tree.is_empty());
+        assert!(everything.iter().eq((10..50).map(|key| (key, key))));
+        everything.verify().unwrap();
+    }
+
+    #[test]
+    fn test_drain_keeps_entries_deleted_despite_full_mapping_table() {
+        let tree = BwTree::with_split_threshold(4);
+        tree.exhaust_node_ids();
+        for key in 0..40u64 {
+            let _ = tree.insert(key, key);
+        }
+        let drained: Result<Vec<_>, _> = tree.drain().collect();
+        assert_eq!(drained, Ok((0..40).map(|key| (key, key)).collect()));
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn test_drain_partially() {
+        let tree = BwTree::with_split_threshold(8);
+        for key in 0..100u64 {
+            tree.insert(key, key).unwrap();
+        }
+        let mut drain = tree.drain();
+        let drained: Vec<_> = drain.by_ref().take(30).map(Result::unwrap).collect();
+        assert_eq!(drained, (0..30).map(|key| (key, key)).collect::<Vec<_>>());
+        drop(drain);
+        assert_eq!(tree.len(), 70);
+        assert!(tree.iter().eq((30..100).map(|key| (key, key))));
+    }
+
+    #[test]
+    fn test_concurrent_drains() {
+        let tree = BwTree::with_split_threshold(8);
+        for key in 0..2000u64 {
+            tree.insert(key, key).unwrap();
+        }
+        let drained: Vec<Vec<_>> = std::thread::scope(|s| {
+            let drains: Vec<_> = (0..4)
+                .map(|_| s.spawn(|| tree.drain().map(Result::unwrap).collect()))
+                .collect();
+            drains
+                .into_iter()
+                .map(|drain| drain.join().unwrap())
+                .collect()
+        });
+        // Every entry is yielded by exactly one of the drains.
+        let mut keys: Vec<_> = drained.concat().into_iter().map(|(key, _)| key).collect();
+        keys.sort_unstable();
+        assert!(keys.into_iter().eq(0..2000));
+        assert!(tree.is_empty());
+        tree.verify().unwrap();
+    }
+
+    #[test]
+    fn test_signed_keys() {
+        let tree = BwTree::new();
+        for key in [5, -3, 0, i32::MIN, -100, i32::MAX, 42] {
+            assert_eq!(tree.insert(key, key), Ok(None));
+        }
+        assert_eq!(tree.get(-3).as_deref(), Some(&-3));
+        assert_eq!(tree.get(i32::MIN).as_deref(), Some(&i32::MIN));
+        assert_eq!(tree.get(-4).as_deref(), None);
+        let keys: Vec<_> = tree.iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![i32::MIN, -100, -3, 0, 5, 42, i32::MAX]);
+        let keys: Vec<_> = tree.range(-100, 1).map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![-100, -3, 0]);
+    }
+
+    #[test]
+    fn test_signed_keys_route_across_leaves() {
+        let tree: BwTree<i32, i32> = BwTree::new();
+        let second_leaf_id = tree.get_next_node_id().unwrap();
+        let first_leaf = LeafNode {
+            count: 2,
+            keys: vec![-50, -10],
+            values: vec![-50, -10],
+            high_key: None,
+            right_sibling: None,
+            version: 0,
+        };
+        let second_leaf = LeafNode {
+            count: 2,
+            keys: vec![0, 10],
+            values: vec![0, 10],
+            high_key: None,
+            right_sibling: None,
+            version: 0,
+        };
+        let mut root = InnerNode::new();
+        root.insert(&tree.order, KeyType::minimum(), FIRST_LEAF_NODE_ID);
+        root.insert(&tree.order, 0, second_leaf_id);
+        tree.mapping_table
+            .insert(FIRST_LEAF_NODE_ID, Node::Leaf(first_leaf));
+        tree.mapping_table
+            .insert(second_leaf_id, Node::Leaf(second_leaf));
+        tree.mapping_table.insert(tree.root_id(), Node::Inner(root));
+
+        assert_eq!(tree.insert(-20, -20), Ok(None));
+        assert_eq!(tree.insert(5, 5), Ok(None));
+        assert_eq!(tree.find_leaf(&-20), FIRST_LEAF_NODE_ID);
+        assert_eq!(tree.find_leaf(&i32::MIN), FIRST_LEAF_NODE_ID);
+        assert_eq!(tree.find_leaf(&5), second_leaf_id);
+        assert_eq!(tree.get(-50).as_deref(), Some(&-50));
+        assert_eq!(tree.get(-20).as_deref(), Some(&-20));
+        assert_eq!(tree.get(5).as_deref(), Some(&5));
+        let keys: Vec<_> = tree.iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![-50, -20, -10, 0, 5, 10]);
+    }
+
+    #[test]
+    fn test_string_keys() {
+        let tree = BwTree::new();
+        for key in ["pear", "apple", "", "banana", "apricot"] {
+            assert_eq!(tree.insert(key.to_string(), key.len()), Ok(None));
+        }
+        assert_eq!(tree.get("apple".to_string()).as_deref(), Some(&5));
+        assert_eq!(tree.get("".to_string()).as_deref(), Some(&0));
+        assert_eq!(tree.get("cherry".to_string()).as_deref(), None);
+        let keys: Vec<_> = tree.iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec!["", "apple", "apricot", "banana", "pear"]);
+    }
+
+    #[test]
+    fn test_char_keys() {
+        let tree = BwTree::with_split_threshold(8);
+        for c in "Bw-Tree: latch-free, ünïcödé 🌲".chars() {
+            tree.insert(c, u32::from(c)).unwrap();
+        }
+        assert_eq!(tree.get('ü').as_deref(), Some(&0xFC));
+        assert_eq!(tree.get(char::minimum()).as_deref(), None);
+        let keys: Vec<_> = tree.keys().collect();
+        let mut expected: Vec<_> = "Bw-Tree: latch-free, ünïcödé 🌲".chars().collect();
+        expected.sort_unstable();
+        expected.dedup();
+        assert_eq!(keys, expected);
+        assert!(tree.values().is_sorted());
+        let lower: String = tree.range('a', '{').map(|(c, _)| c).collect();
+        assert_eq!(lower, "acdefhlnrtw");
+        assert_eq!(tree.iter().last().map(|(c, _)| c), Some('🌲'));
+    }
+
+    #[test]
+    fn test_str_keys() {
+        let tree = BwTree::new();
+        for key in ["pear", "apple", "banana"] {
+            assert_eq!(tree.insert(key, key.len()), Ok(None));
+        }
+        assert_eq!(tree.get("banana").as_deref(), Some(&6));
+        let keys: Vec<_> = tree.range("apple", "pear").map(|(k, _)| k).collect();
+        assert_eq!(keys, vec!["apple", "banana"]);
+    }
+
+    #[test]
+    fn test_vec_keys() {
+        let tree: BwTree<Vec<u8>, u64> = BwTree::new();
+        assert_eq!(tree.insert(vec![2, 1], 21), Ok(None));
+        assert_eq!(tree.insert(vec![1], 1), Ok(None));
+        assert_eq!(tree.insert(vec![1, 0], 10), Ok(None));
+        assert_eq!(tree.get(vec![1, 0]).as_deref(), Some(&10));
+        let keys: Vec<_> = tree.iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![vec![1], vec![1, 0], vec![2, 1]]);
+    }
+
+    #[test]
+    fn test_composite_keys() {
+        let tree: BwTree<(u64, u32), u64> = BwTree::with_split_threshold(8);
+        for a in (0..10u64).rev() {
+            for b in [3u32, 1, 2, 0] {
+                assert_eq!(tree.insert((a, b), a * 10 + u64::from(b)), Ok(None));
+            }
+        }
+        assert_eq!(tree.get((4, 2)).as_deref(), Some(&42));
+        assert_eq!(tree.get(<(u64, u32)>::minimum()).as_deref(), Some(&0));
+        // The range from (2, 2) up to (4, 1) spans the keys with every second
+        // component in between.
+        let keys: Vec<_> = tree.range((2, 2), (4, 1)).map(|(k, _)| k).collect();
+        assert_eq!(
+            keys,
+            vec![(2, 2), (2, 3), (3, 0), (3, 1), (3, 2), (3, 3), (4, 0)]
+        );
+        let all: Vec<_> = tree.iter().map(|(_, v)| v).collect();
+        assert_eq!(
+            all,
+            (0..10)
+                .flat_map(|a| (0..4).map(move |b| a * 10 + b))
+                .collect::<Vec<_>>()
+        );
+
+        let triples: BwTree<(u8, u64, u8), ()> = BwTree::new();
+        for key in [(1, 0, 0), (0, 5, 1), (0, 5, 0), (0, u64::MAX, 0)] {
+            triples.insert(key, ()).unwrap();
+        }
+        let keys: Vec<_> = triples.iter().map(|(k, _)| k).collect();
+        assert_eq!(
+            keys,
+            vec![(0, 5, 0), (0, 5, 1), (0, u64::MAX, 0), (1, 0, 0)]
+        );
+    }
+
+    #[test]
+    fn test_byte_array_keys() {
+        let tree: BwTree<[u8; 16], u64> = BwTree::with_split_threshold(8);
+        // Big-endian bytes order like the numbers they encode.
+        let key = |i: u64| {
+            let mut key = [0; 16];
+            key[8..].copy_from_slice(&i.to_be_bytes());
+            key
+        };
+        for i in (0..100).rev() {
+            assert_eq!(tree.insert(key(i), i), Ok(None));
+        }
+        assert_eq!(tree.get(key(42)).as_deref(), Some(&42));
+        assert_eq!(tree.get(<[u8; 16]>::minimum()).as_deref(), Some(&0));
+        assert_eq!(tree.get([0xFF; 16]).as_deref(), None);
+        assert!(tree.range(key(10), key(20)).map(|(_, v)| v).eq(10..20));
+        assert!(tree.iter().map(|(_, v)| v).eq(0..100));
+
+        // A higher leading byte wins regardless of the bytes after it.
+        let mut high = [0; 16];
+        high[0] = 1;
+        tree.insert(high, 1000).unwrap();
+        assert_eq!(tree.iter().last(), Some((high, 1000)));
+    }
+
+    #[test]
+    fn test_try_get_dangling_child() {
+        let tree = BwTree::new();
+        tree.insert(1, 10).unwrap();
+        assert_eq!(tree.try_get(1).unwrap().as_deref(), Some(&10));
+        for bad_id in [1000, usize::MAX] {
+            let mut root = InnerNode::new();
+            root.insert(&tree.order, KeyType::minimum(), bad_id);
+            let old = tree.mapping_table.swap(tree.root_id(), Node::Inner(root));
+            unsafe { free_node::<u64, u64>(old as *mut u8) };
+            assert_eq!(
+                tree.try_get(1).map(|value| value.as_deref().copied()),
+                Err(BwError::DanglingNode(bad_id))
+            );
+        }
+    }
+
+    #[test]
+    fn test_try_get_owned_dangling_child() {
+        let tree = BwTree::new();
+        tree.insert(1, 10).unwrap();
+        assert_eq!(tree.try_get_owned(1), Ok(Some(10)));
+        assert_eq!(tree.try_get_owned(2), Ok(None));
+        let mut root = InnerNode::new();
+        root.insert(&tree.order, KeyType::minimum(), FIRST_LEAF_NODE_ID);
+        root.insert(&tree.order, 100, 1000);
+        let old = tree.mapping_table.swap(tree.root_id(), Node::Inner(root));
+        unsafe { free_node::<u64, u64>(old as *mut u8) };
+        // Only lookups that are routed to the dangling child fail.
+        assert_eq!(tree.try_get_owned(1), Ok(Some(10)));
+        assert_eq!(tree.try_get_owned(100), Err(BwError::DanglingNode(1000)));
+    }
+
+    #[test]
+    fn test_get_many() {
+        let tree = BwTree::with_split_threshold(8);
+        for key in (0..200u64).step_by(2) {
+            tree.insert(key, key * 10).unwrap();
+        }
+        let keys = [150, 3, 0, 150, 198, 1000, 42, 3, 64, 199, 0];
+        let expected: Vec<_> = keys.iter().map(|&key| tree.get_owned(key)).collect();
+        assert_eq!(tree.get_many(&keys), expected);
+        assert_eq!(expected[0], Some(1500));
+        assert_eq!(expected[1], None);
+
+        let all: Vec<_> = (0..200).rev().collect();
+        let values = tree.get_many(&all);
+        assert!(all
+            .iter()
+            .zip(values)
+            .all(|(key, value)| value == (key % 2 == 0).then_some(key * 10)));
+        assert_eq!(tree.get_many(&[]), Vec::<Option<u64>>::new());
+    }
+
+    #[test]
+    fn test_contains_key() {
+        let tree = BwTree::new();
+        assert!(!tree.contains_key(1));
+        assert_eq!(tree.insert(1, "A"), Ok(None));
+        assert_eq!(tree.insert(2, "B"), Ok(None));
+        assert!(tree.contains_key(1));
+        assert!(tree.contains_key(2));
+        assert_eq!(tree.delete(1), Ok(true));
+        assert!(!tree.contains_key(1));
+        assert!(tree.contains_key(2));
+        assert!(!tree.contains_key(3));
+    }
+
+    #[test]
+    fn test_insert_returns_previous_value() {
+        let tree = BwTree::new();
+        assert_eq!(tree.insert(1, "A"), Ok(None));
+        assert_eq!(tree.insert(1, "B"), Ok(Some("A")));
+        assert_eq!(tree.insert(1, "C"), Ok(Some("B")));
+        assert_eq!(tree.insert(2, "D"), Ok(None));
+        assert_eq!(tree.delete(1), Ok(true));
+        assert_eq!(tree.insert(1, "E"), Ok(None));
+        assert_eq!(tree.get(1).as_deref(), Some(&"E"));
+    }
+
+    #[test]
+    fn test_get_owned() {
+        let tree = BwTree::with_consolidation_threshold(2);
+        assert_eq!(tree.insert(1, "A".to_string()), Ok(None));
+        assert_eq!(tree.insert(2, "B".to_string()), Ok(None));
+        let value = tree.get_owned(1);
+        assert_eq!(value, Some("A".to_string()));
+        // The owned copy outlives consolidation of the node it came from.
+        for i in 3..10 {
+            assert_eq!(tree.insert(i, i.to_string()), Ok(None));
+        }
+        assert_eq!(tree.insert(1, "C".to_string()), Ok(Some("A".to_string())));
+        assert_eq!(value, Some("A".to_string()));
+        assert_eq!(tree.get_owned(1), Some("C".to_string()));
+        assert_eq!(tree.get_owned(10), None);
+    }
+
+    #[test]
+    fn test_len() {
+        let tree = BwTree::with_consolidation_threshold(3);
+        assert!(tree.is_empty());
+        assert_eq!(tree.insert(1, "A"), Ok(None));
+        assert_eq!(tree.insert(2, "B"), Ok(None));
+        assert_eq!(tree.len(), 2);
+        // Replacing an existing value doesn't change the count.
+        assert_eq!(tree.insert(1, "C"), Ok(Some("A")));
+        assert_eq!(tree.update(2, "D"), Ok(Some("B")));
+        assert_eq!(tree.len(), 2);
+        assert_eq!(tree.delete(1), Ok(true));
+        assert_eq!(tree.len(), 1);
+        // Deleting a missing key doesn't change the count.
+        assert_eq!(tree.delete(1), Ok(false));
+        assert_eq!(tree.delete(3), Ok(false));
+        assert_eq!(tree.len(), 1);
+        assert_eq!(tree.insert(1, "E"), Ok(None));
+        assert_eq!(tree.len(), 2);
+        assert_eq!(tree.delete(1), Ok(true));
+        assert_eq!(tree.delete(2), Ok(true));
+        assert_eq!(tree.len(), 0);
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn test_cas_contention() {
+        // Every thread repeatedly replaces the same leaf with a copy holding
+        // an incremented counter. Lost updates would show up as a final count
+        // lower than the total number of increments.
+        let tree: BwTree<u64, u64> = BwTree::new();
+        let threads = 4;
+        let increments = 1000;
+        std::thread::scope(|s| {
+            for _ in 0..threads {
+                s.spawn(|| {
+                    for _ in 0..increments {
+                        let _guard = tree.epoch.pin();
+                        let mut expected = tree.mapping_table.load(FIRST_LEAF_NODE_ID);
+                        loop {
+                            let count = match unsafe { &*expected } {
+                                Node::Leaf(leaf) => leaf.values.first().copied().unwrap_or(0),
+                                _ => unreachable!(),
+                            };
+                            let leaf = LeafNode {
+                                count: 1,
+                                keys: vec![0],
+                                values: vec![count + 1],
+                                high_key: None,
+                                right_sibling: None,
+                                version: 0,
+                            };
+                            match tree.mapping_table.cas(
+                                FIRST_LEAF_NODE_ID,
+                                expected,
+                                Node::Leaf(leaf),
+                            ) {
+                                Ok(()) => {
+                                    unsafe {
+                                        tree.epoch.defer(expected as *mut u8, free_node::<u64, u64>)
+                                    };
+                                    break;
+                                }
+                                Err(actual) => expected = actual,
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(tree.get(0).as_deref(), Some(&(threads * increments)));
+    }
+
+    #[test]
+    fn test_mapping_table_grows() {
+        let table: MappingTable<u64, u64> = MappingTable::new();
+        let ids = 10 * FIRST_SEGMENT_SIZE;
+        for id in 0..ids {
+            let leaf = LeafNode {
+                count: 1,
+                keys: vec![id as u64],
+                values: vec![id as u64],
+                high_key: None,
+                right_sibling: None,
+                version: 0,
+            };
+            assert_eq!(
+                table.insert(id, Node::Leaf(leaf)),
+                Some(std::ptr::null_mut())
+            );
+        }
+        for id in 0..ids {
+            match table.get(id) {
+                Node::Leaf(leaf) => assert_eq!(leaf.keys, vec![id as u64]),
+                _ => unreachable!(),
+            }
+        }
+        assert!(table.load(ids).is_null());
+        assert!(table.load(1 << 40).is_null());
+    }
+
+    #[test]
+    fn test_mapping_table_locate() {
+        type Table = MappingTable<u64, u64>;
+        assert_eq!(Table::locate(0), (0, 0));
+        assert_eq!(
+            Table::locate(FIRST_SEGMENT_SIZE - 1),
+            (0, FIRST_SEGMENT_SIZE - 1)
+        );
+        assert_eq!(Table::locate(FIRST_SEGMENT_SIZE), (1, 0));
+        assert_eq!(
+            Table::locate(3 * FIRST_SEGMENT_SIZE - 1),
+            (1, 2 * FIRST_SEGMENT_SIZE - 1)
+        );
+        assert_eq!(Table::locate(3 * FIRST_SEGMENT_SIZE), (2, 0));
+        assert_eq!(
+            Table::locate(usize::MAX - FIRST_SEGMENT_SIZE).0,
+            SEGMENT_COUNT - 1
+        );
+    }
+
+    #[test]
+    fn test_tree_beyond_first_segment() {
+        let tree = BwTree::new();
+        for _ in 0..2 * FIRST_SEGMENT_SIZE {
+            tree.get_next_node_id().unwrap();
+        }
+        let leaf_id = tree.get_next_node_id().unwrap();
+        let mut root = InnerNode::new();
+        root.insert(&tree.order, KeyType::minimum(), FIRST_LEAF_NODE_ID);
+        root.insert(&tree.order, 100, leaf_id);
+        tree.mapping_table
+            .insert(leaf_id, Node::Leaf(LeafNode::new()));
+        tree.mapping_table.insert(tree.root_id(), Node::Inner(root));
+        assert_eq!(tree.insert(1, 1), Ok(None));
+        assert_eq!(tree.insert(200, 200), Ok(None));
+        assert_eq!(tree.find_leaf(&200), leaf_id);
+        assert_eq!(tree.get(1).as_deref(), Some(&1));
+        assert_eq!(tree.get(200).as_deref(), Some(&200));
+    }
+
+    #[test]
+    fn test_split_leaf() {
+        let tree = BwTree::with_split_threshold(4);
+        for i in 0..100u64 {
+            assert_eq!(tree.insert(i, i), Ok(None));
+        }
+        match tree.mapping_table.get(tree.root_id()).inner(&tree.order) {
+            Some(root) => assert!(root.children.len() > 1),
+            _ => panic!("root is not an inner node"),
+        }
+        for i in 0..100 {
+            assert_eq!(tree.get(i).as_deref(), Some(&i));
+        }
+        assert_eq!(tree.len(), 100);
+        assert!(tree.iter().map(|(k, _)| k).eq(0..100));
+        tree.verify().unwrap();
+    }
+
+    #[test]
+    fn test_split_delta_redirects_to_right_sibling() {
+        let tree: BwTree<u64, &str> = BwTree::new();
+        let right_id = tree.get_next_node_id().unwrap();
+        let left = LeafNode {
+            count: 3,
+            keys: vec![1, 5, 10],
+            values: vec!["A", "B", "stale"],
+            high_key: None,
+            right_sibling: None,
+            version: 0,
+        };
+        let right = LeafNode {
+            count: 1,
+            keys: vec![10],
+            values: vec!["C"],
+            high_key: None,
+            right_sibling: None,
+            version: 0,
+        };
+        tree.mapping_table.insert(right_id, Node::Leaf(right));
+        // The separator is deliberately not posted to the root, as if the
+        // split were still in progress.
+        let base = Box::into_raw(Box::new(Node::Leaf(left)));
+        let split = DeltaNode::new(unsafe { &*base });
+        split.split(10, right_id);
+        tree.mapping_table
+            .insert(FIRST_LEAF_NODE_ID, Node::Delta(split));
+
+        assert_eq!(tree.get(5).as_deref(), Some(&"B"));
+        assert_eq!(tree.get(10).as_deref(), Some(&"C"));
+        assert_eq!(tree.insert(12, "D"), Ok(None));
+        assert_eq!(
+            tree.mapping_table
+                .get(right_id)
+                .get(&tree.order, &12, &tree.mapping_table),
+            Ok(Some(&"D"))
+        );
+        assert_eq!(
+            tree.iter().collect::<Vec<_>>(),
+            vec![(1, "A"), (5, "B"), (10, "C"), (12, "D")]
+        );
+
+        // Consolidation keeps the redirect in the new leaf.
+        tree.consolidate(FIRST_LEAF_NODE_ID).unwrap();
+        match tree.mapping_table.get(FIRST_LEAF_NODE_ID) {
+            Node::Leaf(leaf) => {
+                assert_eq!(leaf.keys, vec![1, 5]);
+                assert_eq!(leaf.high_key, Some(10));
+                assert_eq!(leaf.right_sibling, Some(right_id));
+            }
+            _ => panic!("node was not consolidated"),
+        }
+        assert_eq!(tree.get(10).as_deref(), Some(&"C"));
+        assert_eq!(tree.get(12).as_deref(), Some(&"D"));
+    }
+
+    #[test]
+    fn test_index_term_delta_routes_to_new_child() {
+        let tree = tree_with_leaves(&[&[1, 3, 5], &[10, 12, 14], &[20, 22]]);
+        // Drop the index term of the last leaf from the root, as if the
+        // leaf had just been split off its left sibling.
+        let mut root = tree
+            .mapping_table
+            .get(tree.root_id())
+            .inner(&tree.order)
+            .unwrap()
+            .into_owned();
+        let right_id = root.children.pop().unwrap();
+        root.keys.pop();
+        let middle_id = root.children[1];
+        let old = tree.mapping_table.swap(tree.root_id(), Node::Inner(root));
+        unsafe { free_node::<u64, u64>(old as *mut u8) };
+        assert_eq!(tree.find_leaf(&22), middle_id);
+
+        tree.post_index_term(20, right_id);
+        let Node::IndexDelta(delta) = tree.mapping_table.get(tree.root_id()) else {
+            panic!("root has no index-term delta");
+        };
+        assert_eq!(delta.base().keys, vec![0, 10]);
+        assert_eq!(delta.base().children, vec![FIRST_LEAF_NODE_ID, middle_id]);
+        assert_eq!(delta.terms.len(), 1);
+
+        assert_eq!(tree.find_leaf(&14), middle_id);
+        assert_eq!(tree.find_leaf(&20), right_id);
+        assert_eq!(tree.find_leaf(&u64::MAX), right_id);
+        assert_eq!(tree.find_leaf_with_upper_bound(&12), (middle_id, Some(20)));
+        assert_eq!(tree.find_leaf_before(Some(&20)), (middle_id, 10));
+        assert_eq!(tree.find_leaf_before(None), (right_id, 20));
+        assert_eq!(tree.get(22).as_deref(), Some(&22));
+        assert_eq!(
+            tree.mapping_table
+                .get(tree.root_id())
+                .inner(&tree.order)
+                .unwrap()
+                .keys,
+            vec![0, 10, 20]
+        );
+        assert!(tree
+            .iter()
+            .map(|(k, _)| k)
+            .eq([1, 3, 5, 10, 12, 14, 20, 22]));
+        assert!(tree
+            .iter_rev()
+            .map(|(k, _)| k)
+            .eq([22, 20, 14, 12, 10, 5, 3, 1]));
+        tree.verify().unwrap();
+    }
+
+    #[test]
+    fn test_index_term_deltas_are_consolidated() {
+        let tree = BwTree::with_split_threshold(4);
+        let mut consolidated = false;
+        for i in 0..200u64 {
+            assert_eq!(tree.insert(i, i), Ok(None));
+            match tree.mapping_table.get(tree.root_id()) {
+                Node::IndexDelta(delta) => {
+                    assert!(delta.terms.len() <= DEFAULT_CONSOLIDATION_THRESHOLD)
+                }
+                Node::Inner(root) => consolidated |= root.children.len() > 1,
+                _ => panic!("root is not an inner node"),
+            }
+        }
+        assert!(consolidated);
+        for i in 0..200 {
+            assert_eq!(tree.get(i).as_deref(), Some(&i));
+        }
+        tree.verify().unwrap();
+    }
+
+    #[test]
+    fn test_merge_underfull_leaves() {
+        let tree = BwTree::with_thresholds(2, 8, 2);
+        let leaf_count = |tree: &BwTree<u64, u64>| tree.stats().leaf_count;
+        for i in 0..200 {
+            assert_eq!(tree.insert(i, i), Ok(None));
+        }
+        let before = leaf_count(&tree);
+        assert!(before > 10);
+
+        for i in 0..200 {
+            if i % 20 != 0 {
+                assert_eq!(tree.delete(i), Ok(true));
+            }
+        }
+        let after = leaf_count(&tree);
+        assert!(after < before);
+        // Every merge removes a leaf and retires its ID.
+        assert_eq!(tree.free_node_ids.len(), before - after);
+
+        for i in 0..200 {
+            assert_eq!(tree.get(i).as_deref(), (i % 20 == 0).then_some(&i));
+        }
+        assert_eq!(tree.len(), 10);
+        assert!(tree.iter().map(|(k, _)| k).eq((0..200).step_by(20)));
+        tree.verify().unwrap();
+    }
+
+    #[test]
+    fn test_from_sorted() {
+        let loaded = BwTree::from_sorted((0..1000u64).map(|i| (i * 2, i))).unwrap();
+        let inserted = BwTree::new();
+        for i in 0..1000u64 {
+            inserted.insert(i * 2, i).unwrap();
+        }
+        match loaded.mapping_table.get(loaded.root_id()) {
+            Node::Inner(root) => assert!(root.children.len() > 1),
+            _ => panic!("root is not an inner node"),
+        }
+        assert_eq!(loaded.len(), inserted.len());
+        assert!(loaded.iter().eq(inserted.iter()));
+        for i in 0..2000 {
+            assert_eq!(loaded.get(i).as_deref(), inserted.get(i).as_deref());
+        }
+
+        // The bulk-loaded tree accepts writes like any other.
+        for i in 0..1000u64 {
+            assert_eq!(loaded.insert(i * 2 + 1, i), Ok(None));
+            assert_eq!(inserted.insert(i * 2 + 1, i), Ok(None));
+        }
+        assert!(loaded.iter().eq(inserted.iter()));
+    }
+
+    #[test]
+    fn test_from_sorted_empty() {
+        let tree: BwTree<u64, u64> = BwTree::from_sorted(std::iter::empty()).unwrap();
+        assert!(tree.is_empty());
+        assert_eq!(tree.iter().next(), None);
+        assert_eq!(tree.insert(1, 1), Ok(None));
+        assert_eq!(tree.get(1).as_deref(), Some(&1));
+    }
+
+    #[test]
+    fn test_from_sorted_rejects_unsorted_input() {
+        let err = BwTree::from_sorted([(1, "A"), (3, "B"), (2, "C")])
+            .err()
+            .unwrap();
+        assert_eq!(err.index(), 2);
+        // Duplicate keys are not strictly ascending either.
+        let err = BwTree::from_sorted([(1, "A"), (1, "B")]).err().unwrap();
+        assert_eq!(err.index(), 1);
+    }
+
+    #[test]
+    fn test_write_defers_split_on_full_mapping_table() {
+        let tree = BwTree::with_split_threshold(4);
+        tree.exhaust_node_ids();
+        assert_eq!(tree.get_next_node_id(), Err(BwError::MappingTableFull));
+
+        // The ninth write consolidates the leaf, which then needs a split.
+        for i in 0..8 {
+            assert_eq!(tree.insert(i, i), Ok(None));
+        }
+        assert_eq!(tree.insert(8, 8), Ok(None));
+        assert_eq!(tree.insert(8, 9), Ok(Some(8)));
+        assert_eq!(tree.update(8, 8), Ok(Some(9)));
+        assert_eq!(tree.len(), 9);
+        for i in 0..9 {
+            assert_eq!(tree.get(i).as_deref(), Some(&i));
+        }
+
+        // Once an ID is available again, a later write splits the leaf.
+        tree.free_node_id(100);
+        for i in 9..18 {
+            assert_eq!(tree.insert(i, i), Ok(None));
+        }
+        match tree.mapping_table.get(tree.root_id()).inner(&tree.order) {
+            Some(root) => assert_eq!(root.children, vec![FIRST_LEAF_NODE_ID, 100]),
+            _ => panic!("root is not an inner node"),
+        }
+    }
+
+    /// Writes `keys` straight to the delta chain of the node responsible for
+    /// the first of them, with every key as its own value, without ever
+    /// consolidating the chain.
+    fn push_unconsolidated(tree: &BwTree<u64, u64>, keys: std::ops::Range<u64>) {
+        let _guard = tree.epoch.pin();
+        let (_, delta) = tree.delta_for(&keys.start).unwrap();
+        for key in keys {
+            let old = delta.upsert(&tree.order, key, key, &tree.mapping_table);
+            if old.ok().flatten().is_none() {
+                tree.len.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_to_chain_at_cap_with_full_mapping_table() {
+        let tree: BwTree<u64, u64> = BwTree::new();
+        // The chain is at the cap, and its leaf needs a split that no node ID
+        // is left for.
+        let n = DEFAULT_MAX_CHAIN_LEN as u64 + 1;
+        push_unconsolidated(&tree, 0..n);
+        tree.exhaust_node_ids();
+        assert_eq!(tree.insert(0, 100), Ok(Some(0)));
+        assert_eq!(tree.delete(1), Ok(true));
+        assert_eq!(tree.insert(n, n), Ok(None));
+        assert_eq!(tree.get(0).as_deref(), Some(&100));
+        assert_eq!(tree.get(1).as_deref(), None);
+        assert_eq!(tree.len(), n as usize);
+        assert_eq!(tree.iter().count(), n as usize);
+    }
+
+    #[test]
+    fn test_write_reports_retry_exhaustion() {
+        let tree: BwTree<u64, u64> = BwTree::new();
+        // A leaf that redirects every key to itself can never be written to.
+        let leaf = LeafNode {
+            count: 0,
+            keys: vec![],
+            values: vec![],
+            high_key: Some(0),
+            right_sibling: Some(FIRST_LEAF_NODE_ID),
+            version: 0,
+        };
+        tree.mapping_table
+            .insert(FIRST_LEAF_NODE_ID, Node::Leaf(leaf));
+        assert_eq!(tree.insert(1, 1), Err(BwError::Retry));
+        assert_eq!(tree.update(1, 1), Err(BwError::Retry));
+        assert_eq!(tree.delete(1), Err(BwError::Retry));
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn test_insert_if_absent() {
+        let tree = BwTree::new();
+        assert_eq!(tree.insert_if_absent(1, "A"), Ok(Ok(())));
+        assert_eq!(tree.insert_if_absent(1, "B"), Ok(Err("A")));
+        assert_eq!(tree.get(1).as_deref(), Some(&"A"));
+        assert_eq!(tree.delete(1), Ok(true));
+        assert_eq!(tree.insert_if_absent(1, "C"), Ok(Ok(())));
+        assert_eq!(tree.get(1).as_deref(), Some(&"C"));
+        assert_eq!(tree.len(), 1);
+    }
+
+    #[test]
+    fn test_insert_if_absent_race() {
+        let tree = BwTree::new();
+        for key in 0..100u64 {
+            let barrier = std::sync::Barrier::new(2);
+            let results: Vec<_> = std::thread::scope(|s| {
+                let racers: Vec<_> = (0..2u64)
+                    .map(|racer| {
+                        let (tree, barrier) = (&tree, &barrier);
+                        s.spawn(move || {
+                            barrier.wait();
+                            tree.insert_if_absent(key, racer).unwrap()
+                        })
+                    })
+                    .collect();
+                racers.into_iter().map(|r| r.join().unwrap()).collect()
+            });
+            let winner = *tree.get(key).unwrap();
+            let loser = 1 - winner;
+            assert_eq!(results[winner as usize], Ok(()));
+            assert_eq!(results[loser as usize], Err(winner));
+        }
+        assert_eq!(tree.len(), 100);
+    }
+
+    #[test]
+    fn test_entry_or_insert_with() {
+        let tree = BwTree::new();
+        assert_eq!(tree.entry(1u64).unwrap().or_insert_with(|| 10), Ok(10));
+        assert_eq!(
+            tree.entry(1).unwrap().or_insert_with(|| unreachable!()),
+            Ok(10)
+        );
+        assert_eq!(tree.entry(2).unwrap().or_insert(20), Ok(20));
+        assert_eq!(tree.get(1).as_deref(), Some(&10));
+        assert_eq!(tree.get(2).as_deref(), Some(&20));
+        assert_eq!(tree.len(), 2);
+
+        match tree.entry(1).unwrap() {
+            Entry::Occupied(entry) => {
+                assert_eq!(*entry.key(), 1);
+                assert_eq!(*entry.get(), 10);
+                assert_eq!(entry.insert(11), Ok(Some(10)));
+            }
+            Entry::Vacant(_) => panic!("key 1 is vacant"),
+        }
+        assert_eq!(tree.get(1).as_deref(), Some(&11));
+        assert_eq!(tree.len(), 2);
+    }
+
+    #[test]
+    fn test_entry_and_modify() {
+        let tree = BwTree::with_consolidation_threshold(2);
+        for key in 0..10u64 {
+            tree.insert(key, key).unwrap();
+        }
+        for key in 0..20u64 {
+            let entry = tree.entry(key).unwrap().and_modify(|v| *v *= 2).unwrap();
+            assert_eq!(entry.or_insert(1), Ok(if key < 10 { key * 2 } else { 1 }));
+        }
+        for key in 0..20u64 {
+            assert_eq!(
+                tree.get(key).as_deref(),
+                Some(&if key < 10 { key * 2 } else { 1 })
+            );
+        }
+        assert_eq!(tree.len(), 20);
+
+        tree.delete(0).unwrap();
+        let entry = tree.entry(0).unwrap().and_modify(|_| unreachable!());
+        assert!(matches!(entry, Ok(Entry::Vacant(_))));
+    }
+
+    #[test]
+    fn test_entry_and_modify_contended() {
+        let tree = BwTree::with_consolidation_threshold(8);
+        tree.insert(0u64, 0u64).unwrap();
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        tree.entry(0).unwrap().and_modify(|v| *v += 1).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(tree.get(0).as_deref(), Some(&4000));
+    }
+
+    #[test]
+    fn test_height() {
+        let tree = BwTree::with_split_threshold(4);
+        assert_eq!(tree.height(), 2);
+        for key in 0..1000u64 {
+            tree.insert(key, key).unwrap();
+        }
+        // Every inner node has at most 4 children, so over 100 leaves take at
+        // least 4 levels of inner nodes.
+        assert!(tree.stats().leaf_count > 100);
+        assert!(tree.height() >= 5);
+    }
+
+    #[test]
+    fn test_root_split() {
+        let tree = BwTree::with_split_threshold(4);
+        let root_id = tree.root_id();
+        for key in (0..2000u64).rev() {
+            tree.insert(key, key * 2).unwrap();
+        }
+        assert_ne!(tree.root_id(), root_id);
+        assert!(tree.height() > 2);
+        for key in 0..2000u64 {
+            assert_eq!(tree.get(key).as_deref(), Some(&(key * 2)));
+        }
+        assert!(tree.iter().map(|(key, _)| key).eq(0..2000));
+        assert!(tree.iter_rev().map(|(key, _)| key).eq((0..2000).rev()));
+        assert_eq!(tree.last_key_value(), Some((1999, 3998)));
+        tree.verify().unwrap();
+    }
+
+    #[test]
+    fn test_max_chain_len() {
+        let tree = BwTree::with_max_chain_len(4);
+        let chain_len = |tree: &BwTree<u64, u64>| match tree.mapping_table.get(FIRST_LEAF_NODE_ID) {
+            Node::Delta(delta) => delta.records.len(),
+            _ => 0,
+        };
+        for value in 0..1000u64 {
+            tree.insert(7, value).unwrap();
+            assert!(chain_len(&tree) < 4);
+        }
+        assert_eq!(tree.get(7).as_deref(), Some(&999));
+
+        // The cap bounds the chain even if the consolidation threshold
+        // doesn't.
+        let mut tree = BwTree::with_consolidation_threshold(usize::MAX);
+        tree.max_chain_len = 4;
+        for value in 0..1000u64 {
+            tree.insert(7, value).unwrap();
+            assert!(tree.stats().longest_delta_chain < 4);
+        }
+        assert_eq!(tree.get(7).as_deref(), Some(&999));
+        assert_eq!(tree.len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "max chain length must be positive")]
+    fn test_max_chain_len_zero() {
+        let _: BwTree<u64, u64> = BwTree::with_max_chain_len(0);
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut tree = BwTree::with_consolidation_threshold(usize::MAX);
+        tree.max_chain_len = usize::MAX;
+        for key in 0..200u64 {
+            tree.insert(key, key).unwrap();
+        }
+        for key in (0..200).step_by(3) {
+            tree.delete(key).unwrap();
+        }
+        let before: Vec<_> = tree.iter().collect();
+        assert!(tree.stats().longest_delta_chain > 200);
+
+        tree.compact().unwrap();
+        // A split leaves a split delta behind on the left half.
+        assert!(tree.stats().longest_delta_chain <= 1);
+        assert_eq!(tree.iter().collect::<Vec<_>>(), before);
+        assert_eq!(tree.len(), before.len());
+        tree.verify().unwrap();
+    }
+
+    #[test]
+    fn test_compact_concurrent_with_writes() {
+        let tree = BwTree::with_split_threshold(16);
+        std::thread::scope(|s| {
+            for t in 0..2u64 {
+                let tree = &tree;
+                s.spawn(move || {
+                    for i in 0..1000 {
+                        tree.insert(i * 2 + t, i).unwrap();
+                    }
+                });
+            }
+            s.spawn(|| {
+                for _ in 0..20 {
+                    tree.compact().unwrap();
+                    assert!(tree.iter().map(|(key, _)| key).is_sorted());
+                }
+            });
+        });
+        assert!(tree.iter().map(|(key, _)| key).eq(0..2000));
+        tree.verify().unwrap();
+    }
+
+    #[test]
+    fn test_memory_bytes() {
+        let tree = BwTree::with_split_threshold(64);
+        let empty = tree.memory_bytes();
+        assert!(empty > 0);
+        let items: Vec<_> = (0..10_000u64).map(|key| (key, key)).collect();
+        tree.batch_insert(&items).unwrap();
+        let full = tree.memory_bytes();
+        assert!(full > empty + items.len() * std::mem::size_of::<(u64, u64)>());
+        tree.clear();
+        assert!(tree.memory_bytes() < full);
+    }
+
+    #[test]
+    fn test_clear() {
+        let tree = BwTree::with_split_threshold(8);
+        for key in 0..500u64 {
+            tree.insert(key, key).unwrap();
+        }
+        tree.clear();
+        assert!(tree.is_empty());
+        assert_eq!(tree.get(0).as_deref(), None);
+        assert_eq!(tree.get(499).as_deref(), None);
+        assert_eq!(tree.iter().next(), None);
+        assert_eq!(tree.iter_rev().next(), None);
+
+        for key in (0..500u64).rev() {
+            tree.insert(key, key + 1).unwrap();
+        }
+        assert_eq!(tree.len(), 500);
+        assert!(tree.iter().eq((0..500).map(|key| (key, key + 1))));
+    }
+
+    #[test]
+    fn test_clear_with_concurrent_readers() {
+        let tree = BwTree::with_split_threshold(8);
+        let done = AtomicBool::new(false);
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                while !done.load(Ordering::SeqCst) {
+                    for key in 0..200u64 {
+                        if let Some(value) = tree.get_owned(key) {
+                            assert_eq!(value, key);
+                        }
+                    }
+                    assert!(tree.iter().all(|(key, value)| key == value));
+                }
+            });
+            for _ in 0..20 {
+                for key in 0..200u64 {
+                    tree.insert(key, key).unwrap();
+                }
+                tree.clear();
+            }
+            done.store(true, Ordering::SeqCst);
+        });
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn test_clear_with_concurrent_writers() {
+        let tree = BwTree::with_split_threshold(8);
+        let running = AtomicUsize::new(4);
+        std::thread::scope(|s| {
+            for thread in 0..4u64 {
+                let tree = &tree;
+                let running = &running;
+                s.spawn(move || {
+                    for _ in 0..200 {
+                        for key in (thread..400).step_by(4) {
+                            tree.insert(key, key).unwrap();
+                            if key % 3 == 0 {
+                                tree.delete(key).unwrap();
+                            }
+                        }
+                    }
+                    running.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+            while running.load(Ordering::SeqCst) > 0 {
+                tree.clear();
+            }
+        });
+        assert_eq!(tree.len(), tree.iter().count());
+        tree.verify().unwrap();
+        tree.clear();
+        assert!(tree.is_empty());
+        assert_eq!(tree.iter().next(), None);
+    }
+
+    #[test]
+    fn test_stats() {
+        let tree = BwTree::with_consolidation_threshold(8);
+        let stats = tree.stats();
+        assert_eq!(stats.node_count, 2);
+        assert_eq!(stats.leaf_count, 1);
+        assert_eq!(stats.inner_count, 1);
+        assert_eq!(stats.total_delta_records, 0);
+        assert_eq!(stats.live_keys, 0);
+
+        for key in 0..5u64 {
+            tree.insert(key, key).unwrap();
+        }
+        tree.delete(0).unwrap();
+        let stats = tree.stats();
+        assert_eq!(stats.total_delta_records, 6);
+        assert_eq!(stats.longest_delta_chain, 6);
+        assert_eq!(stats.live_keys, 4);
+
+        // The ninth record pushes the chain over the threshold.
+        for key in 5..8u64 {
+            tree.insert(key, key).unwrap();
+        }
+        let stats = tree.stats();
+        assert_eq!(stats.total_delta_records, 0);
+        assert_eq!(stats.longest_delta_chain, 0);
+        assert_eq!(stats.live_keys, 7);
+        assert_eq!(stats.node_count, 2);
+    }
+
+    #[test]
+    fn test_stats_after_splits() {
+        let tree = BwTree::with_split_threshold(8);
+        for key in 0..100u64 {
+            tree.insert(key, key).unwrap();
+        }
+        let stats = tree.stats();
+        assert!(stats.leaf_count > 8);
+        // The root overflowed and was split.
+        assert!(stats.inner_count > 1);
+        assert_eq!(stats.node_count, stats.leaf_count + stats.inner_count);
+        assert_eq!(stats.live_keys, 100);
+        assert!(stats.longest_delta_chain <= 8);
+    }
+
+    #[test]
+    fn test_builder() {
+        let build = |leaf_split_threshold| {
+            let tree: BwTree<u64, u64> = BwTree::builder()
+                .leaf_split_threshold(leaf_split_threshold)
+                .consolidation_threshold(2)
+                .build();
+            for key in 0..1000 {
+                tree.insert(key, key).unwrap();
+            }
+            tree.verify().unwrap();
+            tree.stats()
+        };
+        let small = build(8);
+        let large = build(256);
+        assert!(small.leaf_count > large.leaf_count);
+        assert!(small.leaf_count >= 1000 / 8);
+        assert!(large.leaf_count <= 1000 / 128 + 1);
+        assert!(small.longest_delta_chain <= 2);
+        assert_eq!(small.live_keys, 1000);
+        assert_eq!(large.live_keys, 1000);
+    }
+
+    #[test]
+    fn test_builder_inner_split_threshold() {
+        let narrow: BwTree<u64, u64> = BwTree::builder().leaf_split_threshold(4).build();
+        let wide: BwTree<u64, u64> = BwTree::builder()
+            .leaf_split_threshold(4)
+            .inner_split_threshold(1024)
+            .build();
+        for key in 0..1000 {
+            narrow.insert(key, key).unwrap();
+            wide.insert(key, key).unwrap();
+        }
+        // With room for every leaf in the root, the tree never grows.
+        assert_eq!(wide.height(), 2);
+        assert_eq!(wide.stats().inner_count, 1);
+        assert!(narrow.height() > 2);
+        assert_eq!(narrow.stats().leaf_count, wide.stats().leaf_count);
+    }
+
+    #[test]
+    fn test_builder_merge_threshold() {
+        let tree: BwTree<u64, u64> = BwTree::builder()
+            .leaf_split_threshold(8)
+            .leaf_merge_threshold(4)
+            .build();
+        for key in 0..100 {
+            tree.insert(key, key).unwrap();
+        }
+        let leaves = tree.stats().leaf_count;
+        for key in 0..100 {
+            if key % 8 != 0 {
+                tree.delete(key).unwrap();
+            }
+        }
+        assert!(tree.stats().leaf_count < leaves);
+        tree.verify().unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "merge threshold must be at most half of the split threshold")]
+    fn test_builder_rejects_high_merge_threshold() {
+        let _: BwTree<u64, u64> = BwTree::builder()
+            .leaf_split_threshold(8)
+            .leaf_merge_threshold(5)
+            .build();
+    }
+
+    #[test]
+    #[should_panic(expected = "inner split threshold must be positive")]
+    fn test_builder_rejects_empty_inner_nodes() {
+        let _: BwTree<u64, u64> = BwTree::builder().inner_split_threshold(0).build();
+    }
+
+    #[test]
+    fn test_smo_events() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut tree = BwTree::builder()
+            .leaf_split_threshold(4)
+            .leaf_merge_threshold(2)
+            .consolidation_threshold(2)
+            .build();
+        let observed = events.clone();
+        tree.on_smo(move |event| observed.lock().unwrap().push(event));
+        for key in 0..15u64 {
+            tree.insert(key, key).unwrap();
+        }
+        let splits: Vec<_> = std::mem::take(&mut *events.lock().unwrap())
+            .into_iter()
+            .filter(|event| !matches!(event, SmoEvent::Consolidation { .. }))
+            .collect();
+        // Keys are appended to the right-most leaf, which splits every time
+        // it fills up, and the fourth split overflows the root before the
+        // split completes.
+        assert_eq!(
+            splits,
+            vec![
+                SmoEvent::LeafSplit {
+                    node: 2,
+                    new_sibling: 3
+                },
+                SmoEvent::LeafSplit {
+                    node: 3,
+                    new_sibling: 4
+                },
+                SmoEvent::LeafSplit {
+                    node: 4,
+                    new_sibling: 5
+                },
+                SmoEvent::RootSplit {
+                    old_root: 1,
+                    new_sibling: 7,
+                    new_root: 8
+                },
+                SmoEvent::LeafSplit {
+                    node: 5,
+                    new_sibling: 6
+                },
+            ]
+        );
+        assert_eq!(tree.root_id(), 8);
+
+        for key in 4..8 {
+            tree.delete(key).unwrap();
+        }
+        let events = std::mem::take(&mut *events.lock().unwrap());
+        assert!(events.contains(&SmoEvent::LeafMerge { node: 3, into: 2 }));
+        assert!(events.iter().all(|event| match event {
+            SmoEvent::Consolidation { chain_len, .. } => *chain_len > 2,
+            _ => matches!(event, SmoEvent::LeafMerge { .. }),
+        }));
+        tree.verify().unwrap();
+    }
+
+    #[test]
+    fn test_batch_insert() {
+        let items: Vec<(u64, u64)> = (0..300).map(|i| ((i * 37) % 100, i)).collect();
+        let batched = BwTree::with_split_threshold(8);
+        batched.batch_insert(&items).unwrap();
+        let individual = BwTree::with_split_threshold(8);
+        for &(key, value) in &items {
+            individual.insert(key, value).unwrap();
+        }
+        assert_eq!(batched.len(), individual.len());
+        assert!(batched.iter().eq(individual.iter()));
+
+        // A batch on top of existing entries overwrites them.
+        batched.batch_insert(&[(5, 0), (500, 1)]).unwrap();
+        assert_eq!(batched.get(5).as_deref(), Some(&0));
+        assert_eq!(batched.get(500).as_deref(), Some(&1));
+        assert_eq!(batched.len(), 101);
+        batched.batch_insert(&[]).unwrap();
+        assert_eq!(batched.len(), 101);
+    }
+
+    #[test]
+    fn test_batch_insert_clones_every_entry_once() {
+        static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(Debug, PartialEq)]
+        struct Counted(u64);
+
+        impl Clone for Counted {
+            fn clone(&self) -> Self {
+                CLONES.fetch_add(1, Ordering::SeqCst);
+                Counted(self.0)
+            }
+        }
+
+        // Few enough entries to leave the delta chain unconsolidated, as
+        // consolidations clone the values too.
+        let items: Vec<_> = (0..50u64).rev().map(|key| (key, Counted(key))).collect();
+        let tree = BwTree::with_consolidation_threshold(usize::MAX);
+        tree.batch_insert(&items).unwrap();
+        assert_eq!(CLONES.load(Ordering::SeqCst), items.len());
+        assert_eq!(tree.get(7).as_deref(), Some(&Counted(7)));
+    }
+
+    #[test]
+    fn test_extend() {
+        let tree = BwTree::with_split_threshold(8);
+        tree.extend(vec![(3u64, 30u64), (1, 10), (2, 20)]).unwrap();
+        assert!(tree.iter().eq([(1, 10), (2, 20), (3, 30)]));
+        tree.extend((0..100u64).rev().map(|key| (key, key * 10)))
+            .unwrap();
+        assert_eq!(tree.len(), 100);
+        assert!(tree.iter().eq((0..100).map(|key| (key, key * 10))));
+        tree.extend(std::iter::empty()).unwrap();
+        assert_eq!(tree.len(), 100);
+    }
+
+    #[test]
+    fn test_insert_batch_unsorted() {
+        let tree = BwTree::with_split_threshold(8);
+        tree.insert(1000, 0).unwrap();
+        // Every key occurs three times, with its values in ascending order,
+        // scattered across the batch.
+        let items: Vec<(u64, u64)> = (0..300).map(|i| ((i * 37) % 100, i)).collect();
+        tree.insert_batch_unsorted(items).unwrap();
+        assert_eq!(tree.len(), 101);
+        for key in 0..100u64 {
+            let last = (0..300).filter(|i| i * 37 % 100 == key).max();
+            assert_eq!(tree.get(key).as_deref(), last.as_ref());
+        }
+        assert_eq!(tree.get(1000).as_deref(), Some(&0));
+        tree.insert_batch_unsorted(vec![(1000, 3), (7, 1), (1000, 2), (7, 2)])
+            .unwrap();
+        assert_eq!(tree.get(1000).as_deref(), Some(&2));
+        assert_eq!(tree.get(7).as_deref(), Some(&2));
+        tree.insert_batch_unsorted(Vec::new()).unwrap();
+        assert_eq!(tree.len(), 101);
+        tree.verify().unwrap();
+    }
+
+    #[test]
+    fn test_batch_insert_sorted() {
+        let items: Vec<(u64, u64)> = (0..100_000).map(|key| (key, key * 2)).collect();
+        let tree = BwTree::new();
+        tree.batch_insert(&items).unwrap();
+        assert_eq!(tree.len(), 100_000);
+        assert!(tree.iter().eq(items.iter().cloned()));
+    }
+
+    #[test]
+    fn test_merge_from_disjoint() {
+        let tree = BwTree::with_split_threshold(8);
+        let other = BwTree::with_split_threshold(8);
+        for i in 0..100u64 {
+            tree.insert(i * 2, i).unwrap();
+            other.insert(i * 2 + 1, i).unwrap();
+        }
+        tree.merge_from(&other).unwrap();
+        assert_eq!(tree.len(), 200);
+        assert!(tree.iter().map(|(k, _)| k).eq(0..200));
+        assert_eq!(tree.get(41).as_deref(), Some(&20));
+        assert_eq!(other.len(), 100);
+        tree.verify().unwrap();
+    }
+
+    #[test]
+    fn test_merge_from_overlapping() {
+        let shard = |keys: std::ops::Range<u64>, value: u64| {
+            let tree = BwTree::with_split_threshold(8);
+            for key in keys {
+                tree.insert(key, value).unwrap();
+            }
+            tree
+        };
+        let other = shard(50..150, 2);
+
+        let tree = shard(0..100, 1);
+        tree.merge_from(&other).unwrap();
+        assert_eq!(tree.len(), 150);
+        assert!(tree.range(0, 100).all(|(_, v)| v == 1));
+        assert!(tree.range(100, 150).all(|(_, v)| v == 2));
+        tree.verify().unwrap();
+
+        let tree = shard(0..100, 1);
+        tree.merge_from_overwriting(&other).unwrap();
+        assert_eq!(tree.len(), 150);
+        assert!(tree.range(0, 50).all(|(_, v)| v == 1));
+        assert!(tree.range(50, 150).all(|(_, v)| v == 2));
+        tree.verify().unwrap();
+    }
+
+    #[test]
+    fn test_merge_from_empty() {
+        let tree = BwTree::new();
+        tree.insert(1u64, 1u64).unwrap();
+        let empty = BwTree::new();
+        tree.merge_from(&empty).unwrap();
+        tree.merge_from_overwriting(&empty).unwrap();
+        assert_eq!(tree.iter().collect::<Vec<_>>(), vec![(1, 1)]);
+
+        empty.merge_from(&tree).unwrap();
+        assert_eq!(empty.iter().collect::<Vec<_>>(), vec![(1, 1)]);
+        assert_eq!(empty.len(), 1);
+    }
+
+    #[test]
+    fn test_delete_range_within_a_leaf() {
+        let tree = tree_with_leaves(&[&[1, 3, 5], &[10, 12, 14], &[20, 22]]);
+        assert_eq!(tree.delete_range(11, 14), Ok(1));
+        assert_eq!(tree.len(), 7);
+        // Keys that were deleted already are not counted again.
+        assert_eq!(tree.delete(3), Ok(true));
+        assert_eq!(tree.delete_range(2, 5), Ok(0));
+        assert_eq!(tree.delete_range(0, 6), Ok(2));
+        let keys: Vec<_> = tree.iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![10, 14, 20, 22]);
+        assert_eq!(tree.len(), 4);
+    }
+
+    #[test]
+    fn test_delete_range_covering_leaves() {
+        let tree = BwTree::with_split_threshold(8);
+        for key in 0..1000u64 {
+            tree.insert(key, key).unwrap();
+        }
+        let leaves = tree.stats().leaf_count;
+        assert_eq!(tree.delete_range(100, 900), Ok(800));
+        assert_eq!(tree.len(), 200);
+        assert_eq!(tree.count_range(0, 1000), 200);
+        assert!(tree.iter().map(|(k, _)| k).eq((0..100).chain(900..1000)));
+        // The emptied leaves are merged away.
+        assert!(tree.stats().leaf_count < leaves);
+        assert_eq!(tree.delete_range(KeyType::minimum(), u64::MAX), Ok(200));
+        assert!(tree.is_empty());
+        tree.verify().unwrap();
+    }
+
+    #[test]
+    fn test_delete_range_empty() {
+        let tree = tree_with_leaves(&[&[1, 3, 5], &[10, 12, 14]]);
+        assert_eq!(tree.delete_range(6, 10), Ok(0));
+        assert_eq!(tree.delete_range(12, 12), Ok(0));
+        assert_eq!(tree.delete_range(14, 3), Ok(0));
+        assert_eq!(tree.delete_range(100, 200), Ok(0));
+        assert_eq!(tree.len(), 6);
+        let tree: BwTree<u64, u64> = BwTree::new();
+        assert_eq!(tree.delete_range(0, 100), Ok(0));
+    }
+
+    #[test]
+    fn test_retain() {
+        let populate = || {
+            let tree = BwTree::with_split_threshold(8);
+            for i in 0..200u64 {
+                tree.insert(i, i * 10).unwrap();
+            }
+            tree
+        };
+
+        let tree = populate();
+        assert!(tree.stats().leaf_count > 1);
+        let mut calls = 0;
+        tree.retain(|key, value| {
+            calls += 1;
+            assert_eq!(*value, key * 10);
+            key % 2 == 0
+        })
+        .unwrap();
+        assert_eq!(calls, 200);
+        assert_eq!(tree.len(), 100);
+        assert!(tree.iter().map(|(k, _)| k).eq((0..200).step_by(2)));
+        tree.verify().unwrap();
+
+        let tree = populate();
+        tree.retain(|_, _| false).unwrap();
+        assert!(tree.is_empty());
+        assert_eq!(tree.iter().count(), 0);
+        tree.verify().unwrap();
+
+        let tree = populate();
+        tree.retain(|_, _| true).unwrap();
+        assert_eq!(tree.len(), 200);
+        assert!(tree.iter().eq((0..200).map(|i| (i, i * 10))));
+        tree.verify().unwrap();
+    }
+
+    #[test]
+    fn test_multimap_preserves_insertion_order() {
+        let map = BwTreeMultimap::new();
+        for (key, value) in [(2, "b1"), (1, "a1"), (2, "b2"), (3, "c1"), (2, "b3")] {
+            map.insert(key, value).unwrap();
+        }
+        map.insert(1, "a1").unwrap();
+        assert_eq!(map.get_all(2), vec!["b1", "b2", "b3"]);
+        assert_eq!(map.get_all(1), vec!["a1", "a1"]);
+        assert_eq!(map.get_all(4), Vec::<&str>::new());
+        assert!(map.contains_key(3));
+        assert!(!map.contains_key(0));
+        assert_eq!(map.len(), 6);
+    }
+
+    #[test]
+    fn test_multimap_delete_one_and_all() {
+        let map = BwTreeMultimap::new();
+        for value in [1, 2, 1, 3, 1] {
+            map.insert("key", value).unwrap();
+        }
+        map.insert("other", 1).unwrap();
+        // Only the oldest matching value goes.
+        assert_eq!(map.delete("key", &1), Ok(true));
+        assert_eq!(map.get_all("key"), vec![2, 1, 3, 1]);
+        assert_eq!(map.delete("key", &4), Ok(false));
+        assert_eq!(map.delete_all("key"), Ok(4));
+        assert!(map.get_all("key").is_empty());
+        assert_eq!(map.delete_all("key"), Ok(0));
+        assert_eq!(map.get_all("other"), vec![1]);
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn test_multimap_many_values_per_key() {
+        let map = BwTreeMultimap::new();
+        for i in 0..1000u64 {
+            map.insert(i % 3, i).unwrap();
+        }
+        for key in 0..3 {
+            assert!(map.get_all(key).into_iter().eq((key..1000).step_by(3)));
+        }
+    }
+
+    #[test]
+    fn test_bounded_evicts_oldest_first() {
+        let tree = BoundedBwTree::with_capacity(3);
+        for key in 0..3u64 {
+            assert_eq!(tree.insert(key, key * 10), Ok(None));
+        }
+        assert_eq!(tree.len(), 3);
+        assert_eq!(tree.insert(3, 30), Ok(None));
+        assert_eq!(tree.len(), 3);
+        assert_eq!(tree.get(0), None);
+        assert_eq!(tree.get(3), Some(30));
+
+        // Overwriting a key makes it the newest one.
+        assert_eq!(tree.insert(1, 11), Ok(Some(10)));
+        assert_eq!(tree.insert(4, 40), Ok(None));
+        assert!(!tree.contains_key(2));
+        assert!((3..5).chain([1]).all(|key| tree.contains_key(key)));
+        assert_eq!(tree.get(1), Some(11));
+    }
+
+    #[test]
+    fn test_bounded_respects_capacity() {
+        let tree = BoundedBwTree::with_capacity(10);
+        for key in 0..1000u64 {
+            tree.insert(key, key).unwrap();
+            assert!(tree.len() <= 10);
+            assert_eq!(tree.get(key), Some(key));
+        }
+        assert!((990..1000).all(|key| tree.get(key) == Some(key)));
+        assert_eq!(tree.capacity(), 10);
+    }
+
+    #[test]
+    fn test_bounded_evicts_with_full_mapping_table() {
+        let capacity = DEFAULT_SPLIT_THRESHOLD + 10;
+        let tree = BoundedBwTree::with_capacity(capacity);
+        tree.tree.exhaust_node_ids();
+        tree.order.exhaust_node_ids();
+        let n = 3 * capacity as u64;
+        assert!((0..n).all(|key| tree.insert(key, key) == Ok(None)));
+        assert_eq!(tree.len(), capacity);
+        assert!((n - capacity as u64..n).all(|key| tree.get(key) == Some(key)));
+        // Overwrites drop the order entries of the writes they replace.
+        assert!((n - capacity as u64..n).all(|key| tree.insert(key, key + 1) == Ok(Some(key))));
+        assert_eq!(tree.order.len(), capacity);
+        assert!((n - capacity as u64..n).all(|key| tree.get(key) == Some(key + 1)));
+        assert!((n - capacity as u64..n).all(|key| tree.delete(key) == Ok(true)));
+        assert!(tree.is_empty());
+        assert!(tree.order.is_empty());
+    }
+
+    #[test]
+    fn test_bounded_delete() {
+        let tree = BoundedBwTree::with_capacity(2);
+        tree.insert(1u64, "a").unwrap();
+        tree.insert(2, "b").unwrap();
+        assert_eq!(tree.delete(1), Ok(true));
+        assert_eq!(tree.delete(1), Ok(false));
+        // The deleted key made room, so nothing is evicted.
+        tree.insert(3, "c").unwrap();
+        assert_eq!(tree.get(2), Some("b"));
+        assert_eq!(tree.len(), 2);
+        tree.insert(4, "d").unwrap();
+        assert_eq!(tree.get(2), None);
+        assert!(!tree.is_empty());
+    }
+
+    #[test]
+    fn test_bounded_concurrent_inserts() {
+        let tree = BoundedBwTree::with_capacity(50);
+        std::thread::scope(|s| {
+            for t in 0..4u64 {
+                let tree = &tree;
+                s.spawn(move || {
+                    for i in 0..1000 {
+                        let key = i * 4 + t;
+                        tree.insert(key, key).unwrap();
+                        if i % 3 == 0 {
+                            tree.delete(key).unwrap();
+                        }
+                    }
+                });
+            }
+        });
+        assert!(tree.len() <= 50);
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity must be positive")]
+    fn test_bounded_zero_capacity() {
+        let _: BoundedBwTree<u64, u64> = BoundedBwTree::with_capacity(0);
+    }
+
+    /// Returns a path for a write-ahead log in the temporary directory, after
+    /// removing any file a previous run left there.
+    fn wal_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("bwtree-{}-{name}.wal", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_wal_recover() {
+        let path = wal_path("recover");
+        let tree = DurableBwTree::with_wal(&path).unwrap();
+        let mut expected = BTreeMap::new();
+        for i in 0..500u64 {
+            let key = i * 7 % 300;
+            assert_eq!(
+                tree.insert(key, format!("value {i}")).unwrap(),
+                expected.insert(key, format!("value {i}"))
+            );
+        }
+        for key in (0..300).step_by(4) {
+            assert_eq!(tree.delete(key).unwrap(), expected.remove(&key).is_some());
+        }
+        tree.sync().unwrap();
+        // Crash without any clean shutdown.
+        std::mem::forget(tree);
+
+        let recovered: DurableBwTree<u64, String> = DurableBwTree::recover(&path).unwrap();
+        assert_eq!(recovered.len(), expected.len());
+        assert!(recovered.iter().eq(expected.clone()));
+
+        // The recovered tree keeps logging where the log left off.
+        recovered.insert(1000, "new".to_string()).unwrap();
+        drop(recovered);
+        let recovered: DurableBwTree<u64, String> = DurableBwTree::recover(&path).unwrap();
+        assert_eq!(
+            recovered.get(1000).as_deref().map(String::as_str),
+            Some("new")
+        );
+        assert_eq!(recovered.len(), expected.len() + 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_wal_recover_torn_tail() {
+        let path = wal_path("torn");
+        let tree = DurableBwTree::with_wal(&path).unwrap();
+        for key in 0..10u64 {
+            tree.insert(key, vec![key as u8; 100]).unwrap();
+        }
+        drop(tree);
+        // Cut the last record short, as a crash in the middle of writing it
+        // would.
+        let len = std::fs::metadata(&path).unwrap().len();
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(len - 50).unwrap();
+        drop(file);
+
+        let recovered: DurableBwTree<u64, Vec<u8>> = DurableBwTree::recover(&path).unwrap();
+        assert!(recovered.iter().map(|(key, _)| key).eq(0..9));
+        recovered.insert(9, vec![1]).unwrap();
+        drop(recovered);
+        let recovered: DurableBwTree<u64, Vec<u8>> = DurableBwTree::recover(&path).unwrap();
+        assert_eq!(recovered.get(9).as_deref(), Some(&vec![1]));
+        assert_eq!(recovered.len(), 10);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_wal_recover_stops_at_bad_checksum() {
+        let path = wal_path("checksum");
+        let tree = DurableBwTree::with_wal(&path).unwrap();
+        for key in 0..3u64 {
+            tree.insert(key, key).unwrap();
+        }
+        drop(tree);
+        // Every record holds a tag, a key length, a key and a value behind
+        // its 8-byte header, so flip a byte of the value of the second one.
+        let mut bytes = std::fs::read(&path).unwrap();
+        let record_len = bytes.len() / 3;
+        bytes[2 * record_len - 1] ^= 0xFF;
+        std::fs::write(&path, &bytes).unwrap();
+
+        let recovered: DurableBwTree<u64, u64> = DurableBwTree::recover(&path).unwrap();
+        assert!(recovered.iter().eq([(0, 0)]));
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), record_len as u64);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_wal_with_full_mapping_table() {
+        let path = wal_path("full");
+        let tree: DurableBwTree<u64, u64> = DurableBwTree::with_wal(&path).unwrap();
+        tree.tree.exhaust_node_ids();
+        // The leaf can't be split, so the writes only defer the split.
+        let n = 2 * DEFAULT_SPLIT_THRESHOLD as u64;
+        assert!((0..n).all(|key| tree.insert(key, key).is_ok_and(|old| old.is_none())));
+        assert!((0..n)
+            .step_by(2)
+            .all(|key| tree.delete(key).is_ok_and(|deleted| deleted)));
+        assert!(tree.iter().map(|(k, _)| k).eq((1..n).step_by(2)));
+        let expected: Vec<_> = tree.iter().collect();
+        drop(tree);
+
+        let recovered: DurableBwTree<u64, u64> = DurableBwTree::recover(&path).unwrap();
+        assert!(recovered.iter().eq(expected));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_wal_logs_write_to_chain_at_cap_with_full_mapping_table() {
+        let path = wal_path("cap");
+        let tree: DurableBwTree<u64, u64> = DurableBwTree::with_wal(&path).unwrap();
+        // The chain is at the cap, and its leaf needs a split that no node ID
+        // is left for.
+        let n = DEFAULT_MAX_CHAIN_LEN as u64 + 1;
+        push_unconsolidated(&tree.tree, 0..n);
+        tree.tree.exhaust_node_ids();
+        assert_eq!(tree.insert(0, 100).unwrap(), Some(0));
+        // The header, the tag, the key length, the key, and the value.
+        let record_len = 8 + 1 + 4 + 8 + 8;
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), record_len);
+        drop(tree);
+
+        let recovered: DurableBwTree<u64, u64> = DurableBwTree::recover(&path).unwrap();
+        assert!(recovered.iter().eq([(0, 100)]));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_wal_errors() {
+        let path = wal_path("errors");
+        let tree: DurableBwTree<u64, u64> = DurableBwTree::with_wal(&path).unwrap();
+        tree.insert(1, 1).unwrap();
+        drop(tree);
+        assert!(matches!(
+            DurableBwTree::<u64, u64>::with_wal(&path),
+            Err(WalError::Io(err)) if err.kind() == std::io::ErrorKind::AlreadyExists
+        ));
+        // A value that was logged as a u64 doesn't decode as a u32.
+        assert!(matches!(
+            DurableBwTree::<u64, u32>::recover(&path),
+            Err(WalError::Corrupt(0))
+        ));
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            DurableBwTree::<u64, u64>::recover(&path),
+            Err(WalError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_arc_values() {
+        // Neither `Clone` nor `Debug`.
+        struct Blob(Box<[u8; 4096]>);
+
+        let tree: ArcBwTree<u64, Blob> = BwTree::with_consolidation_threshold(2);
+        let blobs: Vec<_> = (0..10u8)
+            .map(|i| Arc::new(Blob(Box::new([i; 4096]))))
+            .collect();
+        for (key, blob) in blobs.iter().enumerate() {
+            assert!(tree.insert(key as u64, blob.clone()).unwrap().is_none());
+        }
+        for (key, blob) in blobs.iter().enumerate() {
+            let value = tree.get_owned(key as u64).unwrap();
+            assert!(Arc::ptr_eq(&value, blob));
+            assert_eq!(value.0[0], key as u8);
+        }
+        assert_eq!(tree.get(3).unwrap().0[4095], 3);
+    }
+
+    #[test]
+    fn test_bytes_values() {
+        let tree: BytesBwTree<u64> = BwTree::new();
+        let value: Arc<[u8]> = vec![7u8; 1 << 20].into();
+        assert_eq!(tree.insert_bytes(1, value.clone()), Ok(None));
+        assert_eq!(tree.insert_bytes(2, &b"small"[..]), Ok(None));
+        let count = Arc::strong_count(&value);
+
+        let reads: Vec<_> = (0..100).map(|_| tree.get_bytes(1).unwrap()).collect();
+        assert!(reads.iter().all(|read| Arc::ptr_eq(read, &value)));
+        assert_eq!(Arc::strong_count(&value), count + 100);
+        drop(reads);
+        assert_eq!(Arc::strong_count(&value), count);
+        assert_eq!(tree.get_bytes(2).as_deref(), Some(&b"small"[..]));
+        assert_eq!(tree.get_bytes(3), None);
+    }
+
+    #[test]
+    fn test_get_with_version() {
+        let tree = BwTree::new();
+        for key in 0..10u64 {
+            tree.insert(key, key).unwrap();
+        }
+        let (value, version) = tree.get_with_version(3).unwrap();
+        assert_eq!(value, 3);
+        assert!(tree.validate(3, version));
+
+        // Reads and consolidations leave the version alone.
+        assert_eq!(tree.get_with_version(3), Some((3, version)));
+        tree.compact().unwrap();
+        assert!(tree.validate(3, version));
+
+        tree.insert(3, 30).unwrap();
+        assert!(!tree.validate(3, version));
+        let (value, updated) = tree.get_with_version(3).unwrap();
+        assert_eq!(value, 30);
+        assert!(updated > version);
+        assert!(tree.validate(3, updated));
+
+        tree.delete(3).unwrap();
+        assert!(!tree.validate(3, updated));
+        assert_eq!(tree.get_with_version(3), None);
+    }
+
+    #[test]
+    fn test_descend_to_leaf() {
+        let tree = BwTree::new();
+        for key in 0..10u64 {
+            tree.insert(key, key).unwrap();
+        }
+        let leaf = tree.descend_to_leaf(&3);
+        assert_eq!(leaf.node_id(), FIRST_LEAF_NODE_ID);
+        assert_eq!(leaf.upper_bound(), None);
+        assert_eq!(leaf.get(&3), Some(&3));
+        assert_eq!(leaf.get(&10), None);
+        assert!(leaf.is_current());
+        tree.compact().unwrap();
+        assert!(leaf.is_current());
+        tree.insert(10, 10).unwrap();
+        assert!(!leaf.is_current());
+        drop(leaf);
+        assert_eq!(tree.descend_to_leaf(&3).get(&10), Some(&10));
+    }
+
+    #[test]
+    fn test_descend_to_leaf_consistent_read() {
+        // Reads both keys from the same state of the node, retrying if the
+        // node changed in between.
+        fn read_pair(tree: &BwTree<u64, u64>) -> (u64, u64) {
+            loop {
+                let leaf = tree.descend_to_leaf(&0);
+                let first = *leaf.get(&0).unwrap();
+                let second = *leaf.get(&1).unwrap();
+                if leaf.is_current() {
+                    return (first, second);
+                }
+            }
+        }
+
+        let tree = BwTree::new();
+        tree.insert(0, 0u64).unwrap();
+        tree.insert(1, 0).unwrap();
+        let done = AtomicBool::new(false);
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for i in 1..=1000 {
+                    tree.insert(0, i).unwrap();
+                    tree.insert(1, i).unwrap();
+                }
+                done.store(true, Ordering::SeqCst);
+            });
+            while !done.load(Ordering::SeqCst) {
+                // The second key is written after the first one, so it can
+                // only be ahead of it in a read that mixes states.
+                let (first, second) = read_pair(&tree);
+                assert!(second == first || second + 1 == first, "{first} {second}");
+            }
+        });
+        assert_eq!(read_pair(&tree), (1000, 1000));
+    }
+
+    #[test]
+    fn test_version_grows_across_splits_and_merges() {
+        let tree = BwTree::with_split_and_merge_thresholds(8, 2);
+        let mut versions = Vec::new();
+        for key in 0..100u64 {
+            tree.insert(key, key).unwrap();
+            versions.push(tree.get_with_version(0).unwrap().1);
+        }
+        for key in 1..100u64 {
+            tree.delete(key).unwrap();
+            versions.push(tree.get_with_version(0).unwrap().1);
+        }
+        assert!(versions.windows(2).all(|pair| pair[0] <= pair[1]));
+        tree.clear();
+        tree.insert(0, 0).unwrap();
+        assert!(tree.get_with_version(0).unwrap().1 > *versions.last().unwrap());
+    }
+
+    #[test]
+    fn test_get_or_insert_with() {
+        let tree = BwTree::new();
+        assert_eq!(tree.get_or_insert_with(1u64, || 10u64), Ok(10));
+        assert_eq!(tree.get_or_insert_with(1, || unreachable!()), Ok(10));
+        assert_eq!(tree.len(), 1);
+    }
+
+    #[test]
+    fn test_get_or_insert_with_race() {
+        let tree = BwTree::new();
+        for key in 0..50u64 {
+            let calls = AtomicUsize::new(0);
+            let barrier = std::sync::Barrier::new(4);
+            let values: Vec<_> = std::thread::scope(|s| {
+                let racers: Vec<_> = (0..4u64)
+                    .map(|racer| {
+                        let (tree, calls, barrier) = (&tree, &calls, &barrier);
+                        s.spawn(move || {
+                            barrier.wait();
+                            tree.get_or_insert_with(key, || {
+                                calls.fetch_add(1, Ordering::SeqCst);
+                                racer
+                            })
+                            .unwrap()
+                        })
+                    })
+                    .collect();
+                racers.into_iter().map(|r| r.join().unwrap()).collect()
+            });
+            // Every racer may construct a value, but only one is kept.
+            assert!((1..=4).contains(&calls.load(Ordering::SeqCst)));
+            let winner = *tree.get(key).unwrap();
+            assert!(values.iter().all(|&value| value == winner));
+        }
+        assert_eq!(tree.len(), 50);
+    }
+
+    #[test]
+    fn test_debug() {
+        let tree = BwTree::new();
+        for key in [3u64, 1, 2] {
+            tree.insert(key, key * 10).unwrap();
+        }
+        let output = format!("{tree:?}");
+        assert!(output.contains("len: 3"));
+        assert!(output.contains("entries: {1: 10, 2: 20, 3: 30}"));
+        assert!(output.contains("longest_delta_chain: 3"));
+
+        let output = format!("{tree:.2?}");
+        assert!(output.contains("entries: {1: 10, 2: 20, ..}"));
+    }
+
+    #[test]
+    fn test_verify_after_random_writes() {
+        for seed in 1..10u64 {
+            let tree = BwTree::with_thresholds(3, 8, 3);
+            let mut model = BTreeMap::new();
+            let mut x = seed;
+            for i in 0..5000 {
+                // xorshift64
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                let key = x % 300;
+                // Alternate between phases that mostly insert and phases that
+                // mostly delete, so that leaves are both split and merged.
+                if (x % 3 == 0) ^ (i % 1000 < 500) {
+                    assert_eq!(tree.delete(key), Ok(model.remove(&key).is_some()));
+                } else {
+                    assert_eq!(tree.insert(key, x), Ok(model.insert(key, x)));
+                }
+                if i % 100 == 0 {
+                    tree.verify().unwrap();
+                }
+            }
+            tree.verify().unwrap();
+            assert!(tree.iter().eq(model.into_iter()));
+        }
+    }
+
+    #[test]
+    fn test_verify_reports_violations() {
+        let tree = tree_with_leaves(&[&[1, 5], &[10, 15], &[20, 25]]);
+        tree.verify().unwrap();
+
+        tree.len.fetch_add(1, Ordering::SeqCst);
+        assert_eq!(
+            tree.verify(),
+            Err("length is 7 but the tree holds 6 live entries".to_string())
+        );
+        tree.len.fetch_sub(1, Ordering::SeqCst);
+
+        let leaf = LeafNode {
+            count: 2,
+            keys: vec![10, 21],
+            values: vec![10, 21],
+            high_key: Some(20),
+            right_sibling: Some(FIRST_LEAF_NODE_ID + 2),
+            version: 0,
+        };
+        tree.mapping_table
+            .insert(FIRST_LEAF_NODE_ID + 1, Node::Leaf(leaf));
+        assert_eq!(
+            tree.verify(),
+            Err("leaf-level node 3 holds key 21 outside of 10..Some(20)".to_string())
+        );
+    }
+
+    #[test]
+    fn test_compare_and_swap() {
+        let tree = BwTree::new();
+        tree.insert(1u64, 10u64).unwrap();
+        assert_eq!(tree.compare_and_swap(1, &10, 11), Ok(Ok(())));
+        assert_eq!(tree.get(1).as_deref(), Some(&11));
+
+        assert_eq!(tree.compare_and_swap(1, &10, 12), Ok(Err(Some(11))));
+        assert_eq!(tree.get(1).as_deref(), Some(&11));
+
+        assert_eq!(tree.compare_and_swap(2, &10, 12), Ok(Err(None)));
+        assert_eq!(tree.get(2).as_deref(), None);
+        tree.delete(1).unwrap();
+        assert_eq!(tree.compare_and_swap(1, &11, 12), Ok(Err(None)));
+        assert_eq!(tree.len(), 0);
+    }
+
+    #[test]
+    fn test_compare_and_swap_counter() {
+        let tree = BwTree::with_consolidation_threshold(4);
+        tree.insert(0u64, 0u64).unwrap();
+        for _ in 0..100 {
+            let mut current = tree.get_owned(0).unwrap();
+            while let Err(actual) = tree.compare_and_swap(0, &current, current + 1).unwrap() {
+                current = actual.unwrap();
+            }
+        }
+        assert_eq!(tree.get(0).as_deref(), Some(&100));
+    }
+
+    #[test]
+    fn test_remove_if() {
+        let tree = BwTree::new();
+        tree.insert(1u64, 10u64).unwrap();
+        tree.insert(2, 20).unwrap();
+
+        assert_eq!(tree.remove_if(1, |value| *value == 10), Ok(Some(10)));
+        assert_eq!(tree.get(1).as_deref(), None);
+        assert_eq!(tree.len(), 1);
+
+        assert_eq!(tree.remove_if(2, |value| *value > 20), Ok(None));
+        assert_eq!(tree.get(2).as_deref(), Some(&20));
+        assert_eq!(tree.len(), 1);
+
+        assert_eq!(tree.remove_if(3, |_| unreachable!()), Ok(None));
+        assert_eq!(tree.remove_if(1, |_| unreachable!()), Ok(None));
+        assert_eq!(tree.len(), 1);
+    }
+
+    #[test]
+    fn test_reverse_comparator() {
+        let tree = BwTree::builder()
+            .leaf_split_threshold(8)
+            .comparator(u64::MAX, |a: &u64, b: &u64| b.cmp(a))
+            .build();
+        for key in 0..100u64 {
+            tree.insert(key, key).unwrap();
+        }
+        assert_eq!(tree.get(42).as_deref(), Some(&42));
+        let keys: Vec<_> = tree.keys().collect();
+        assert_eq!(keys, (0..100).rev().collect::<Vec<_>>());
+        let keys: Vec<_> = tree.range(50, 45).map(|(key, _)| key).collect();
+        assert_eq!(keys, vec![50, 49, 48, 47, 46]);
+        let keys: Vec<_> = tree.iter_rev().map(|(key, _)| key).take(3).collect();
+        assert_eq!(keys, vec![0, 1, 2]);
+        assert_eq!(tree.count_range(50, 45), 5);
+        assert_eq!(tree.delete_range(9, 0), Ok(9));
+        assert_eq!(tree.first_key_value().map(|(key, _)| key), Some(99));
+        assert_eq!(tree.last_key_value().map(|(key, _)| key), Some(0));
+        tree.verify().unwrap();
+    }
+
+    #[test]
+    fn test_stateful_comparator() {
+        // The keys are ordered by a rank looked up in a table that the
+        // comparator captures, which no `Ord` implementation could express.
+        let rank: Arc<Vec<u64>> = Arc::new((0..200u64).map(|key| (key * 37 + 11) % 200).collect());
+        let minimum = rank.iter().position(|&r| r == 0).unwrap() as u64;
+        let by_rank = {
+            let rank = rank.clone();
+            move |a: &u64, b: &u64| rank[*a as usize].cmp(&rank[*b as usize])
+        };
+        let tree = BwTree::builder()
+            .leaf_split_threshold(8)
+            .comparator(minimum, by_rank)
+            .build();
+        for key in 0..200u64 {
+            tree.insert(key, key * 10).unwrap();
+        }
+        let mut expected: Vec<u64> = (0..200).collect();
+        expected.sort_by_key(|&key| rank[key as usize]);
+        assert_eq!(tree.keys().collect::<Vec<_>>(), expected);
+        for key in 0..200u64 {
+            assert_eq!(tree.get(key).as_deref(), Some(&(key * 10)));
+        }
+        tree.verify().unwrap();
+
+        // Ranges, snapshots, and the trees derived from the tree all follow
+        // the same order.
+        let (low, high) = (expected[20], expected[30]);
+        let keys: Vec<_> = tree.range(low, high).map(|(key, _)| key).collect();
+        assert_eq!(keys, expected[20..30]);
+        let snapshot = tree.snapshot();
+        assert_eq!(snapshot.get(low), Some(&(low * 10)));
+        let keys: Vec<_> = snapshot.range(low, high).map(|(key, _)| key).collect();
+        assert_eq!(keys, expected[20..30]);
+        drop(snapshot);
+        let copy = tree.clone();
+        assert_eq!(copy.keys().collect::<Vec<_>>(), expected);
+        copy.verify().unwrap();
+        let upper = tree.split_off(expected[100]).unwrap();
+        assert_eq!(tree.keys().collect::<Vec<_>>(), expected[..100]);
+        assert_eq!(upper.keys().collect::<Vec<_>>(), expected[100..]);
+        tree.verify().unwrap();
+        upper.verify().unwrap();
+
+        // Entries merged in from a tree with the natural order are sorted
+        // by the order of the tree they are merged into.
+        let natural: BwTree<u64, u64> = (100..200).map(|key| (key, key)).collect();
+        upper.merge_from(&natural).unwrap();
+        let mut keys: Vec<_> = expected[100..].to_vec();
+        keys.extend((100..200).filter(|key| !expected[100..].contains(key)));
+        keys.sort_by_key(|&key| rank[key as usize]);
+        assert_eq!(upper.keys().collect::<Vec<_>>(), keys);
+        upper.verify().unwrap();
+    }
+
+    #[test]
+    fn test_count_range() {
+        let tree = tree_with_leaves(&[&[1, 3, 5], &[10, 12, 14], &[20, 22]]);
+        assert_eq!(tree.count_range(0, 100), 8);
+        assert_eq!(tree.count_range(3, 12), 3);
+        assert_eq!(tree.count_range(5, 21), 5);
+        assert_eq!(tree.count_range(6, 10), 0);
+        assert_eq!(tree.count_range(12, 12), 0);
+        assert_eq!(tree.count_range(22, 0), 0);
+
+        // Delta records on top of the leaves are counted without
+        // consolidating them.
+        tree.insert(4, 4).unwrap();
+        tree.insert(3, 30).unwrap();
+        assert_eq!(tree.delete(10), Ok(true));
+        tree.insert(11, 11).unwrap();
+        assert_eq!(tree.delete(11), Ok(true));
+        assert_eq!(tree.count_range(0, 100), 8);
+        assert_eq!(tree.count_range(3, 12), 3);
+        assert_eq!(tree.count_range(4, 13), 3);
+    }
+
+    #[test]
+    fn test_count_range_matches_range() {
+        let tree = BwTree::with_thresholds(3, 8, 3);
+        for key in 0..300u64 {
+            tree.insert(key * 7 % 300, key).unwrap();
+            if key % 3 == 0 {
+                tree.delete(key / 2).unwrap();
+            }
+        }
+        for (start, end) in [(0, 300), (17, 18), (50, 150), (95, 205), (299, 300)] {
+            assert_eq!(tree.count_range(start, end), tree.range(start, end).count());
+        }
+    }
+
+    #[test]
+    fn test_contains_range() {
+        let tree = tree_with_leaves(&[&[1, 3, 5], &[10, 12, 14], &[20, 22]]);
+        // Exactly one key.
+        assert!(tree.contains_range(2, 4));
+        assert!(tree.contains_range(22, 23));
+        assert!(tree.contains_range(KeyType::minimum(), 2));
+        // No keys, including between the leaves and in empty ranges.
+        assert!(!tree.contains_range(6, 10));
+        assert!(!tree.contains_range(15, 20));
+        assert!(!tree.contains_range(23, 100));
+        assert!(!tree.contains_range(12, 12));
+        assert!(!tree.contains_range(14, 3));
+        // Many keys, across leaves.
+        assert!(tree.contains_range(0, 100));
+
+        // Deletes mask the keys at the boundaries of a range, and the range
+        // suffices only once a key in between is live.
+        assert_eq!(tree.delete(10), Ok(true));
+        assert_eq!(tree.delete(14), Ok(true));
+        assert!(tree.contains_range(10, 15));
+        assert_eq!(tree.delete(12), Ok(true));
+        assert!(!tree.contains_range(6, 20));
+        tree.insert(13, 13).unwrap();
+        assert!(tree.contains_range(6, 20));
+        assert!(!tree.contains_range(6, 13));
+        tree.insert(5, 50).unwrap();
+        assert_eq!(tree.delete(5), Ok(true));
+        assert!(!tree.contains_range(4, 13));
+    }
+
+    #[test]
+    fn test_contains_range_matches_range() {
+        let tree = BwTree::with_thresholds(3, 8, 3);
+        for key in 0..300u64 {
+            tree.insert(key * 7 % 300, key).unwrap();
+            if key % 3 == 0 {
+                tree.delete(key / 2).unwrap();
+            }
+        }
+        for start in (0..300).step_by(7) {
+            for len in [1, 2, 5, 40] {
+                let end = start + len;
+                let expected = tree.range(start, end).next().is_some();
+                assert_eq!(tree.contains_range(start, end), expected);
+            }
+        }
+        for key in 0..300u64 {
+            tree.delete(key).unwrap();
+        }
+        assert!(!tree.contains_range(0, 300));
+    }
+
+    #[test]
+    fn test_first_and_last_key_value() {
+        let tree = tree_with_leaves(&[&[1, 5], &[10, 15], &[20, 25]]);
+        assert_eq!(tree.first_key_value(), Some((1, 1)));
+        assert_eq!(tree.last_key_value(), Some((25, 25)));
+
+        assert_eq!(tree.delete(1), Ok(true));
+        assert_eq!(tree.first_key_value(), Some((5, 5)));
+        // Emptying the right-most leaf falls back to the leaf before it.
+        assert_eq!(tree.delete(20), Ok(true));
+        assert_eq!(tree.delete(25), Ok(true));
+        assert_eq!(tree.last_key_value(), Some((15, 15)));
+        assert_eq!(tree.delete(5), Ok(true));
+        assert_eq!(tree.first_key_value(), Some((10, 10)));
+
+        for key in [10, 15] {
+            assert_eq!(tree.delete(key), Ok(true));
+        }
+        assert_eq!(tree.first_key_value(), None);
+        assert_eq!(tree.last_key_value(), None);
+    }
+
+    #[test]
+    fn test_first_and_last_key_value_after_splits() {
+        let tree = BwTree::with_split_threshold(4);
+        assert_eq!(tree.first_key_value(), None);
+        assert_eq!(tree.last_key_value(), None);
+        for i in 0..100u64 {
+            assert_eq!(tree.insert(i, i), Ok(None));
+        }
+        assert_eq!(tree.first_key_value(), Some((0, 0)));
+        assert_eq!(tree.last_key_value(), Some((99, 99)));
+        assert_eq!(tree.delete(0), Ok(true));
+        assert_eq!(tree.delete(99), Ok(true));
+        assert_eq!(tree.first_key_value(), Some((1, 1)));
+        assert_eq!(tree.last_key_value(), Some((98, 98)));
+    }
+
+    #[test]
+    fn test_iter_rev() {
+        let tree = BwTree::with_split_threshold(4);
+        for i in 0..200u64 {
+            let key = (i * 7919) % 200;
+            assert_eq!(tree.insert(key, key * 2), Ok(None));
+        }
+        for key in (0..200).filter(|key| key % 3 == 0) {
+            assert_eq!(tree.delete(key), Ok(true));
+        }
+        let mut expected: Vec<_> = tree.iter().collect();
+        expected.reverse();
+        assert_eq!(tree.iter_rev().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_cursor_seek_then_next() {
+        let tree = BwTree::new();
+        for key in (0..100u64).step_by(10) {
+            tree.insert(key, key).unwrap();
+        }
+        let mut cursor = tree.cursor();
+        assert_eq!(cursor.prev(), None);
+        assert_eq!(cursor.next(), Some((0, 0)));
+        cursor.seek(35);
+        assert_eq!(cursor.next(), Some((40, 40)));
+        assert_eq!(cursor.next(), Some((50, 50)));
+        cursor.seek(50);
+        assert_eq!(cursor.next(), Some((50, 50)));
+        cursor.seek(50);
+        assert_eq!(cursor.prev(), Some((40, 40)));
+        cursor.seek(95);
+        assert_eq!(cursor.next(), None);
+        assert_eq!(cursor.next(), None);
+        assert_eq!(cursor.prev(), Some((90, 90)));
+    }
+
+    #[test]
+    fn test_cursor_prev_across_leaves() {
+        let tree = BwTree::with_split_threshold(8);
+        for key in 0..100u64 {
+            tree.insert(key, key).unwrap();
+        }
+        assert!(tree.stats().leaf_count > 2);
+        let mut cursor = tree.cursor();
+        cursor.seek(100);
+        let mut keys = Vec::new();
+        while let Some((key, _)) = cursor.prev() {
+            keys.push(key);
+        }
+        assert!(keys.into_iter().eq((0..100).rev()));
+        assert_eq!(cursor.prev(), None);
+        assert_eq!(cursor.next(), Some((0, 0)));
+    }
+
+    #[test]
+    fn test_cursor_current_key_deleted() {
+        let tree = BwTree::with_split_threshold(8);
+        for key in 0..50u64 {
+            tree.insert(key, key).unwrap();
+        }
+        let mut cursor = tree.cursor();
+        cursor.seek(20);
+        assert_eq!(cursor.next(), Some((20, 20)));
+        tree.delete(20).unwrap();
+        tree.delete(21).unwrap();
+        assert_eq!(cursor.next(), Some((22, 22)));
+        tree.delete(22).unwrap();
+        assert_eq!(cursor.prev(), Some((19, 19)));
+        tree.insert(20, 200).unwrap();
+        assert_eq!(cursor.next(), Some((20, 200)));
+        for key in 21..50 {
+            tree.delete(key).unwrap();
+        }
+        assert_eq!(cursor.next(), None);
+        assert_eq!(cursor.prev(), Some((20, 200)));
+    }
+
+    #[test]
+    fn test_range_rev() {
+        let tree = tree_with_leaves(&[&[1, 3, 5], &[10, 12, 14], &[20, 22]]);
+        assert_eq!(tree.delete(12), Ok(true));
+        for (start, end) in [(0, 100), (3, 21), (5, 10), (10, 11), (6, 9), (14, 3)] {
+            let mut expected: Vec<_> = tree.range(start, end).collect();
+            expected.reverse();
+            assert_eq!(tree.range_rev(start, end).collect::<Vec<_>>(), expected);
+        }
+        assert_eq!(
+            tree.range_rev(3, 21).map(|(k, _)| k).collect::<Vec<_>>(),
+            vec![20, 14, 10, 5, 3]
+        );
+    }
+
+    #[test]
+    fn test_prefix_successor() {
+        assert_eq!(prefix_successor(b"ab"), Some(b"ac".to_vec()));
+        assert_eq!(prefix_successor(&[0x01, 0xFF, 0xFF]), Some(vec![0x02]));
+        assert_eq!(prefix_successor(&[0xFF, 0xFF]), None);
+        assert_eq!(prefix_successor(&[]), None);
+    }
+
+    #[test]
+    fn test_prefix_scan() {
+        let tree = BwTree::with_split_threshold(2);
+        for key in ["a", "ab", "abc", "abd", "ac", "b", "ba"] {
+            assert_eq!(tree.insert(key.as_bytes().to_vec(), key), Ok(None));
+        }
+        let scan = |prefix: &str| -> Vec<_> {
+            tree.prefix_scan(prefix.as_bytes())
+                .map(|(_, v)| v)
+                .collect()
+        };
+        assert_eq!(scan("ab"), vec!["ab", "abc", "abd"]);
+        assert_eq!(scan("a"), vec!["a", "ab", "abc", "abd", "ac"]);
+        assert_eq!(scan("abc"), vec!["abc"]);
+        assert_eq!(scan("b"), vec!["b", "ba"]);
+        assert_eq!(scan("c"), Vec::<&str>::new());
+        assert_eq!(scan("").len(), 7);
+    }
+
+    #[test]
+    fn test_prefix_scan_all_ff_prefix() {
+        let tree = BwTree::new();
+        for key in [
+            vec![0xFE],
+            vec![0xFF],
+            vec![0xFF, 0xFE],
+            vec![0xFF, 0xFF],
+            vec![0xFF, 0xFF, 0x01],
+        ] {
+            assert_eq!(tree.insert(key.clone(), key), Ok(None));
+        }
+        let scan = |prefix: &[u8]| -> Vec<_> { tree.prefix_scan(prefix).map(|(k, _)| k).collect() };
+        assert_eq!(
+            scan(&[0xFF, 0xFF]),
+            vec![vec![0xFF, 0xFF], vec![0xFF, 0xFF, 0x01]]
+        );
+        assert_eq!(scan(&[0xFF]).len(), 4);
     }
 }
@@ -1,47 +1,387 @@
-use std::sync::atomic::{AtomicPtr, Ordering};
+use crate::sync::{AtomicPtr, AtomicUsize, Ordering};
+use std::alloc::Layout;
+use std::marker::PhantomData;
+
+/// The number of node slots in the first block of a packed list. Every
+/// further block holds twice as many slots as the one before it.
+const FIRST_BLOCK_LEN: usize = 4;
 
 /// A lock-free singly-linked list.
 #[derive(Debug)]
 pub struct LinkedList<T> {
     head: AtomicPtr<Node<T>>,
+    /// The number of nodes in the list.
+    len: AtomicUsize,
+    /// The blocks the nodes are carved out of, if the list is packed.
+    arena: Option<Arena<T>>,
 }
 
 impl<T> LinkedList<T> {
     pub fn new() -> Self {
         LinkedList {
             head: AtomicPtr::new(std::ptr::null_mut()),
+            len: AtomicUsize::new(0),
+            arena: None,
+        }
+    }
+
+    /// Creates an empty list whose nodes are carved out of blocks of slots
+    /// rather than allocated one at a time, so that a push only allocates
+    /// when the current block is full.
+    ///
+    /// Every block holds twice as many slots as the one before it, and the
+    /// blocks are only freed along with the list. That suits short-lived
+    /// lists of small values, like the delta chains of leaf-level nodes,
+    /// which are replaced as a whole once they grow long.
+    pub fn packed() -> Self {
+        LinkedList {
+            head: AtomicPtr::new(std::ptr::null_mut()),
+            len: AtomicUsize::new(0),
+            arena: Some(Arena::new()),
         }
     }
 
+    /// Returns the number of nodes in the list.
+    ///
+    /// The count is updated before a node is published and after nodes are
+    /// detached, so a concurrent reader may briefly observe a count that
+    /// includes nodes it cannot see.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+
     pub fn push_front(&self, value: T) {
-        let new_node = Box::into_raw(Box::new(Node {
-            value,
-            next: AtomicPtr::new(std::ptr::null_mut()),
-        }));
+        let pushed = self.push_front_if(value, |_, _| true);
+        debug_assert!(pushed.is_ok());
+    }
+
+    /// Pushes `value` to the front of the list if `accept` returns `true` for
+    /// it and the current contents of the list, or returns it back otherwise.
+    ///
+    /// If another node is pushed concurrently, `accept` is called again on the
+    /// new contents, so the value is only ever published on top of contents
+    /// that were accepted.
+    pub fn push_front_if(
+        &self,
+        value: T,
+        mut accept: impl FnMut(&T, Iter<'_, T>) -> bool,
+    ) -> Result<(), T> {
+        // The node is only allocated once the value is accepted, so that a
+        // rejected value costs no allocation, unless it is only rejected
+        // after a failed compare-and-swap.
+        let mut value = Some(value);
+        let mut new_node: *mut Node<T> = std::ptr::null_mut();
 
         loop {
             let head = self.head.load(Ordering::Acquire);
 
-            unsafe {
-                (*new_node).next.store(head, Ordering::Relaxed);
+            let contents = Iter {
+                next: unsafe { head.as_ref() },
+            };
+            let candidate = match &value {
+                Some(value) => value,
+                None => unsafe { &(*new_node).value },
+            };
+            if !accept(candidate, contents) {
+                return Err(match value {
+                    Some(value) => value,
+                    None => unsafe { self.discard(new_node) },
+                });
+            }
+
+            match value.take() {
+                Some(value) => {
+                    // Counted before it is published, so that nodes detached
+                    // by `take_chain` are never subtracted before they are
+                    // added.
+                    self.len.fetch_add(1, Ordering::AcqRel);
+                    new_node = self.alloc(Node {
+                        value,
+                        next: AtomicPtr::new(head),
+                    });
+                }
+                None => unsafe {
+                    (*new_node).next.store(head, Ordering::Relaxed);
+                },
             }
 
             if self
                 .head
-                .compare_exchange_weak(head, new_node, Ordering::Release, Ordering::Relaxed)
+                .compare_exchange(head, new_node, Ordering::Release, Ordering::Relaxed)
                 .is_ok()
             {
-                break;
+                return Ok(());
+            }
+        }
+    }
+
+    /// Detaches every node from the list at once, leaving it empty, and
+    /// returns them as a chain that frees them when dropped, or `None` if the
+    /// list was empty.
+    ///
+    /// A node pushed concurrently ends up either in the chain or in the list.
+    ///
+    /// # Safety
+    ///
+    /// Iterators over the list may still be walking the detached nodes, so no
+    /// iterator obtained before the call, including those handed to the
+    /// `accept` callback of `push_front_if`, may be alive when the chain is
+    /// dropped.
+    pub unsafe fn take_chain(&self) -> Option<Chain<'_, T>> {
+        let head = self.head.swap(std::ptr::null_mut(), Ordering::AcqRel);
+        if head.is_null() {
+            return None;
+        }
+        let chain = Chain {
+            head,
+            packed: self.arena.is_some(),
+            _list: PhantomData,
+        };
+        self.len.fetch_sub(chain.iter().count(), Ordering::AcqRel);
+        Some(chain)
+    }
+
+    /// Returns the number of bytes allocated for the nodes of the list, not
+    /// counting heap memory owned by the values.
+    pub fn memory_bytes(&self) -> usize {
+        match &self.arena {
+            Some(arena) => arena.memory_bytes(),
+            None => self.len() * std::mem::size_of::<Node<T>>(),
+        }
+    }
+
+    /// Moves `node` to where the nodes of the list live, and returns a
+    /// pointer to it there.
+    fn alloc(&self, node: Node<T>) -> *mut Node<T> {
+        match &self.arena {
+            Some(arena) => {
+                let slot = arena.alloc();
+                unsafe { slot.write(node) };
+                slot
             }
+            None => Box::into_raw(Box::new(node)),
+        }
+    }
+
+    /// Frees `node`, which was allocated for a push but never published, and
+    /// returns its value.
+    ///
+    /// # Safety
+    ///
+    /// `node` must have been returned by `alloc` and not been discarded yet.
+    unsafe fn discard(&self, node: *mut Node<T>) -> T {
+        self.len.fetch_sub(1, Ordering::AcqRel);
+        match &self.arena {
+            // The slot stays unused until the list is dropped.
+            Some(_) => unsafe { node.read() }.value,
+            None => unsafe { Box::from_raw(node) }.value,
         }
     }
 
+    /// Returns an iterator over the list, from the most recently pushed node.
+    ///
+    /// Nodes are published with release stores and followed with acquire
+    /// loads, so the iterator sees fully initialized nodes even while other
+    /// threads push. Nodes are only freed when the list itself is dropped,
+    /// which cannot happen while the iterator borrows it. A list that is
+    /// reached through a raw pointer, like the delta chain of a node in the
+    /// mapping table, must additionally be protected by an epoch guard that
+    /// outlives the iterator, so that the list is not retired and freed
+    /// underneath it.
     pub fn iter(&self) -> Iter<'_, T> {
-        let next = unsafe { self.head.load(Ordering::Relaxed).as_ref() };
+        let next = unsafe { self.head.load(Ordering::Acquire).as_ref() };
         Iter { next }
     }
 }
 
+impl<T> Drop for LinkedList<T> {
+    fn drop(&mut self) {
+        // No iterator can borrow the list while it is dropped, and the chain
+        // is dropped before the blocks it lives in.
+        drop(unsafe { self.take_chain() });
+    }
+}
+
+/// The nodes detached from a list by `LinkedList::take_chain`, which are freed
+/// when the chain is dropped.
+///
+/// The nodes of a packed list live in the blocks of the list, so the chain
+/// borrows the list, and only drops the values when it is dropped.
+pub struct Chain<'a, T> {
+    head: *mut Node<T>,
+    packed: bool,
+    _list: PhantomData<&'a LinkedList<T>>,
+}
+
+impl<T> Chain<'_, T> {
+    /// Returns an iterator over the chain, from the most recently pushed
+    /// node.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            next: unsafe { self.head.as_ref() },
+        }
+    }
+}
+
+impl<T> Drop for Chain<'_, T> {
+    fn drop(&mut self) {
+        let mut node = self.head;
+        while !node.is_null() {
+            let next = unsafe { (*node).next.load(Ordering::Acquire) };
+            if self.packed {
+                unsafe { std::ptr::drop_in_place(node) };
+            } else {
+                drop(unsafe { Box::from_raw(node) });
+            }
+            node = next;
+        }
+    }
+}
+
+/// The blocks of node slots of a packed list.
+///
+/// Slots are handed out in order by bumping a counter, and the blocks are
+/// linked from the first one, so that the block of a slot is found by
+/// walking the links. A thread that finds the block it needs missing
+/// allocates it and links it in with a compare-and-swap, and frees its own
+/// block again if another thread got in first.
+#[derive(Debug)]
+struct Arena<T> {
+    /// The index of the next slot to hand out, counting through the blocks.
+    next_slot: AtomicUsize,
+    /// The first block, or null until the first node is allocated.
+    first: AtomicPtr<Block<T>>,
+}
+
+impl<T> Arena<T> {
+    fn new() -> Self {
+        Arena {
+            next_slot: AtomicUsize::new(0),
+            first: AtomicPtr::new(std::ptr::null_mut()),
+        }
+    }
+
+    /// Returns the block index and the offset in that block of `slot`.
+    fn locate(slot: usize) -> (usize, usize) {
+        let block = (slot / FIRST_BLOCK_LEN + 1).ilog2() as usize;
+        (block, slot - FIRST_BLOCK_LEN * ((1 << block) - 1))
+    }
+
+    /// Returns a pointer to an unused slot.
+    fn alloc(&self) -> *mut Node<T> {
+        let (index, offset) = Self::locate(self.next_slot.fetch_add(1, Ordering::AcqRel));
+        let mut link = &self.first;
+        let mut block = std::ptr::null_mut();
+        for i in 0..=index {
+            block = link.load(Ordering::Acquire);
+            if block.is_null() {
+                let new = Block::alloc(i);
+                block = match link.compare_exchange(
+                    std::ptr::null_mut(),
+                    new,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                ) {
+                    Ok(_) => new,
+                    Err(current) => {
+                        unsafe { Block::free(new, i) };
+                        current
+                    }
+                };
+            }
+            link = unsafe { &(*block).next };
+        }
+        unsafe { Block::slot(block, index, offset) }
+    }
+
+    /// Returns the number of bytes allocated for the blocks.
+    fn memory_bytes(&self) -> usize {
+        let mut bytes = 0;
+        let mut block = self.first.load(Ordering::Acquire);
+        let mut index = 0;
+        while !block.is_null() {
+            bytes += Block::<T>::layout(index).0.size();
+            block = unsafe { &(*block).next }.load(Ordering::Acquire);
+            index += 1;
+        }
+        bytes
+    }
+}
+
+impl<T> Drop for Arena<T> {
+    fn drop(&mut self) {
+        // The values in the slots have been dropped along with the chain.
+        let mut block = self.first.load(Ordering::Acquire);
+        let mut index = 0;
+        while !block.is_null() {
+            let next = unsafe { &(*block).next }.load(Ordering::Acquire);
+            unsafe { Block::free(block, index) };
+            block = next;
+            index += 1;
+        }
+    }
+}
+
+/// A block of node slots of a packed list, which are laid out right behind
+/// it in the same allocation. Block `i` holds `FIRST_BLOCK_LEN << i` slots.
+#[repr(C)]
+struct Block<T> {
+    /// The next block, or null until it is needed.
+    next: AtomicPtr<Block<T>>,
+    _slots: PhantomData<Node<T>>,
+}
+
+impl<T> Block<T> {
+    /// Returns the layout of block `index` along with the offset of its first
+    /// slot.
+    fn layout(index: usize) -> (Layout, usize) {
+        let slots = Layout::array::<Node<T>>(FIRST_BLOCK_LEN << index).expect("block too large");
+        Layout::new::<Block<T>>()
+            .extend(slots)
+            .expect("block too large")
+    }
+
+    /// Allocates block `index`, with uninitialized slots.
+    fn alloc(index: usize) -> *mut Block<T> {
+        let (layout, _) = Self::layout(index);
+        let block = unsafe { std::alloc::alloc(layout) }.cast::<Block<T>>();
+        if block.is_null() {
+            std::alloc::handle_alloc_error(layout);
+        }
+        unsafe {
+            block.write(Block {
+                next: AtomicPtr::new(std::ptr::null_mut()),
+                _slots: PhantomData,
+            })
+        };
+        block
+    }
+
+    /// Returns a pointer to the slot at `offset` in `block`, block `index`.
+    ///
+    /// # Safety
+    ///
+    /// `block` must have been allocated as block `index`, and `offset` be less
+    /// than the number of slots of that block.
+    unsafe fn slot(block: *mut Block<T>, index: usize, offset: usize) -> *mut Node<T> {
+        let (_, start) = Self::layout(index);
+        unsafe { block.cast::<u8>().add(start).cast::<Node<T>>().add(offset) }
+    }
+
+    /// Frees `block`, block `index`, without dropping anything in its slots.
+    ///
+    /// # Safety
+    ///
+    /// `block` must have been allocated as block `index`, and must not be
+    /// used afterwards.
+    unsafe fn free(block: *mut Block<T>, index: usize) {
+        unsafe {
+            std::ptr::drop_in_place(block);
+            std::alloc::dealloc(block.cast(), Self::layout(index).0);
+        }
+    }
+}
+
 #[derive(Debug)]
 struct Node<T> {
     value: T,
@@ -52,12 +392,25 @@ pub struct Iter<'a, T> {
     next: Option<&'a Node<T>>,
 }
 
+impl<T> Clone for Iter<'_, T> {
+    fn clone(&self) -> Self {
+        Iter { next: self.next }
+    }
+}
+
+/// An iterator over an empty list.
+impl<T> Default for Iter<'_, T> {
+    fn default() -> Self {
+        Iter { next: None }
+    }
+}
+
 impl<'a, T> Iterator for Iter<'a, T> {
     type Item = &'a T;
 
     fn next(&mut self) -> Option<Self::Item> {
         if let Some(node) = self.next {
-            let next = unsafe { node.next.load(Ordering::Relaxed).as_ref() };
+            let next = unsafe { node.next.load(Ordering::Acquire).as_ref() };
             self.next = next;
             Some(&node.value)
         } else {
@@ -65,3 +418,239 @@ impl<'a, T> Iterator for Iter<'a, T> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+
+    struct DropCounter(Arc<AtomicUsize>);
+
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_drop_frees_every_node() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let list = LinkedList::new();
+        for _ in 0..100 {
+            list.push_front(DropCounter(drops.clone()));
+        }
+        assert_eq!(drops.load(Ordering::SeqCst), 0);
+        drop(list);
+        assert_eq!(drops.load(Ordering::SeqCst), 100);
+    }
+
+    #[test]
+    fn test_len() {
+        let list = LinkedList::new();
+        assert_eq!(list.len(), 0);
+        for i in 0..100 {
+            list.push_front(i);
+        }
+        assert_eq!(list.len(), 100);
+        assert_eq!(list.iter().count(), 100);
+    }
+
+    #[test]
+    fn test_len_concurrent_pushes() {
+        let list = LinkedList::new();
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for i in 0..1000 {
+                        list.push_front(i);
+                    }
+                });
+            }
+        });
+        assert_eq!(list.len(), 4000);
+        assert_eq!(list.iter().count(), 4000);
+    }
+
+    #[test]
+    fn test_iter_concurrent_pushes() {
+        let list = LinkedList::new();
+        let done = std::sync::atomic::AtomicBool::new(false);
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for i in 0..10_000 {
+                    list.push_front(i);
+                }
+                done.store(true, Ordering::SeqCst);
+            });
+            while !done.load(Ordering::SeqCst) {
+                // Every snapshot is a suffix of the pushes, newest first.
+                let values: Vec<_> = list.iter().copied().collect();
+                assert!(values.iter().rev().copied().eq(0..values.len()));
+            }
+        });
+        assert_eq!(list.iter().count(), 10_000);
+    }
+
+    #[test]
+    fn test_push_front_if() {
+        let list = LinkedList::new();
+        let unique = |value: &i32, mut contents: Iter<'_, i32>| !contents.any(|v| v == value);
+        assert_eq!(list.push_front_if(1, unique), Ok(()));
+        assert_eq!(list.push_front_if(2, unique), Ok(()));
+        assert_eq!(list.push_front_if(1, unique), Err(1));
+        assert_eq!(list.iter().copied().collect::<Vec<_>>(), vec![2, 1]);
+        assert_eq!(list.len(), 2);
+    }
+
+    #[test]
+    fn test_take_chain() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let list = LinkedList::new();
+        for i in 0..10 {
+            list.push_front((i, DropCounter(drops.clone())));
+        }
+        let chain = unsafe { list.take_chain() }.unwrap();
+        assert_eq!(list.len(), 0);
+        assert_eq!(list.iter().count(), 0);
+        assert!(chain.iter().map(|(i, _)| *i).eq((0..10).rev()));
+
+        list.push_front((10, DropCounter(drops.clone())));
+        assert_eq!(list.len(), 1);
+        assert_eq!(drops.load(Ordering::SeqCst), 0);
+        drop(chain);
+        assert_eq!(drops.load(Ordering::SeqCst), 10);
+        assert!(unsafe { list.take_chain() }.is_some());
+        assert!(unsafe { list.take_chain() }.is_none());
+        assert_eq!(drops.load(Ordering::SeqCst), 11);
+    }
+
+    #[test]
+    fn test_take_chain_concurrent_pushes() {
+        let list = LinkedList::new();
+        let mut taken = 0;
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for i in 0..10_000 {
+                    list.push_front(i);
+                }
+            });
+            for _ in 0..100 {
+                if let Some(chain) = unsafe { list.take_chain() } {
+                    taken += chain.iter().count();
+                }
+            }
+        });
+        assert_eq!(taken + list.iter().count(), 10_000);
+        assert_eq!(list.len(), list.iter().count());
+    }
+
+    #[test]
+    fn test_drop_empty() {
+        let list: LinkedList<DropCounter> = LinkedList::new();
+        drop(list);
+        let list: LinkedList<DropCounter> = LinkedList::packed();
+        drop(list);
+    }
+
+    #[test]
+    fn test_packed_drop_frees_every_node() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let list = LinkedList::packed();
+        for _ in 0..100 {
+            list.push_front(DropCounter(drops.clone()));
+        }
+        assert_eq!(drops.load(Ordering::SeqCst), 0);
+        drop(list);
+        assert_eq!(drops.load(Ordering::SeqCst), 100);
+    }
+
+    #[test]
+    fn test_packed_push_front_if() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let list = LinkedList::packed();
+        let unique = |value: &(i32, DropCounter), mut contents: Iter<'_, (i32, DropCounter)>| {
+            !contents.any(|v| v.0 == value.0)
+        };
+        assert!(list
+            .push_front_if((1, DropCounter(drops.clone())), unique)
+            .is_ok());
+        assert!(list
+            .push_front_if((2, DropCounter(drops.clone())), unique)
+            .is_ok());
+        let rejected = list.push_front_if((1, DropCounter(drops.clone())), unique);
+        assert_eq!(rejected.as_ref().map_err(|v| v.0), Err(1));
+        drop(rejected);
+        assert_eq!(drops.load(Ordering::SeqCst), 1);
+        assert!(list.iter().map(|v| v.0).eq([2, 1]));
+        assert_eq!(list.len(), 2);
+        drop(list);
+        assert_eq!(drops.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_packed_concurrent_pushes() {
+        let list = LinkedList::packed();
+        std::thread::scope(|s| {
+            for t in 0..4 {
+                let list = &list;
+                s.spawn(move || {
+                    for i in 0..1000 {
+                        list.push_front(t * 1000 + i);
+                    }
+                });
+            }
+        });
+        assert_eq!(list.len(), 4000);
+        let mut values: Vec<_> = list.iter().copied().collect();
+        values.sort();
+        assert!(values.into_iter().eq(0..4000));
+    }
+
+    #[test]
+    fn test_packed_take_chain() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let list = LinkedList::packed();
+        for i in 0..10 {
+            list.push_front((i, DropCounter(drops.clone())));
+        }
+        let chain = unsafe { list.take_chain() }.unwrap();
+        assert_eq!(list.len(), 0);
+        assert!(chain.iter().map(|(i, _)| *i).eq((0..10).rev()));
+
+        list.push_front((10, DropCounter(drops.clone())));
+        drop(chain);
+        assert_eq!(drops.load(Ordering::SeqCst), 10);
+        assert!(list.iter().map(|(i, _)| *i).eq([10]));
+        drop(list);
+        assert_eq!(drops.load(Ordering::SeqCst), 11);
+    }
+
+    #[test]
+    fn test_packed_memory_bytes() {
+        let list = LinkedList::packed();
+        assert_eq!(list.memory_bytes(), 0);
+        list.push_front(0u64);
+        let first = list.memory_bytes();
+        assert!(first >= FIRST_BLOCK_LEN * std::mem::size_of::<Node<u64>>());
+        // The rest of the first block is filled without allocating.
+        for i in 1..FIRST_BLOCK_LEN as u64 {
+            list.push_front(i);
+        }
+        assert_eq!(list.memory_bytes(), first);
+        list.push_front(FIRST_BLOCK_LEN as u64);
+        assert!(list.memory_bytes() >= 3 * FIRST_BLOCK_LEN * std::mem::size_of::<Node<u64>>());
+    }
+
+    #[test]
+    fn test_arena_locate() {
+        let mut expected = Vec::new();
+        for block in 0..4 {
+            for offset in 0..FIRST_BLOCK_LEN << block {
+                expected.push((block, offset));
+            }
+        }
+        let located: Vec<_> = (0..expected.len()).map(Arena::<u64>::locate).collect();
+        assert_eq!(located, expected);
+    }
+}
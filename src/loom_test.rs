@@ -0,0 +1,118 @@
+//! Exhaustive checks of the lock-free primitives, which loom runs under every
+//! interleaving of the threads in a model.
+//!
+//! Run with `RUSTFLAGS="--cfg loom" cargo test --release --lib loom_test`.
+
+use crate::linked_list::LinkedList;
+use crate::{free_node, LeafNode, MappingTable, Node};
+use loom::sync::Arc;
+use loom::thread;
+
+#[test]
+fn test_concurrent_push_front_loses_no_node() {
+    loom::model(|| {
+        let list = Arc::new(LinkedList::new());
+        let threads: Vec<_> = (0..2)
+            .map(|t| {
+                let list = list.clone();
+                thread::spawn(move || list.push_front(t))
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let mut values: Vec<_> = list.iter().copied().collect();
+        values.sort();
+        assert_eq!(values, vec![0, 1]);
+        assert_eq!(list.len(), 2);
+    });
+}
+
+#[test]
+fn test_concurrent_packed_push_front_loses_no_node() {
+    loom::model(|| {
+        let list = Arc::new(LinkedList::packed());
+        // Both threads need the second block, which neither has allocated.
+        for i in 0..4 {
+            list.push_front(i);
+        }
+        let threads: Vec<_> = (4..6)
+            .map(|t| {
+                let list = list.clone();
+                thread::spawn(move || list.push_front(t))
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let mut values: Vec<_> = list.iter().copied().collect();
+        values.sort();
+        assert_eq!(values, vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(list.len(), 6);
+    });
+}
+
+#[test]
+fn test_iter_during_push_front() {
+    loom::model(|| {
+        let list = Arc::new(LinkedList::new());
+        list.push_front(0);
+        let pusher = {
+            let list = list.clone();
+            thread::spawn(move || {
+                list.push_front(1);
+                list.push_front(2);
+            })
+        };
+        // Every snapshot is a suffix of the pushes, newest first.
+        let values: Vec<_> = list.iter().copied().collect();
+        assert!(values.iter().rev().copied().eq(0..values.len()));
+        pusher.join().unwrap();
+        assert!(list.iter().copied().eq([2, 1, 0]));
+    });
+}
+
+#[test]
+fn test_concurrent_cas_replaces_every_node_once() {
+    loom::model(|| {
+        let table = Arc::new(MappingTable::<u64, u64>::new());
+        table.insert(0, Node::Leaf(LeafNode::new()));
+        let threads: Vec<_> = (0..2u64)
+            .map(|t| {
+                let table = table.clone();
+                thread::spawn(move || {
+                    let mut leaf = LeafNode::new();
+                    leaf.keys.push(t);
+                    leaf.values.push(t);
+                    leaf.count = 1;
+                    let expected = table.load(0);
+                    // Raw pointers are not `Send`, so the replaced one is
+                    // handed back as an address.
+                    table
+                        .cas(0, expected, Node::Leaf(leaf))
+                        .map(|()| expected as usize)
+                        .map_err(|actual| actual as usize)
+                })
+            })
+            .collect();
+        let results: Vec<_> = threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect();
+        let replaced: Vec<_> = results.iter().filter_map(|r| r.ok()).collect();
+        assert!(!replaced.is_empty());
+        // A node replaced by two writes would mean one of them was lost.
+        assert!(replaced.len() == 1 || replaced[0] != replaced[1]);
+        // A failed write observed the node the other one installed.
+        for actual in results.iter().filter_map(|r| r.err()) {
+            assert_eq!(actual, table.load(0) as usize);
+        }
+        for ptr in replaced {
+            unsafe { free_node::<u64, u64>(ptr as *mut u8) };
+        }
+        match table.get(0) {
+            Node::Leaf(leaf) => assert_eq!(leaf.count, 1),
+            _ => panic!("not a leaf"),
+        }
+    });
+}
@@ -0,0 +1,116 @@
+use crate::{BwError, BwTree, KeyType};
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A Bw-Tree that holds any number of values per key.
+///
+/// Every value is stored as a separate entry of an ordinary Bw-Tree, keyed by
+/// the key and a sequence number that grows with every insert. The entries of
+/// a key are therefore adjacent in the leaf level, and ordered by insertion.
+pub struct BwTreeMultimap<K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    tree: BwTree<(K, u64), V>,
+    /// The sequence number of the next insert.
+    next_seq: AtomicU64,
+}
+
+impl<K, V> BwTreeMultimap<K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    pub fn new() -> Self {
+        BwTreeMultimap {
+            tree: BwTree::new(),
+            next_seq: AtomicU64::new(0),
+        }
+    }
+
+    /// Appends `value` to the values of `key`.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `BwTree::insert`.
+    pub fn insert(&self, key: K, value: V) -> Result<(), BwError> {
+        let seq = self.next_seq.fetch_add(1, Ordering::SeqCst);
+        self.tree.insert((key, seq), value)?;
+        Ok(())
+    }
+
+    /// Returns copies of all values of `key`, in the order they were
+    /// inserted.
+    pub fn get_all(&self, key: K) -> Vec<V> {
+        self.entries(key).map(|(_, value)| value).collect()
+    }
+
+    /// Deletes the oldest value of `key` that equals `value`, returning `true`
+    /// if there was one.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `BwTree::delete`.
+    pub fn delete(&self, key: K, value: &V) -> Result<bool, BwError>
+    where
+        V: PartialEq,
+    {
+        for (seq_key, current) in self.entries(key) {
+            // The value may have been deleted by another thread since the
+            // scan read it, in which case the next match is tried.
+            if current == *value && self.tree.remove_if(seq_key, |v| v == value)?.is_some() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Deletes all values of `key`, returning how many were deleted.
+    ///
+    /// Values inserted concurrently may or may not be deleted.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `BwTree::delete`, in which case only some of the values
+    /// have been deleted.
+    pub fn delete_all(&self, key: K) -> Result<usize, BwError> {
+        let mut deleted = 0;
+        for (seq_key, _) in self.entries(key) {
+            if self.tree.delete(seq_key)? {
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Returns `true` if `key` has at least one value.
+    pub fn contains_key(&self, key: K) -> bool {
+        self.entries(key).next().is_some()
+    }
+
+    /// Returns the number of values over all keys.
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    /// Returns `true` if no key has any values.
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// Returns an iterator over the entries of `key` in the underlying tree.
+    fn entries(&self, key: K) -> impl Iterator<Item = ((K, u64), V)> + '_ {
+        self.tree.range_inclusive((key.clone(), 0), (key, u64::MAX))
+    }
+}
+
+impl<K, V> Default for BwTreeMultimap<K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    fn default() -> Self {
+        BwTreeMultimap::new()
+    }
+}
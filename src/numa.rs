@@ -0,0 +1,259 @@
+//! Striping of node IDs across NUMA nodes.
+//!
+//! Every NUMA node claims node IDs a stripe at a time, a page of mapping table
+//! entries, and hands them out to the threads running on it. The entries of
+//! the nodes a thread allocates thus share cache lines and pages only with
+//! those of threads on the same NUMA node. Mapping table segments are
+//! allocated zeroed, so that with the first-touch placement of Linux, the page
+//! of a stripe lands on the NUMA node of the thread that first maps an ID of
+//! it.
+
+use crate::{BwError, NodeID, FIRST_SEGMENT_SIZE, MAX_NODE_ID};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+/// The number of node IDs in a stripe, which is a page of mapping table
+/// entries.
+const STRIPE_LEN: usize = 4096 / std::mem::size_of::<usize>();
+
+/// The stripes of node IDs the NUMA nodes hand out.
+pub(crate) struct NodeIdStripes {
+    /// The next ID of the stripe of every NUMA node, which is at a stripe
+    /// boundary or beyond `MAX_NODE_ID` once the stripe runs out.
+    cursors: Box<[Cursor]>,
+}
+
+/// Keeps the cursors of different NUMA nodes out of each other's cache lines.
+#[repr(align(128))]
+struct Cursor(AtomicUsize);
+
+impl NodeIdStripes {
+    pub(crate) fn new() -> Self {
+        NodeIdStripes {
+            cursors: (0..node_count())
+                .map(|_| Cursor(AtomicUsize::new(MAX_NODE_ID + 1)))
+                .collect(),
+        }
+    }
+
+    /// Allocates an ID from the stripe of NUMA node `node`, claiming the next
+    /// stripe from `next_unused` if it ran out.
+    ///
+    /// Returns `BwError::MappingTableFull` if there is no stripe left.
+    pub(crate) fn next(&self, node: usize, next_unused: &AtomicUsize) -> Result<NodeID, BwError> {
+        let cursor = &self.cursors[node % self.cursors.len()].0;
+        let mut next = cursor.load(Ordering::Acquire);
+        while next <= MAX_NODE_ID && !Self::is_boundary(next) {
+            match cursor.compare_exchange_weak(next, next + 1, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => return Ok(next),
+                Err(actual) => next = actual,
+            }
+        }
+        let start = next_unused
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |id| {
+                (id <= MAX_NODE_ID).then(|| Self::stripe_end(id))
+            })
+            .map_err(|_| BwError::MappingTableFull)?;
+        // If another thread on the node claimed a stripe first, the rest of
+        // this one goes unused.
+        let _ = cursor.compare_exchange(next, start + 1, Ordering::AcqRel, Ordering::Acquire);
+        Ok(start)
+    }
+
+    /// Makes every NUMA node claim a new stripe for its next ID.
+    #[cfg(test)]
+    pub(crate) fn exhaust(&self) {
+        for cursor in self.cursors.iter() {
+            cursor.0.store(MAX_NODE_ID + 1, Ordering::SeqCst);
+        }
+    }
+
+    /// Returns whether a stripe starts at `id`.
+    ///
+    /// Stripes are aligned to the mapping table segments, which start at
+    /// `FIRST_SEGMENT_SIZE` short of a power of two, so that no stripe
+    /// straddles two segments that are larger than one.
+    fn is_boundary(id: NodeID) -> bool {
+        (id + FIRST_SEGMENT_SIZE).is_multiple_of(STRIPE_LEN)
+    }
+
+    /// Returns the end of the stripe `id` is in.
+    fn stripe_end(id: NodeID) -> NodeID {
+        ((id + FIRST_SEGMENT_SIZE) / STRIPE_LEN + 1)
+            .checked_mul(STRIPE_LEN)
+            .map_or(MAX_NODE_ID + 1, |end| {
+                (end - FIRST_SEGMENT_SIZE).min(MAX_NODE_ID + 1)
+            })
+    }
+}
+
+impl Default for NodeIdStripes {
+    fn default() -> Self {
+        NodeIdStripes::new()
+    }
+}
+
+thread_local! {
+    /// The NUMA node of the thread, once it is known.
+    static NODE: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// Returns the NUMA node the calling thread ran on when it first asked.
+///
+/// The node is looked up once per thread rather than on every allocation of
+/// a node ID, so a thread that migrates to another NUMA node keeps
+/// allocating from the stripes of the node it started on.
+pub(crate) fn current_node() -> usize {
+    NODE.with(|node| match node.get() {
+        Some(node) => node,
+        None => {
+            let current = query_node();
+            node.set(Some(current));
+            current
+        }
+    })
+}
+
+/// Returns the NUMA node the calling thread is running on, or 0 if it isn't
+/// known.
+#[cfg(target_os = "linux")]
+fn query_node() -> usize {
+    let mut cpu: libc::c_uint = 0;
+    let mut node: libc::c_uint = 0;
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_getcpu,
+            &mut cpu as *mut libc::c_uint,
+            &mut node as *mut libc::c_uint,
+            std::ptr::null_mut::<libc::c_void>(),
+        )
+    };
+    if ret == 0 {
+        node as usize
+    } else {
+        0
+    }
+}
+
+/// Returns the NUMA node the calling thread is running on, or 0 if it isn't
+/// known.
+#[cfg(not(target_os = "linux"))]
+fn query_node() -> usize {
+    0
+}
+
+/// Returns the number of NUMA nodes of the machine, as listed by sysfs, or 1
+/// if they aren't known.
+fn node_count() -> usize {
+    static COUNT: OnceLock<usize> = OnceLock::new();
+    *COUNT.get_or_init(|| {
+        // The possible nodes are listed as ranges, like `0-3`.
+        std::fs::read_to_string("/sys/devices/system/node/possible")
+            .ok()
+            .and_then(|nodes| {
+                let last = nodes.trim().rsplit([',', '-']).next()?;
+                last.parse::<usize>().ok()
+            })
+            .map_or(1, |last| last + 1)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::BwTree;
+    use std::collections::{BTreeMap, BTreeSet};
+
+    /// Returns the stripe `id` is in.
+    fn stripe(id: NodeID) -> usize {
+        (id + FIRST_SEGMENT_SIZE) / STRIPE_LEN
+    }
+
+    #[test]
+    fn test_stripes_keep_ids_of_a_numa_node_together() {
+        let stripes = NodeIdStripes {
+            cursors: (0..4)
+                .map(|_| Cursor(AtomicUsize::new(MAX_NODE_ID + 1)))
+                .collect(),
+        };
+        let next_unused = AtomicUsize::new(1);
+        let per_node = 4 * STRIPE_LEN;
+        let mut ids = BTreeMap::new();
+        // The NUMA nodes take turns, as if their threads allocated at once.
+        for _ in 0..per_node {
+            for node in 0..4 {
+                let id = stripes.next(node, &next_unused).unwrap();
+                assert!(ids.insert(id, node).is_none());
+            }
+        }
+        let mut owners = BTreeMap::new();
+        for (&id, &node) in &ids {
+            assert!(id < next_unused.load(Ordering::SeqCst));
+            assert_eq!(*owners.entry(stripe(id)).or_insert(node), node);
+        }
+        // Apart from the first stripe, which is shorter, every NUMA node fills
+        // up whole stripes, so its IDs span as few of them as possible.
+        for node in 0..4 {
+            let used: BTreeSet<_> = owners.iter().filter(|(_, &n)| n == node).collect();
+            assert!(used.len() <= per_node / STRIPE_LEN + 1, "{}", used.len());
+        }
+    }
+
+    #[test]
+    fn test_stripes_report_full_mapping_table() {
+        let stripes = NodeIdStripes::new();
+        let next_unused = AtomicUsize::new(MAX_NODE_ID - 2);
+        let ids: Vec<_> = (0..3).map(|_| stripes.next(0, &next_unused)).collect();
+        assert_eq!(
+            ids,
+            vec![Ok(MAX_NODE_ID - 2), Ok(MAX_NODE_ID - 1), Ok(MAX_NODE_ID)]
+        );
+        assert_eq!(
+            stripes.next(0, &next_unused),
+            Err(BwError::MappingTableFull)
+        );
+    }
+
+    #[test]
+    fn test_current_node_is_within_node_count() {
+        assert!(current_node() < node_count());
+        assert_eq!(current_node(), current_node());
+    }
+
+    #[test]
+    fn test_new_tree_reserves_its_first_node_ids() {
+        // Another NUMA node, as if the thread had migrated, must not change
+        // the IDs of the root and the first leaf.
+        NODE.with(|node| node.set(Some(node_count() + 1)));
+        let tree: BwTree<u64, u64> = BwTree::new();
+        assert_eq!(tree.root_id(), crate::ROOT_NODE_ID);
+        assert!(tree.get(0).is_none());
+        tree.insert(0, 0).unwrap();
+        assert_eq!(tree.get(0).as_deref(), Some(&0));
+        NODE.with(|node| node.set(None));
+    }
+
+    #[test]
+    fn test_concurrent_node_ids_are_unique() {
+        let tree: BwTree<u64, u64> = BwTree::new();
+        let ids: Vec<Vec<_>> = std::thread::scope(|s| {
+            let threads: Vec<_> = (0..4)
+                .map(|_| {
+                    s.spawn(|| {
+                        (0..1000)
+                            .map(|_| tree.get_next_node_id().unwrap())
+                            .collect()
+                    })
+                })
+                .collect();
+            threads.into_iter().map(|t| t.join().unwrap()).collect()
+        });
+        let unique: BTreeSet<_> = ids.iter().flatten().collect();
+        assert_eq!(unique.len(), 4000);
+        assert!(unique
+            .into_iter()
+            .all(|&id| id < tree.next_unused_node_id.load(Ordering::SeqCst)));
+    }
+}
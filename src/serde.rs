@@ -0,0 +1,64 @@
+use crate::{BwTree, KeyType};
+use serde::de::{Deserialize, Deserializer, Error};
+use serde::ser::{Serialize, Serializer};
+use std::fmt::Debug;
+
+/// Serializes the live entries of the tree as a sequence of key/value pairs
+/// in ascending key order.
+///
+/// Only the logical contents are serialized, not the layout of the nodes and
+/// delta chains. Entries written concurrently may or may not be included.
+impl<K, V> Serialize for BwTree<K, V>
+where
+    K: KeyType + Debug + Serialize,
+    V: Clone + Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+/// Deserializes a sequence of key/value pairs in strictly ascending key
+/// order, as produced by `Serialize`, by bulk-loading them with
+/// `BwTree::from_sorted`.
+impl<'de, K, V> Deserialize<'de> for BwTree<K, V>
+where
+    K: KeyType + Debug + Deserialize<'de>,
+    V: Clone + Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let entries = Vec::<(K, V)>::deserialize(deserializer)?;
+        BwTree::from_sorted(entries).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::BwTree;
+
+    #[test]
+    fn test_round_trip() {
+        let tree = BwTree::new();
+        for i in 0..500u64 {
+            tree.insert(i * 7 % 500, format!("value {i}")).unwrap();
+        }
+        for i in (0..500).step_by(3) {
+            tree.delete(i).unwrap();
+        }
+        let json = serde_json::to_string(&tree).unwrap();
+        assert!(json.starts_with(r#"[[1,"value "#));
+        let loaded: BwTree<u64, String> = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.len(), tree.len());
+        assert!(loaded.iter().eq(tree.iter()));
+        loaded.verify().unwrap();
+    }
+
+    #[test]
+    fn test_deserialize_rejects_unsorted_entries() {
+        let err = serde_json::from_str::<BwTree<u64, u64>>("[[1,1],[3,3],[2,2]]").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "entry 2 is not greater than the entry before it"
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use crate::comparator::KeyOrder;
+use crate::epoch;
+use crate::linked_list;
+use crate::{BwTree, DeltaNode, DeltaRecord, KeyType, LeafNode, Node};
+use std::fmt::Debug;
+
+/// A read-only view of a Bw-Tree that doesn't change as the tree is
+/// modified, as returned by `BwTree::snapshot`.
+///
+/// Delta records are only ever prepended to a delta chain, so the snapshot
+/// remembers the head of the chain of every leaf-level node, and reads
+/// through the snapshot ignore everything prepended later. The epoch stays
+/// pinned while the snapshot is alive, so that the nodes it refers to are
+/// not freed when writers consolidate, split, or merge them. Memory retired
+/// in the meantime is not reclaimed until the snapshot is dropped.
+///
+/// The nodes are captured one after the other, so writes that race with
+/// `BwTree::snapshot` may be visible in some nodes and not in others.
+/// Writes that start after it returns are never visible.
+pub struct Snapshot<'a, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    /// The leaf-level nodes in ascending key order.
+    nodes: Vec<Frozen<'a, K, V>>,
+    /// The key order of the tree.
+    order: &'a KeyOrder<K>,
+    _pin: epoch::Guard<'a>,
+}
+
+/// A leaf-level node as it was when the snapshot was taken.
+struct Frozen<'a, K, V> {
+    /// The lowest key of the node's key range.
+    low: K,
+    /// The delta chain of the node, or no records for a bare leaf.
+    records: linked_list::Iter<'a, DeltaRecord<K, V>>,
+    base: &'a LeafNode<K, V>,
+}
+
+impl<'a, K, V> Snapshot<'a, K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    pub(crate) fn new(tree: &'a BwTree<K, V>) -> Self {
+        let pin = tree.epoch.pin();
+        let mut nodes = Vec::new();
+        let mut low = tree.order.minimum();
+        let mut next = Some(tree.find_leaf(&low));
+        // Walk the leaf level along the sibling links, which skip nodes that
+        // were merged into their left sibling and reach the right halves of
+        // splits whose index term is not posted yet.
+        while let Some(node_id) = next {
+            let (records, base) = match tree.mapping_table.get(node_id) {
+                Node::Delta(delta) => (delta.records.iter(), delta.base()),
+                Node::Leaf(leaf) => (linked_list::Iter::default(), leaf),
+                _ => unreachable!("inner node at leaf level"),
+            };
+            let split = DeltaNode::split_records(records.clone(), base);
+            nodes.push(Frozen { low, records, base });
+            (low, next) = match split {
+                Some((high_key, right)) => (high_key.clone(), Some(right)),
+                None => break,
+            };
+        }
+        Snapshot {
+            nodes,
+            order: &tree.order,
+            _pin: pin,
+        }
+    }
+
+    /// Returns a reference to the value of `key`.
+    pub fn get(&self, key: K) -> Option<&V> {
+        self.nodes[self.position(&key)].get(self.order, &key)
+    }
+
+    /// Returns `true` if the snapshot contains a live entry for `key`.
+    pub fn contains_key(&self, key: K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Returns an iterator over the entries with keys in `start..end`, in
+    /// ascending key order.
+    pub fn range(&self, start: K, end: K) -> SnapshotIter<'_, 'a, K, V> {
+        SnapshotIter {
+            nodes: self.nodes[self.position(&start)..].iter(),
+            order: self.order,
+            buffer: Vec::new(),
+            start,
+            end: Some(end),
+        }
+    }
+
+    /// Returns an iterator over all entries in ascending key order.
+    pub fn iter(&self) -> SnapshotIter<'_, 'a, K, V> {
+        SnapshotIter {
+            nodes: self.nodes.iter(),
+            order: self.order,
+            buffer: Vec::new(),
+            start: self.order.minimum(),
+            end: None,
+        }
+    }
+
+    /// Returns the index of the node whose key range covers `key`.
+    fn position(&self, key: &K) -> usize {
+        // The first node covers the minimum key, so the partition point is
+        // never 0.
+        self.nodes
+            .partition_point(|node| self.order.le(&node.low, key))
+            - 1
+    }
+}
+
+impl<'a, K, V> Frozen<'a, K, V>
+where
+    K: KeyType,
+    V: Clone,
+{
+    /// Looks up `key`, which must be within the key range of the node.
+    ///
+    /// Unlike a lookup in the tree, this never follows a link to another
+    /// node, whose current contents may be newer than the snapshot.
+    fn get(&self, order: &KeyOrder<K>, key: &K) -> Option<&'a V> {
+        for record in self.records.clone() {
+            match record {
+                DeltaRecord::Insert(k, v) | DeltaRecord::Update(k, v) if order.eq(k, key) => {
+                    return Some(v);
+                }
+                DeltaRecord::Delete(k) if order.eq(k, key) => return None,
+                DeltaRecord::Merge(separator, merged) if order.le(separator, key) => {
+                    return Self::search(order, merged, key);
+                }
+                // Split records only hand over keys beyond the range of the
+                // node, and removal is only complete once the left sibling
+                // holds a merge record, which the snapshot then routes to.
+                _ => {}
+            }
+        }
+        Self::search(order, self.base, key)
+    }
+
+    fn search(order: &KeyOrder<K>, leaf: &'a LeafNode<K, V>, key: &K) -> Option<&'a V> {
+        let idx = order.binary_search(&leaf.keys, key).ok()?;
+        Some(&leaf.values[idx])
+    }
+}
+
+/// An iterator over a range of entries in a snapshot, in ascending key order.
+pub struct SnapshotIter<'s, 'a, K, V> {
+    nodes: std::slice::Iter<'s, Frozen<'a, K, V>>,
+    /// The key order of the tree.
+    order: &'a KeyOrder<K>,
+    /// Buffered entries of the current node, in descending key order.
+    buffer: Vec<(K, V)>,
+    /// The inclusive lower bound of the range.
+    start: K,
+    /// The exclusive upper bound of the range, if any.
+    end: Option<K>,
+}
+
+impl<K, V> Iterator for SnapshotIter<'_, '_, K, V>
+where
+    K: KeyType,
+    V: Clone,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.buffer.pop() {
+                return Some(entry);
+            }
+            let node = self.nodes.next()?;
+            if self
+                .end
+                .as_ref()
+                .is_some_and(|end| self.order.le(end, &node.low))
+            {
+                return None;
+            }
+            let leaf = DeltaNode::consolidate_records(self.order, node.records.clone(), node.base);
+            let range = leaf.range(self.order, &self.start, self.end.as_ref());
+            self.buffer = leaf
+                .keys
+                .into_iter()
+                .zip(leaf.values)
+                .skip(range.start)
+                .take(range.len())
+                .rev()
+                .collect();
+        }
+    }
+}
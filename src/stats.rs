@@ -0,0 +1,20 @@
+/// Statistics about the shape of a Bw-Tree, as returned by `BwTree::stats`.
+///
+/// The statistics are gathered by walking the tree without synchronizing with
+/// writers, so they are only a consistent snapshot if the tree is not modified
+/// concurrently.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of nodes reachable from the root.
+    pub node_count: usize,
+    /// The number of leaf-level nodes, with or without a delta chain.
+    pub leaf_count: usize,
+    /// The number of inner nodes, including the root.
+    pub inner_count: usize,
+    /// The number of delta records over all delta chains.
+    pub total_delta_records: usize,
+    /// The number of delta records in the longest delta chain.
+    pub longest_delta_chain: usize,
+    /// The number of live entries in the leaf-level nodes.
+    pub live_keys: usize,
+}
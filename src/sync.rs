@@ -0,0 +1,10 @@
+//! The atomics the lock-free primitives are built on.
+//!
+//! These are the `std` atomics, unless the crate is built with `--cfg loom`,
+//! in which case they are the ones of `loom`, so that the loom tests can
+//! explore every interleaving of the primitives.
+
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+#[cfg(not(loom))]
+pub(crate) use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
@@ -0,0 +1,307 @@
+use crate::{BwTree, Guard, Iter, KeyType, WalError};
+use std::fmt::Debug;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// The tag of a logged insert.
+const INSERT: u8 = 1;
+
+/// The tag of a logged delete.
+const DELETE: u8 = 2;
+
+/// The size of the length prefix and the checksum in front of every record.
+const HEADER_LEN: usize = 8;
+
+/// A type that keys and values must be for writing them to a write-ahead log.
+pub trait WalCodec: Sized {
+    /// Appends the encoding of the value to `buf`.
+    fn encode(&self, buf: &mut Vec<u8>);
+
+    /// Decodes a value from all of `bytes`, or returns `None` if they are not
+    /// the encoding of one.
+    fn decode(bytes: &[u8]) -> Option<Self>;
+}
+
+macro_rules! impl_wal_codec_for_integer {
+    ($($t:ty),*) => {
+        $(
+            impl WalCodec for $t {
+                fn encode(&self, buf: &mut Vec<u8>) {
+                    buf.extend_from_slice(&self.to_le_bytes());
+                }
+
+                fn decode(bytes: &[u8]) -> Option<Self> {
+                    bytes.try_into().ok().map(<$t>::from_le_bytes)
+                }
+            }
+        )*
+    };
+}
+
+impl_wal_codec_for_integer!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+impl WalCodec for Vec<u8> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self);
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        Some(bytes.to_vec())
+    }
+}
+
+impl WalCodec for String {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+}
+
+/// A Bw-Tree whose writes are appended to a write-ahead log before they are
+/// applied, so that the tree can be rebuilt from the log after a crash.
+///
+/// Every record in the log starts with the length of its payload and a
+/// CRC-32 checksum of it. The payload is the tag of the write, the length of
+/// the key and the key itself, followed by the value for an insert. Writes are
+/// serialized through the log, so that the log holds them in the order they
+/// were applied in. Reads go to the tree directly.
+///
+/// Every record is handed to the operating system before the write returns,
+/// so it survives the process crashing. Call `sync` to make the writes so far
+/// survive the machine crashing as well.
+pub struct DurableBwTree<K, V>
+where
+    K: KeyType + Debug,
+    V: Clone,
+{
+    pub(crate) tree: BwTree<K, V>,
+    log: Mutex<Log>,
+}
+
+/// The log file, along with its length, which is where the next record goes.
+struct Log {
+    file: File,
+    len: u64,
+}
+
+impl<K, V> DurableBwTree<K, V>
+where
+    K: KeyType + Debug + WalCodec,
+    V: Clone + WalCodec,
+{
+    /// Creates an empty tree that logs to a new file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if the file cannot be created, including if it
+    /// exists already, in which case it should be passed to `recover`.
+    pub fn with_wal(path: impl AsRef<Path>) -> Result<Self, WalError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create_new(true)
+            .open(path)?;
+        Ok(DurableBwTree {
+            tree: BwTree::new(),
+            log: Mutex::new(Log { file, len: 0 }),
+        })
+    }
+
+    /// Rebuilds a tree by replaying the log at `path`, and keeps logging to
+    /// it.
+    ///
+    /// Replay stops at the first record that is cut short or fails its
+    /// checksum, which is what the last record looks like if the process
+    /// crashed while writing it, and the log is truncated there.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if the file cannot be read or truncated,
+    /// `WalError::Corrupt` if a record passes its checksum but doesn't decode,
+    /// and `WalError::Tree` if the tree fails a replayed write.
+    pub fn recover(path: impl AsRef<Path>) -> Result<Self, WalError> {
+        let mut file = OpenOptions::new().read(true).append(true).open(path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let tree = BwTree::new();
+        let mut offset = 0;
+        while let Some(payload) = next_record(&bytes[offset..]) {
+            Self::replay(&tree, payload, offset as u64)?;
+            offset += HEADER_LEN + payload.len();
+        }
+        let len = offset as u64;
+        if offset < bytes.len() {
+            file.set_len(len)?;
+        }
+        Ok(DurableBwTree {
+            tree,
+            log: Mutex::new(Log { file, len }),
+        })
+    }
+
+    /// Applies the write logged in `payload`, the payload of the record at
+    /// `offset`, to `tree`.
+    fn replay(tree: &BwTree<K, V>, payload: &[u8], offset: u64) -> Result<(), WalError> {
+        let corrupt = || WalError::Corrupt(offset);
+        let (&tag, rest) = payload.split_first().ok_or_else(corrupt)?;
+        let (key_len, rest) = rest.split_first_chunk::<4>().ok_or_else(corrupt)?;
+        let (key, value) = rest
+            .split_at_checked(u32::from_le_bytes(*key_len) as usize)
+            .ok_or_else(corrupt)?;
+        let key = K::decode(key).ok_or_else(corrupt)?;
+        match tag {
+            INSERT => {
+                tree.insert(key, V::decode(value).ok_or_else(corrupt)?)?;
+            }
+            DELETE if value.is_empty() => {
+                tree.delete(key)?;
+            }
+            _ => return Err(corrupt()),
+        }
+        Ok(())
+    }
+
+    /// Logs and then inserts `value` for `key`, returning the previous value
+    /// of `key`, or `None` if the key was not present.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if the record cannot be written, and
+    /// `WalError::Tree` if the tree fails the insert like `BwTree::insert`,
+    /// in which case the record is taken back out of the log. A tree only
+    /// fails writes it hasn't applied, so the log never holds one of them.
+    pub fn insert(&self, key: K, value: V) -> Result<Option<V>, WalError> {
+        let mut log = self.log.lock().unwrap();
+        let mut payload = vec![INSERT];
+        encode_key(&key, &mut payload);
+        value.encode(&mut payload);
+        let len = log.append(&payload)?;
+        match self.tree.insert(key, value) {
+            Ok(old) => Ok(old),
+            Err(err) => {
+                log.truncate(len)?;
+                Err(err.into())
+            }
+        }
+    }
+
+    /// Logs and then deletes `key`, returning `true` if it had a live value.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `insert`.
+    pub fn delete(&self, key: K) -> Result<bool, WalError> {
+        let mut log = self.log.lock().unwrap();
+        let mut payload = vec![DELETE];
+        encode_key(&key, &mut payload);
+        let len = log.append(&payload)?;
+        match self.tree.delete(key) {
+            Ok(deleted) => Ok(deleted),
+            Err(err) => {
+                log.truncate(len)?;
+                Err(err.into())
+            }
+        }
+    }
+
+    /// Flushes the log to stable storage, so that every write that returned
+    /// so far survives a crash of the machine.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WalError::Io` if the flush fails.
+    pub fn sync(&self) -> Result<(), WalError> {
+        let log = self.log.lock().unwrap();
+        log.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Returns a reference to the value of `key`, like `BwTree::get`.
+    pub fn get(&self, key: K) -> Option<Guard<'_, V>> {
+        self.tree.get(key)
+    }
+
+    /// Returns an iterator over all entries in ascending key order.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        self.tree.iter()
+    }
+
+    /// Returns the number of live entries.
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    /// Returns `true` if the tree holds no live entries.
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+}
+
+impl Log {
+    /// Appends a record holding `payload`, and returns the length of the log
+    /// before it.
+    ///
+    /// If the write fails, the log is truncated back to that length, so that
+    /// a partial record doesn't stay in the way of the next one.
+    fn append(&mut self, payload: &[u8]) -> Result<u64, WalError> {
+        let len = u32::try_from(payload.len())
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidInput))?;
+        let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+        record.extend_from_slice(&len.to_le_bytes());
+        record.extend_from_slice(&crc32(payload).to_le_bytes());
+        record.extend_from_slice(payload);
+        let start = self.len;
+        if let Err(err) = self.file.write_all(&record) {
+            self.truncate(start)?;
+            return Err(err.into());
+        }
+        self.len += record.len() as u64;
+        Ok(start)
+    }
+
+    /// Drops the records at and after `len`.
+    fn truncate(&mut self, len: u64) -> std::io::Result<()> {
+        self.file.set_len(len)?;
+        self.len = len;
+        Ok(())
+    }
+}
+
+/// Appends the length of `key` and the key itself to `payload`.
+fn encode_key<K: WalCodec>(key: &K, payload: &mut Vec<u8>) {
+    let start = payload.len();
+    payload.extend_from_slice(&[0; 4]);
+    key.encode(payload);
+    let len = (payload.len() - start - 4) as u32;
+    payload[start..start + 4].copy_from_slice(&len.to_le_bytes());
+}
+
+/// Returns the payload of the record at the start of `bytes`, or `None` if
+/// the record is cut short or fails its checksum.
+fn next_record(bytes: &[u8]) -> Option<&[u8]> {
+    let (len, rest) = bytes.split_first_chunk::<4>()?;
+    let (checksum, rest) = rest.split_first_chunk::<4>()?;
+    let payload = rest.get(..u32::from_le_bytes(*len) as usize)?;
+    (crc32(payload) == u32::from_le_bytes(*checksum)).then_some(payload)
+}
+
+/// Returns the CRC-32 (IEEE) checksum of `bytes`.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
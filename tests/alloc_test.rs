@@ -0,0 +1,71 @@
+//! Counts the allocations made by writes, to compare the delta chains of
+//! small `Copy` values, whose records are packed into blocks, with those of
+//! values whose records are boxed one at a time.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use bwtree_rs::BwTree;
+
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // Threads that are being torn down no longer count.
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// Returns the number of allocations `f` makes on the current thread.
+fn allocations(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+/// A `u64` with drop glue, which keeps its delta records boxed.
+#[derive(Clone)]
+struct Boxed(#[allow(dead_code)] u64);
+
+impl Drop for Boxed {
+    fn drop(&mut self) {}
+}
+
+const KEYS: u64 = 1_000;
+const ROUNDS: u64 = 10;
+
+/// Inserts and then repeatedly overwrites `KEYS` keys.
+fn write_heavy<V: Clone>(value: impl Fn(u64) -> V) -> usize {
+    let tree = BwTree::new();
+    allocations(|| {
+        for round in 0..ROUNDS {
+            for key in 0..KEYS {
+                tree.insert(key, value(round)).unwrap();
+            }
+        }
+    })
+}
+
+#[test]
+fn test_packed_records_allocate_less() {
+    let packed = write_heavy(|round| round);
+    let boxed = write_heavy(Boxed);
+    // Boxing allocates a record for every write, while packing only allocates
+    // a block every few records, on top of what consolidation allocates alike.
+    assert!(
+        boxed - packed >= (KEYS * ROUNDS / 2) as usize,
+        "packed {packed}, boxed {boxed}"
+    );
+}
@@ -5,21 +5,20 @@ use shuttle::rand::{thread_rng, Rng};
 use shuttle::thread;
 
 #[test]
-#[ignore = "not yet"]
 fn test_disjoint_concurrent_inserts() {
     let tree = Arc::new(BwTree::new());
     shuttle::check_random(
         move || {
             let iterations = 10000;
-            let t1_start = thread_rng().gen::<u64>();
+            let t1_start = thread_rng().gen::<u64>() >> 1;
             {
                 let tree = tree.clone();
                 thread::spawn(move || {
                     for i in 0..iterations {
                         let key = t1_start + i;
                         let value = thread_rng().gen::<u64>();
-                        tree.insert(key, value);
-                        assert_eq!(tree.get(key), Some(&value));
+                        tree.insert(key, value).unwrap();
+                        assert_eq!(tree.get(key).as_deref(), Some(&value));
                     }
                 });
             }
@@ -30,8 +29,8 @@ fn test_disjoint_concurrent_inserts() {
                     for i in 0..iterations {
                         let key = t2_start + i;
                         let value = thread_rng().gen::<u64>();
-                        tree.insert(key, value);
-                        assert_eq!(tree.get(key), Some(&value));
+                        tree.insert(key, value).unwrap();
+                        assert_eq!(tree.get(key).as_deref(), Some(&value));
                     }
                 });
             }
@@ -39,3 +38,279 @@ fn test_disjoint_concurrent_inserts() {
         100,
     );
 }
+
+#[test]
+fn test_overlapping_concurrent_inserts() {
+    // Both threads insert the same keys, each with its own values, so every
+    // key ends up with the value of whichever insert came last.
+    shuttle::check_random(
+        || {
+            let tree = Arc::new(BwTree::with_consolidation_threshold(4));
+            let threads: Vec<_> = (0..2u64)
+                .map(|t| {
+                    let tree = tree.clone();
+                    thread::spawn(move || {
+                        for key in 0..100 {
+                            tree.insert(key, key * 2 + t).unwrap();
+                            let value = tree.get_owned(key).unwrap();
+                            assert_eq!(value / 2, key);
+                        }
+                    })
+                })
+                .collect();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            for key in 0..100 {
+                assert_eq!(tree.get_owned(key).map(|value| value / 2), Some(key));
+            }
+            assert_eq!(tree.len(), 100);
+        },
+        100,
+    );
+}
+
+#[test]
+fn test_concurrent_inserts_to_same_node() {
+    // All keys land in the same leaf, so every insert contends on the same
+    // mapping table entry.
+    shuttle::check_random(
+        || {
+            let tree = Arc::new(BwTree::with_consolidation_threshold(4));
+            let threads: Vec<_> = (0..3)
+                .map(|t| {
+                    let tree = tree.clone();
+                    thread::spawn(move || {
+                        for i in 0..20 {
+                            let key = i * 3 + t;
+                            tree.insert(key, key).unwrap();
+                        }
+                    })
+                })
+                .collect();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            for key in 0..60 {
+                assert_eq!(tree.get_owned(key), Some(key));
+            }
+            assert_eq!(tree.len(), 60);
+        },
+        100,
+    );
+}
+
+#[test]
+fn test_iteration_during_consolidation() {
+    // Every overwrite pushes a delta record, and every third one consolidates
+    // the node and retires the old delta chain while the reader walks it.
+    shuttle::check_random(
+        || {
+            let tree = Arc::new(BwTree::with_consolidation_threshold(2));
+            for key in 0..10u64 {
+                tree.insert(key, key).unwrap();
+            }
+            let writer = {
+                let tree = tree.clone();
+                thread::spawn(move || {
+                    for round in 1..20 {
+                        for key in 0..10 {
+                            tree.insert(key, key + round * 10).unwrap();
+                        }
+                        thread::yield_now();
+                    }
+                })
+            };
+            let reader = {
+                let tree = tree.clone();
+                thread::spawn(move || {
+                    for _ in 0..20 {
+                        let entries: Vec<_> = tree.iter().collect();
+                        assert!(entries.iter().map(|(key, _)| *key).eq(0..10));
+                        assert!(entries.iter().all(|(key, value)| value % 10 == *key));
+                        thread::yield_now();
+                    }
+                })
+            };
+            writer.join().unwrap();
+            reader.join().unwrap();
+            assert!(tree.iter().eq((0..10).map(|key| (key, key + 190))));
+        },
+        100,
+    );
+}
+
+#[test]
+fn test_concurrent_insert_delete_with_reader() {
+    // One writer inserts the even keys while the other deletes the odd ones
+    // it starts out with, which splits and merges leaves along the way. The
+    // reader must only ever observe entries that one of them wrote.
+    shuttle::check_random(
+        || {
+            let tree = Arc::new(BwTree::with_split_and_merge_thresholds(8, 2));
+            for key in (1..100u64).step_by(2) {
+                tree.insert(key, key * 10).unwrap();
+            }
+            let inserter = {
+                let tree = tree.clone();
+                thread::spawn(move || {
+                    for key in (0..100u64).step_by(2) {
+                        tree.insert(key, key * 10).unwrap();
+                    }
+                })
+            };
+            let deleter = {
+                let tree = tree.clone();
+                thread::spawn(move || {
+                    for key in (1..100u64).step_by(2) {
+                        assert!(tree.delete(key).unwrap());
+                    }
+                })
+            };
+            let reader = {
+                let tree = tree.clone();
+                thread::spawn(move || {
+                    for _ in 0..10 {
+                        let entries: Vec<_> = tree.iter().collect();
+                        assert!(entries.windows(2).all(|pair| pair[0].0 < pair[1].0));
+                        assert!(entries.iter().all(|(key, value)| *value == key * 10));
+                        for key in 0..100 {
+                            let value = tree.get_owned(key);
+                            assert!(value.is_none_or(|value| value == key * 10));
+                        }
+                        thread::yield_now();
+                    }
+                })
+            };
+            inserter.join().unwrap();
+            deleter.join().unwrap();
+            reader.join().unwrap();
+            assert!(tree
+                .iter()
+                .eq((0..100).step_by(2).map(|key| (key, key * 10))));
+            assert_eq!(tree.len(), 50);
+        },
+        100,
+    );
+}
+
+#[test]
+fn test_writes_during_consolidation() {
+    // The writers only append delta records, and the compactor consolidates
+    // the chain they append to. A record appended after the compactor read
+    // the chain must make it into the consolidated leaf, or be rejected and
+    // retried on top of it.
+    shuttle::check_random(
+        || {
+            let tree = Arc::new(BwTree::with_consolidation_threshold(1000));
+            let writers: Vec<_> = (0..2u64)
+                .map(|t| {
+                    let tree = tree.clone();
+                    thread::spawn(move || {
+                        for key in (t..40).step_by(2) {
+                            tree.insert(key, key * 10).unwrap();
+                            thread::yield_now();
+                        }
+                    })
+                })
+                .collect();
+            let compactor = {
+                let tree = tree.clone();
+                thread::spawn(move || {
+                    for _ in 0..20 {
+                        tree.compact().unwrap();
+                        thread::yield_now();
+                    }
+                })
+            };
+            for writer in writers {
+                writer.join().unwrap();
+            }
+            compactor.join().unwrap();
+            assert!(tree.iter().eq((0..40).map(|key| (key, key * 10))));
+            assert_eq!(tree.len(), 40);
+            tree.verify().unwrap();
+        },
+        100,
+    );
+}
+
+#[test]
+fn test_concurrent_try_inserts() {
+    // The threads write the same keys, so their records race for the same
+    // delta chain, which the consolidations along the way freeze. A write
+    // that is handed back is retried with `insert`, and must come back
+    // exactly as it went in.
+    shuttle::check_random(
+        || {
+            let tree = Arc::new(BwTree::with_consolidation_threshold(2));
+            let threads: Vec<_> = (0..3u64)
+                .map(|t| {
+                    let tree = tree.clone();
+                    thread::spawn(move || {
+                        for key in 0..20 {
+                            if let Err(pair) = tree.try_insert(key, key * 3 + t) {
+                                assert_eq!(pair, (key, key * 3 + t));
+                                tree.insert(pair.0, pair.1).unwrap();
+                            }
+                            thread::yield_now();
+                        }
+                    })
+                })
+                .collect();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            assert!(tree.iter().all(|(key, value)| value / 3 == key));
+            assert_eq!(tree.len(), 20);
+        },
+        100,
+    );
+}
+
+#[test]
+fn test_concurrent_drains() {
+    // Two drains race over the same entries while a writer overwrites them.
+    // Every key must be yielded by exactly one drain, with a value that was
+    // written to it.
+    shuttle::check_random(
+        || {
+            let tree = Arc::new(BwTree::with_split_threshold(8));
+            for key in 0..30u64 {
+                tree.insert(key, key * 2).unwrap();
+            }
+            let drains: Vec<_> = (0..2)
+                .map(|_| {
+                    let tree = tree.clone();
+                    thread::spawn(move || {
+                        let mut drained = Vec::new();
+                        for entry in tree.drain() {
+                            drained.push(entry.unwrap());
+                            thread::yield_now();
+                        }
+                        drained
+                    })
+                })
+                .collect();
+            let writer = {
+                let tree = tree.clone();
+                thread::spawn(move || {
+                    for key in 0..30u64 {
+                        tree.update(key, key * 2 + 1).unwrap();
+                        thread::yield_now();
+                    }
+                })
+            };
+            writer.join().unwrap();
+            let mut drained: Vec<_> = drains
+                .into_iter()
+                .flat_map(|drain| drain.join().unwrap())
+                .collect();
+            drained.sort_unstable();
+            assert!(drained.iter().all(|&(key, value)| value / 2 == key));
+            assert!(drained.iter().map(|&(key, _)| key).eq(0..30));
+            assert!(tree.is_empty());
+        },
+        100,
+    );
+}